@@ -15,7 +15,7 @@ mod packet;
 mod pixfmt;
 mod scale;
 
-pub use format::InputContainer;
+pub use format::{InputContainer, InputStream, StreamKind};
 pub use frame::{AvFrame, PictureSettings, PictureData, PictureDataMut};
 pub use ioctx::{AvIoError, IoReader, AvIoReader};
 pub use packet::{AvPacket, AvPacketRef, PacketInfo};
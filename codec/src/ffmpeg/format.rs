@@ -12,6 +12,11 @@ use mixlab_util::time::{MediaDuration, MediaTime, TimeBase};
 use crate::ffmpeg::{AvIoError, AvPacket, AvError, EOF};
 use crate::ffmpeg::codec::AvCodecParameters;
 use crate::ffmpeg::ioctx::{IoReader, AvIoReader};
+use crate::ffmpeg::media::MediaType;
+
+// AV_TIME_BASE and AV_NOPTS_VALUE are macros bindgen does not generate:
+const AV_TIME_BASE: i64 = 1_000_000;
+const AV_NOPTS_VALUE: i64 = i64::min_value();
 
 pub struct InputContainer<R: IoReader> {
     ctx: RawContext,
@@ -48,6 +53,52 @@ impl<R: IoReader> InputContainer<R> {
         unsafe { &*(self.ctx.ptr as *const _) }
     }
 
+    /// Reads packets as necessary to fill in stream parameters missing from
+    /// the container header. Fails if the input cannot be demuxed
+    pub fn find_stream_info(&mut self) -> Result<(), AvIoError<R>> {
+        let rc = unsafe {
+            ff::avformat_find_stream_info(self.ctx.ptr, ptr::null_mut())
+        };
+
+        // returns >= 0 on success
+        self.io.check_error(rc.min(0))
+    }
+
+    pub fn format_name(&self) -> Option<&'static str> {
+        let format = self.as_underlying().iformat;
+
+        if format == ptr::null_mut() {
+            return None;
+        }
+
+        let name = unsafe { CStr::from_ptr((*format).name) };
+        name.to_str().ok()
+    }
+
+    pub fn duration(&self) -> Option<MediaDuration> {
+        match self.as_underlying().duration {
+            AV_NOPTS_VALUE => None,
+            duration => Some(MediaDuration::new(duration, AV_TIME_BASE)),
+        }
+    }
+
+    /// Index of the stream of the given media type ffmpeg considers most
+    /// suitable for playback
+    pub fn best_stream<T: MediaType>(&self) -> Option<usize> {
+        let rc = unsafe {
+            ff::av_find_best_stream(
+                self.ctx.ptr,
+                T::FFMPEG_MEDIA_TYPE,
+                -1, // wanted_stream_nb
+                -1, // related_stream
+                ptr::null_mut(), // decoder_ret
+                0, // flags
+            )
+        };
+
+        rc.try_into().ok()
+    }
+
     pub fn streams(&self) -> &[InputStream] {
         let underlying = self.as_underlying();
 
@@ -138,11 +189,37 @@ impl InputStream {
         unsafe { AvCodecParameters::from_raw(&*self.as_underlying().codecpar) }
     }
 
+    pub fn kind(&self) -> StreamKind {
+        match self.codec_parameters().codec_type {
+            ff::AVMediaType_AVMEDIA_TYPE_VIDEO => StreamKind::Video,
+            ff::AVMediaType_AVMEDIA_TYPE_AUDIO => StreamKind::Audio,
+            _ => StreamKind::Other,
+        }
+    }
+
+    /// Average frame rate as frames per second, if known
+    pub fn frame_rate(&self) -> Option<f64> {
+        let rate = self.as_underlying().avg_frame_rate;
+
+        if rate.num == 0 || rate.den == 0 {
+            None
+        } else {
+            Some(rate.num as f64 / rate.den as f64)
+        }
+    }
+
     fn as_underlying(&self) -> &ff::AVStream {
         unsafe { &*(self.ptr as *const _) }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Other,
+}
+
 pub struct RawContext {
     ptr: *mut ff::AVFormatContext,
 }
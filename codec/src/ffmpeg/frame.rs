@@ -5,7 +5,7 @@ use std::ptr;
 
 use ffmpeg_dev::sys as ff;
 
-use crate::ffmpeg::media::{MediaType, Video};
+use crate::ffmpeg::media::{Audio, MediaType, Video};
 use crate::ffmpeg::{AvError, PixelFormat, ColorFormat};
 
 #[derive(Debug)]
@@ -303,6 +303,81 @@ impl AvFrame<Video> {
     }
 }
 
+impl AvFrame<Audio> {
+    pub fn sample_count(&self) -> usize {
+        self.as_underlying().nb_samples.try_into().expect("nb_samples >= 0")
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.as_underlying().channels.try_into().expect("channels >= 0")
+    }
+
+    /// Copies samples out of the frame as interleaved f32, or returns None if
+    /// the frame is in a sample format we do not know how to convert
+    pub fn to_f32_interleaved(&self) -> Option<Vec<f32>> {
+        fn u8(data: *const u8, idx: usize) -> f32 {
+            let sample = unsafe { *data.add(idx) };
+            (sample as f32 - 128.0) / 128.0
+        }
+
+        fn s16(data: *const u8, idx: usize) -> f32 {
+            let sample = unsafe { ptr::read_unaligned((data as *const i16).add(idx)) };
+            sample as f32 / 32768.0
+        }
+
+        fn s32(data: *const u8, idx: usize) -> f32 {
+            let sample = unsafe { ptr::read_unaligned((data as *const i32).add(idx)) };
+            sample as f32 / 2147483648.0
+        }
+
+        fn flt(data: *const u8, idx: usize) -> f32 {
+            unsafe { ptr::read_unaligned((data as *const f32).add(idx)) }
+        }
+
+        fn dbl(data: *const u8, idx: usize) -> f32 {
+            unsafe { ptr::read_unaligned((data as *const f64).add(idx)) as f32 }
+        }
+
+        let underlying = self.as_underlying();
+
+        let (planar, read): (bool, fn(*const u8, usize) -> f32) = match underlying.format {
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_U8 => (false, u8),
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_U8P => (true, u8),
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_S16 => (false, s16),
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_S16P => (true, s16),
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_S32 => (false, s32),
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_S32P => (true, s32),
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_FLT => (false, flt),
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_FLTP => (true, flt),
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_DBL => (false, dbl),
+            fmt if fmt == ff::AVSampleFormat_AV_SAMPLE_FMT_DBLP => (true, dbl),
+            _ => return None,
+        };
+
+        let samples = self.sample_count();
+        let channels = self.channel_count();
+        let mut out = Vec::with_capacity(samples * channels);
+
+        // planar formats have a plane per channel in extended_data, packed
+        // formats interleave all channels in the first plane:
+        for idx in 0..samples {
+            for chan in 0..channels {
+                let sample = unsafe {
+                    if planar {
+                        read(*underlying.extended_data.add(chan), idx)
+                    } else {
+                        read(*underlying.extended_data, idx * channels + chan)
+                    }
+                };
+
+                out.push(sample);
+            }
+        }
+
+        Some(out)
+    }
+}
+
 type PlanarData = [*mut u8; ff::AV_NUM_DATA_POINTERS as usize];
 type PlanarStride = [c_int; ff::AV_NUM_DATA_POINTERS as usize];
 
@@ -12,3 +12,10 @@ pub struct Video;
 impl MediaType for Video {
     const FFMPEG_MEDIA_TYPE: ff::AVMediaType = ff::AVMediaType_AVMEDIA_TYPE_VIDEO;
 }
+
+#[derive(Debug)]
+pub struct Audio;
+
+impl MediaType for Audio {
+    const FFMPEG_MEDIA_TYPE: ff::AVMediaType = ff::AVMediaType_AVMEDIA_TYPE_AUDIO;
+}
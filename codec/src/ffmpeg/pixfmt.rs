@@ -13,6 +13,10 @@ impl PixelFormat {
         PixelFormat(ff::AVPixelFormat_AV_PIX_FMT_YUV420P)
     }
 
+    pub const fn rgb24() -> Self {
+        PixelFormat(ff::AVPixelFormat_AV_PIX_FMT_RGB24)
+    }
+
     pub unsafe fn from_raw(pixfmt: ff::AVPixelFormat) -> Self {
         PixelFormat(pixfmt)
     }
@@ -3,7 +3,10 @@ use std::mem;
 use web_sys::MouseEvent;
 use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties, Callback, Children};
 
+use mixlab_protocol::MidiTarget;
+
 use crate::service::midi::{self, RangeSubscription, MidiRangeId, ConfigureTask};
+use crate::util::notify;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum MidiUiMode {
@@ -15,6 +18,7 @@ pub struct MidiRangeTarget {
     link: ComponentLink<Self>,
     props: MidiTargetProps,
     state: MidiState,
+    _mappings_notify: notify::Handle,
 }
 
 #[derive(Debug)]
@@ -27,6 +31,8 @@ pub enum MidiState {
 #[derive(Properties, Clone)]
 pub struct MidiTargetProps {
     pub ui_mode: MidiUiMode,
+    // what the control acts on, for finding its mapping in the workspace:
+    pub target: MidiTarget,
     pub onchange: Callback<f64>,
     #[prop_or_default]
     pub children: Children,
@@ -35,9 +41,11 @@ pub struct MidiTargetProps {
 #[derive(Debug)]
 pub enum MidiTargetMsg {
     Configure,
+    Cancel,
     Unbind,
     RangeConfigured(MidiRangeId, u8),
     RangeChanged(u8),
+    MappingsChanged,
 }
 
 impl Component for MidiRangeTarget {
@@ -45,22 +53,34 @@ impl Component for MidiRangeTarget {
     type Message = MidiTargetMsg;
 
     fn create(props: MidiTargetProps, link: ComponentLink<Self>) -> Self {
-        MidiRangeTarget {
+        let mappings_notify = midi::broker().listen_mappings(
+            link.callback(|()| MidiTargetMsg::MappingsChanged));
+
+        let mut target = MidiRangeTarget {
             props,
             link,
             state: MidiState::Unbound,
-        }
+            _mappings_notify: mappings_notify,
+        };
+
+        target.rebind();
+        target
     }
 
     fn change(&mut self, mut props: MidiTargetProps) -> ShouldRender {
         mem::swap(&mut self.props, &mut props);
 
+        if props.target != self.props.target {
+            self.rebind();
+        }
+
         if props.ui_mode != self.props.ui_mode {
             match (&self.state, self.props.ui_mode) {
                 (MidiState::Configure(_), MidiUiMode::Normal) => {
                     // if we're still in configure state when the UI changes
-                    // back to normal mode, return to unbound mode:
+                    // back to normal mode, return to whatever is mapped:
                     self.state = MidiState::Unbound;
+                    self.rebind();
                 }
                 _ => { /* otherwise do nothing */ }
             }
@@ -74,7 +94,7 @@ impl Component for MidiRangeTarget {
             MidiTargetMsg::Configure => {
                 let configure = midi::broker().configure_range(self.link.callback(|result| {
                     match result {
-                        None => MidiTargetMsg::Unbind,
+                        None => MidiTargetMsg::Cancel,
                         Some((range_id, range_value)) =>
                             MidiTargetMsg::RangeConfigured(range_id, range_value),
                     }
@@ -82,16 +102,25 @@ impl Component for MidiRangeTarget {
                 self.state = MidiState::Configure(configure);
                 true
             }
+            MidiTargetMsg::Cancel => {
+                // another target started configuring, keep what is mapped:
+                self.state = MidiState::Unbound;
+                self.rebind();
+                true
+            }
             MidiTargetMsg::Unbind => {
+                midi::broker().bind(self.props.target.clone(), None);
                 self.state = MidiState::Unbound;
                 true
             }
             MidiTargetMsg::RangeConfigured(range_id, range_value) => {
                 // only handle this message if we're still in configure state:
                 if let MidiState::Configure(_) = self.state {
-                    let subscription = midi::broker().subscribe_range(range_id,
+                    let subscription = midi::broker().subscribe_range(range_id.clone(),
                         self.link.callback(MidiTargetMsg::RangeChanged));
 
+                    midi::broker().bind(self.props.target.clone(), Some(range_id));
+
                     self.props.onchange.emit(range_value as f64 / 127.0);
                     self.state = MidiState::Bound(subscription);
                     true
@@ -99,6 +128,10 @@ impl Component for MidiRangeTarget {
                     false
                 }
             }
+            MidiTargetMsg::MappingsChanged => {
+                self.rebind();
+                true
+            }
             MidiTargetMsg::RangeChanged(range_value) => {
                 self.props.onchange.emit(range_value as f64 / 127.0);
                 false
@@ -152,3 +185,20 @@ impl Component for MidiRangeTarget {
         }
     }
 }
+
+impl MidiRangeTarget {
+    /// Subscribes to whatever is mapped to the target, unless configuring
+    fn rebind(&mut self) {
+        if let MidiState::Configure(_) = self.state {
+            return;
+        }
+
+        self.state = match midi::broker().mapping(&self.props.target) {
+            Some(range_id) => {
+                MidiState::Bound(midi::broker().subscribe_range(range_id,
+                    self.link.callback(MidiTargetMsg::RangeChanged)))
+            }
+            None => MidiState::Unbound,
+        };
+    }
+}
@@ -3,7 +3,12 @@
 mod component;
 mod control;
 mod library;
+mod macros;
 mod module;
+mod presence;
+mod presets;
+mod projects;
+mod scenes;
 mod service;
 mod session;
 mod sidebar;
@@ -16,9 +21,12 @@ use derive_more::Display;
 use wasm_bindgen::prelude::*;
 use yew::{html, Component, ComponentLink, Html, ShouldRender, Callback, Properties};
 
-use mixlab_protocol::WorkspaceOp;
+use mixlab_protocol::{WorkspaceOp, Role};
 
 use library::MediaLibrary;
+use macros::Macros;
+use projects::Projects;
+use scenes::Scenes;
 use session::{Session, SessionRef};
 use sidebar::Sidebar;
 use util::{notify, Sequence};
@@ -36,6 +44,12 @@ pub enum Tab {
     Workspace,
     #[display(fmt = "Media Library")]
     MediaLibrary,
+    #[display(fmt = "Scenes")]
+    Scenes,
+    #[display(fmt = "Macros")]
+    Macros,
+    #[display(fmt = "Projects")]
+    Projects,
 }
 
 #[derive(Debug)]
@@ -84,6 +98,9 @@ impl Component for App {
                         tabs={vec![
                             Tab::Workspace,
                             Tab::MediaLibrary,
+                            Tab::Scenes,
+                            Tab::Macros,
+                            Tab::Projects,
                         ]}
                         onchange={self.link.callback(AppMsg::ChangeTab)}
                     />
@@ -98,6 +115,15 @@ impl Component for App {
                         Tab::MediaLibrary => html! {
                             <MediaLibrary session={self.session.clone()} />
                         },
+                        Tab::Scenes => html! {
+                            <Scenes session={self.session.clone()} />
+                        },
+                        Tab::Macros => html! {
+                            <Macros session={self.session.clone()} />
+                        },
+                        Tab::Projects => html! {
+                            <Projects session={self.session.clone()} />
+                        },
                     } }
                 </div>
             </div>
@@ -141,12 +167,22 @@ impl Component for WorkspaceContainer {
 
     fn view(&self) -> Html {
         if let Some(state) = self.props.session.workspace() {
-            html! {
+            let workspace = html! {
                 <Workspace
                     app={self.props.app.clone()}
                     state={state.clone()}
                     session={self.props.session.clone()}
                 />
+            };
+
+            match self.props.session.role() {
+                Role::Operator => workspace,
+                Role::Viewer => html! {
+                    <div class="workspace-read-only">
+                        <div class="workspace-read-only-banner">{"View only"}</div>
+                        {workspace}
+                    </div>
+                },
             }
         } else {
             html! {}
@@ -2,23 +2,28 @@ use std::collections::BTreeMap;
 use std::num::NonZeroUsize;
 use std::rc::Rc;
 
-use gloo_events::EventListener;
-use wasm_bindgen::{JsCast, JsValue};
-use web_sys::{File, XmlHttpRequest, ProgressEvent};
+use web_sys::File;
 use yew::events::ChangeData;
 use yew::{html, Callback, Component, ComponentLink, Html, ShouldRender, Properties, NodeRef};
 
 use mixlab_protocol as protocol;
+use mixlab_protocol::{MediaId, MediaMetadata, MediaOp, MediaStreamKind, ModuleParams};
 
 use crate::session::SessionRef;
-use crate::util::{self, notify, Sequence};
+use crate::util::{notify, Sequence};
+
+mod upload;
+
+use upload::{UploadEvent, UploadProgress, UploadTask};
 
 pub struct MediaLibrary {
     link: ComponentLink<Self>,
     upload_seq: Sequence,
     uploads: BTreeMap<NonZeroUsize, InProgressUpload>,
     library: Option<Rc<protocol::MediaLibrary>>,
+    props: MediaLibraryProps,
     _notify: notify::Handle,
+    _workspace_notify: notify::Handle,
 }
 
 #[derive(Properties, Clone)]
@@ -28,8 +33,12 @@ pub struct MediaLibraryProps {
 
 pub enum LibraryMsg {
     Update(Rc<protocol::MediaLibrary>),
+    WorkspaceChanged,
     SelectFiles(Vec<File>),
     Upload(NonZeroUsize, UploadEvent),
+    Rename(MediaId, String),
+    Delete(MediaId),
+    CollectGarbage,
 }
 
 impl Component for MediaLibrary {
@@ -38,13 +47,16 @@ impl Component for MediaLibrary {
 
     fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
         let notify = props.session.listen_media(link.callback(LibraryMsg::Update));
+        let workspace_notify = props.session.listen_workspace(link.callback(|()| LibraryMsg::WorkspaceChanged));
 
         MediaLibrary {
             link,
             upload_seq: Sequence::new(),
             uploads: BTreeMap::new(),
             library: None,
+            props,
             _notify: notify,
+            _workspace_notify: workspace_notify,
         }
     }
 
@@ -58,6 +70,9 @@ impl Component for MediaLibrary {
                 self.library = Some(library);
                 true
             }
+            LibraryMsg::WorkspaceChanged => {
+                true
+            }
             LibraryMsg::SelectFiles(files) => {
                 for file in files {
                     let id = self.upload_seq.next();
@@ -67,11 +82,12 @@ impl Component for MediaLibrary {
                     let task = UploadTask::start(file,
                         self.link.callback(move |ev|
                             LibraryMsg::Upload(id, ev))
-                    ).expect("UploadTask::start");
+                    );
 
                     self.uploads.insert(id, InProgressUpload {
                         filename,
                         progress: None,
+                        failed: false,
                         _task: task,
                     });
                 }
@@ -92,7 +108,29 @@ impl Component for MediaLibrary {
                         self.uploads.remove(&id);
                         true
                     }
+                    UploadEvent::Failed => {
+                        if let Some(upload) = self.uploads.get_mut(&id) {
+                            upload.failed = true;
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                }
+            }
+            LibraryMsg::Rename(media_id, name) => {
+                if !name.trim().is_empty() {
+                    self.props.session.update_media(MediaOp::Rename(media_id, name));
                 }
+                false
+            }
+            LibraryMsg::Delete(media_id) => {
+                self.props.session.update_media(MediaOp::Delete(media_id));
+                false
+            }
+            LibraryMsg::CollectGarbage => {
+                self.props.session.update_media(MediaOp::CollectGarbage);
+                false
             }
         }
     }
@@ -102,6 +140,12 @@ impl Component for MediaLibrary {
             <div class="media-library">
                 <div class="media-library-main-button-row">
                     <UploadButton on_file_upload={self.link.callback(LibraryMsg::SelectFiles)} />
+                    <div class="media-library-main-button"
+                        title="Reclaim space left behind by deleted media and interrupted uploads"
+                        onclick={self.link.callback(|_| LibraryMsg::CollectGarbage)}
+                    >
+                        {"Clean Up"}
+                    </div>
                 </div>
                 { if self.uploads.is_empty() {
                     html! {}
@@ -118,6 +162,7 @@ impl Component for MediaLibrary {
                                             <td>{&item.filename}</td>
                                             <td class="media-library-upload-progress-percent">
                                                 { match &item.progress {
+                                                    _ if item.failed => "Failed".to_string(),
                                                     Some(progress) => format!("{:.1}%", progress.as_percent()),
                                                     None => "".to_string(),
                                                 } }
@@ -142,19 +187,59 @@ impl Component for MediaLibrary {
                     }
                 } }
                 { if let Some(library) = &self.library {
+                    let in_use = self.media_in_use();
+
                     html! {
                         <table class="media-library-table">
                             <tr class="table-heading">
+                                <th></th>
                                 <th>{"Name"}</th>
                                 <th>{"Kind"}</th>
+                                <th>{"Details"}</th>
                                 <th>{"Size"}</th>
+                                <th></th>
                             </tr>
                             { for library.items.iter().map(|item| {
+                                let media_id = item.id;
+                                let in_use = in_use.contains(&media_id);
+                                let url = format!("/_media/{}", media_id.0);
+
                                 html! {
                                     <tr>
-                                        <td>{&item.name}</td>
+                                        <td>
+                                            <a href={url.clone()} target="_blank" title="Open in a new tab">
+                                                {view_preview(item)}
+                                            </a>
+                                        </td>
+                                        <td>
+                                            <input type="text"
+                                                class="media-library-name"
+                                                value={item.name.clone()}
+                                                onchange={self.link.batch_callback(move |change| match change {
+                                                    ChangeData::Value(name) => vec![LibraryMsg::Rename(media_id, name)],
+                                                    _ => vec![],
+                                                })}
+                                            />
+                                        </td>
                                         <td>{&item.kind}</td>
+                                        <td>
+                                            { for item.metadata.iter().flat_map(format_metadata).map(|detail| html! {
+                                                <div>{detail}</div>
+                                            }) }
+                                        </td>
                                         <td>{format_size(item.size)}</td>
+                                        <td>
+                                            <button
+                                                disabled={in_use}
+                                                title={if in_use { "In use by a media source" } else { "" }}
+                                                onclick={self.link.callback(move |_| LibraryMsg::Delete(media_id))}
+                                            >
+                                                {"Delete"}
+                                            </button>
+                                            <a class="media-library-download" href={url} download={item.name.clone()}>
+                                                {"Download"}
+                                            </a>
+                                        </td>
                                     </tr>
                                 }
                             }) }
@@ -168,6 +253,79 @@ impl Component for MediaLibrary {
     }
 }
 
+impl MediaLibrary {
+    fn media_in_use(&self) -> Vec<MediaId> {
+        let state = match self.props.session.workspace() {
+            Some(state) => state,
+            None => return Vec::new(),
+        };
+
+        let state = state.borrow();
+
+        state.modules.values()
+            .filter_map(|params| match params {
+                ModuleParams::MediaSource(params) => params.media_id,
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Thumbnail for video, waveform for audio-only media, or nothing if
+/// previews are not available yet
+pub fn view_preview(item: &protocol::MediaItem) -> Html {
+    if item.thumbnail {
+        html! {
+            <img class="media-preview" src={format!("/_media/{}/thumbnail", item.id.0)} />
+        }
+    } else if item.waveform {
+        html! {
+            <img class="media-preview media-preview-waveform" src={format!("/_media/{}/waveform.svg", item.id.0)} />
+        }
+    } else {
+        html! {}
+    }
+}
+
+// one line for the container and one for each stream, eg:
+//   mp4, 3:25
+//   H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10, 1920x1080, 30 fps
+fn format_metadata(metadata: &MediaMetadata) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    lines.push(match metadata.duration {
+        Some(duration) => {
+            let secs = duration.0 / 1_000_000;
+            format!("{}, {}:{:02}", metadata.format, secs / 60, secs % 60)
+        }
+        None => metadata.format.clone(),
+    });
+
+    for stream in &metadata.streams {
+        let codec = stream.codec.as_ref().map(String::as_str).unwrap_or("unknown codec");
+
+        match &stream.kind {
+            MediaStreamKind::Video { width, height, frame_rate } => {
+                let mut line = format!("{}, {}x{}", codec, width, height);
+
+                if let Some(frame_rate) = frame_rate {
+                    let fps = format!("{:.3}", frame_rate);
+                    let fps = fps.trim_end_matches('0').trim_end_matches('.');
+                    line += &format!(", {} fps", fps);
+                }
+
+                lines.push(line);
+            }
+            MediaStreamKind::Audio { sample_rate, channels } => {
+                lines.push(format!("{}, {} Hz, {} ch", codec, sample_rate, channels));
+            }
+            MediaStreamKind::Other => {}
+        }
+    }
+
+    lines
+}
+
 fn format_size(bytes: usize) -> String {
     const KIB: usize = 1024;
     const MIB: usize = 1024 * 1024;
@@ -263,83 +421,6 @@ impl Component for UploadButton {
 struct InProgressUpload {
     filename: String,
     progress: Option<UploadProgress>,
+    failed: bool,
     _task: UploadTask,
 }
-
-pub struct UploadProgress {
-    uploaded: u64,
-    total: u64,
-}
-
-impl UploadProgress {
-    fn as_percent(&self) -> f64 {
-        (self.uploaded as f64 / self.total as f64) * 100.0
-    }
-}
-
-struct UploadTask {
-    xhr: XmlHttpRequest,
-    _progress_event: EventListener,
-    _load_event: EventListener,
-}
-
-pub enum UploadEvent {
-    Progress(UploadProgress),
-    Complete,
-}
-
-impl UploadTask {
-    fn start(file: File, callback: Callback<UploadEvent>) -> Result<UploadTask, JsValue> {
-        crate::log!("origin: {:?}", util::origin());
-        let url = util::origin() + "/_upload/" + &file.name();
-
-        let mut kind = file.type_();
-        if kind == "" {
-            kind = "application/octet-stream".to_string();
-        }
-
-        let xhr = XmlHttpRequest::new()?;
-        let upload = xhr.upload()?;
-
-        let progress_event = EventListener::new(&upload, "progress", {
-            let callback = callback.clone();
-            move |ev| {
-                if let Some(ev) = ev.dyn_ref::<ProgressEvent>() {
-                    let uploaded = ev.loaded() as u64;
-                    let total = ev.total() as u64;
-                    callback.emit(UploadEvent::Progress(UploadProgress {
-                        uploaded,
-                        total,
-                    }));
-                }
-            }
-        });
-
-        let load_event = EventListener::new(&upload, "load", {
-            let callback = callback.clone();
-            move |_| { callback.emit(UploadEvent::Complete); }
-        });
-
-        xhr.open("POST", &url)?;
-        xhr.override_mime_type(&kind)?;
-        xhr.send_with_opt_blob(Some(&file))?;
-
-        Ok(UploadTask {
-            xhr,
-            _progress_event: progress_event,
-            _load_event: load_event,
-        })
-    }
-}
-
-impl Drop for UploadTask {
-    fn drop(&mut self) {
-        /// https://developer.mozilla.org/en-US/docs/Web/API/XMLHttpRequest/readyState
-        const DONE: u16 = 4;
-
-        if self.xhr.ready_state() != DONE {
-            // nothing we can do in drop if abort fails
-            let _ = self.xhr.abort();
-        }
-    }
-}
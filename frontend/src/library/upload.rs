@@ -0,0 +1,273 @@
+// Uploads files in chunks over the resumable upload protocol served at
+// /_uploads. When a chunk fails we ask the server how much it committed and
+// carry on from there, so a dropped connection costs at most one chunk.
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::rc::Rc;
+
+use gloo_events::EventListener;
+use js_sys::{Object, Promise, Reflect, JSON};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, File, ProgressEvent, XmlHttpRequest};
+use yew::Callback;
+
+use crate::util;
+
+const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+const MAX_RETRIES: u32 = 6;
+const RETRY_DELAY_MS: i32 = 500;
+
+pub struct UploadProgress {
+    pub uploaded: u64,
+    pub total: u64,
+}
+
+impl UploadProgress {
+    pub fn as_percent(&self) -> f64 {
+        (self.uploaded as f64 / self.total as f64) * 100.0
+    }
+}
+
+pub enum UploadEvent {
+    Progress(UploadProgress),
+    Complete,
+    Failed,
+}
+
+pub struct UploadTask {
+    state: Rc<TaskState>,
+}
+
+struct TaskState {
+    cancelled: Cell<bool>,
+    xhr: RefCell<Option<XmlHttpRequest>>,
+}
+
+impl UploadTask {
+    pub fn start(file: File, callback: Callback<UploadEvent>) -> UploadTask {
+        let state = Rc::new(TaskState {
+            cancelled: Cell::new(false),
+            xhr: RefCell::new(None),
+        });
+
+        wasm_bindgen_futures::spawn_local({
+            let state = state.clone();
+            async move {
+                let event = match upload(&state, &file, &callback).await {
+                    Ok(()) => UploadEvent::Complete,
+                    Err(e) => {
+                        if state.cancelled.get() {
+                            return;
+                        }
+
+                        crate::warn!("upload of {:?} failed: {:?}", file.name(), e);
+                        UploadEvent::Failed
+                    }
+                };
+
+                callback.emit(event);
+            }
+        });
+
+        UploadTask { state }
+    }
+}
+
+impl Drop for UploadTask {
+    fn drop(&mut self) {
+        self.state.cancelled.set(true);
+
+        if let Some(xhr) = self.state.xhr.borrow_mut().take() {
+            // nothing we can do in drop if abort fails
+            let _ = xhr.abort();
+        }
+    }
+}
+
+async fn upload(state: &TaskState, file: &File, callback: &Callback<UploadEvent>) -> Result<(), JsValue> {
+    let mut kind = file.type_();
+    if kind == "" {
+        kind = "application/octet-stream".to_string();
+    }
+
+    let params = Object::new();
+    Reflect::set(&params, &"name".into(), &file.name().into())?;
+    Reflect::set(&params, &"kind".into(), &kind.into())?;
+    let params = String::from(JSON::stringify(&params)?);
+
+    let response = request(state, "POST", &(util::origin() + "/_uploads"), Body::Json(&params), None).await?;
+    let created = response.json(200)?;
+
+    let id = Reflect::get(&created, &"id".into())?.as_string()
+        .ok_or_else(|| JsValue::from_str("missing upload id"))?;
+
+    let url = format!("{}/_uploads/{}", util::origin(), id);
+    let total = file.size() as u64;
+    let mut offset = read_offset(&created)?;
+    let mut retries = 0;
+
+    while offset < total {
+        let end = cmp::min(offset + CHUNK_SIZE, total);
+        let chunk = file.slice_with_f64_and_f64(offset as f64, end as f64)?;
+
+        let on_progress: Box<dyn Fn(u64)> = Box::new({
+            let callback = callback.clone();
+            let start = offset;
+            move |loaded| callback.emit(UploadEvent::Progress(UploadProgress {
+                uploaded: start + loaded,
+                total,
+            }))
+        });
+
+        let chunk_url = format!("{}?offset={}", url, offset);
+        let result = request(state, "PUT", &chunk_url, Body::Blob(&chunk), Some(on_progress)).await;
+
+        match result {
+            Ok(response) if response.status == 200 => {
+                offset = read_offset(&response.json(200)?)?;
+                retries = 0;
+                continue;
+            }
+            Ok(response) if response.status == 409 && response.body != "" => {
+                // we were out of step with the server, it tells us where to
+                // continue from:
+                offset = read_offset(&response.json(409)?)?;
+                continue;
+            }
+            Ok(response) if response.status == 409 || response.status >= 500 => {
+                // server busy committing a previous attempt, or trouble on
+                // its end. worth retrying
+            }
+            Ok(response) => {
+                return Err(JsValue::from_str(&format!("upload chunk failed with status {}", response.status)));
+            }
+            Err(e) => {
+                if state.cancelled.get() {
+                    return Err(e);
+                }
+            }
+        }
+
+        retries += 1;
+        if retries > MAX_RETRIES {
+            return Err(JsValue::from_str("upload failed, too many retries"));
+        }
+
+        sleep(RETRY_DELAY_MS << (retries - 1)).await?;
+
+        // if this fails too we go around again and retry the chunk at the
+        // offset we last knew of:
+        if let Ok(response) = request(state, "GET", &url, Body::None, None).await {
+            if response.status == 200 {
+                offset = read_offset(&response.json(200)?)?;
+            }
+        }
+    }
+
+    let response = request(state, "POST", &(url + "/finalize"), Body::None, None).await?;
+    response.json(200)?;
+
+    Ok(())
+}
+
+fn read_offset(status: &JsValue) -> Result<u64, JsValue> {
+    Reflect::get(status, &"offset".into())?.as_f64()
+        .map(|offset| offset as u64)
+        .ok_or_else(|| JsValue::from_str("missing upload offset"))
+}
+
+enum Body<'a> {
+    None,
+    Json(&'a str),
+    Blob(&'a Blob),
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn json(&self, expect_status: u16) -> Result<JsValue, JsValue> {
+        if self.status != expect_status {
+            return Err(JsValue::from_str(&format!("unexpected status {}", self.status)));
+        }
+
+        JSON::parse(&self.body)
+    }
+}
+
+/// Performs a request, resolving once the response is loaded. Network
+/// errors and aborts, including cancellation of the task, are errors
+async fn request(
+    state: &TaskState,
+    method: &str,
+    url: &str,
+    body: Body<'_>,
+    on_progress: Option<Box<dyn Fn(u64)>>,
+) -> Result<Response, JsValue> {
+    if state.cancelled.get() {
+        return Err(JsValue::from_str("upload cancelled"));
+    }
+
+    let xhr = XmlHttpRequest::new()?;
+    xhr.open(method, url)?;
+
+    let _progress_event = match on_progress {
+        Some(on_progress) => Some(EventListener::new(&xhr.upload()?, "progress", move |ev| {
+            if let Some(ev) = ev.dyn_ref::<ProgressEvent>() {
+                on_progress(ev.loaded() as u64);
+            }
+        })),
+        None => None,
+    };
+
+    let mut listeners = Vec::new();
+
+    let done = Promise::new(&mut |resolve, reject| {
+        listeners.push(EventListener::new(&xhr, "load", move |_| {
+            let _ = resolve.call0(&JsValue::NULL);
+        }));
+
+        for event in &["error", "abort", "timeout"] {
+            let event: &'static str = *event;
+            let reject = reject.clone();
+            listeners.push(EventListener::new(&xhr, event, move |_| {
+                let _ = reject.call1(&JsValue::NULL, &JsValue::from_str(event));
+            }));
+        }
+    });
+
+    match body {
+        Body::None => xhr.send()?,
+        Body::Json(json) => {
+            xhr.set_request_header("content-type", "application/json")?;
+            xhr.send_with_opt_str(Some(json))?;
+        }
+        Body::Blob(blob) => xhr.send_with_opt_blob(Some(blob))?,
+    }
+
+    *state.xhr.borrow_mut() = Some(xhr.clone());
+    let result = JsFuture::from(done).await;
+    state.xhr.borrow_mut().take();
+    result?;
+
+    Ok(Response {
+        status: xhr.status()?,
+        body: xhr.response_text()?.unwrap_or_default(),
+    })
+}
+
+async fn sleep(millis: i32) -> Result<(), JsValue> {
+    let window = web_sys::window()
+        .ok_or_else(|| JsValue::from_str("no window"))?;
+
+    let timeout = Promise::new(&mut |resolve, _| {
+        let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, millis);
+    });
+
+    JsFuture::from(timeout).await?;
+    Ok(())
+}
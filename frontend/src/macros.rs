@@ -0,0 +1,389 @@
+use std::num::NonZeroUsize;
+
+use yew::events::ChangeData;
+use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties};
+
+use mixlab_protocol::{ModuleId, MacroId, MacroParams, MacroTarget, MacroCurve, MidiTarget, WorkspaceOp};
+
+use crate::component::midi_target::{MidiRangeTarget, MidiUiMode};
+use crate::control::Fader;
+use crate::session::SessionRef;
+use crate::util::notify;
+
+pub struct Macros {
+    link: ComponentLink<Self>,
+    props: MacrosProps,
+    name: String,
+    target: TargetForm,
+    midi_mode: MidiUiMode,
+    _workspace_notify: notify::Handle,
+}
+
+// target being built in the editor before it is added to a macro:
+struct TargetForm {
+    macro_id: Option<MacroId>,
+    module: Option<ModuleId>,
+    field: String,
+    min: f64,
+    max: f64,
+    invert: bool,
+    curve: MacroCurve,
+}
+
+#[derive(Properties, Clone)]
+pub struct MacrosProps {
+    pub session: SessionRef,
+}
+
+pub enum MacrosMsg {
+    WorkspaceChanged,
+    SetName(String),
+    Create,
+    Delete(MacroId),
+    SetValue(MacroId, f64),
+    ToggleMidiMode,
+    SetTargetMacro(Option<MacroId>),
+    SetTargetModule(Option<ModuleId>),
+    SetTargetField(String),
+    SetTargetMin(f64),
+    SetTargetMax(f64),
+    ToggleTargetInvert,
+    SetTargetCurve(MacroCurve),
+    AddTarget,
+    RemoveTarget(MacroId, usize),
+}
+
+impl Component for Macros {
+    type Message = MacrosMsg;
+    type Properties = MacrosProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let workspace_notify = props.session.listen_workspace(link.callback(|()| MacrosMsg::WorkspaceChanged));
+
+        Macros {
+            link,
+            props,
+            name: String::new(),
+            target: TargetForm {
+                macro_id: None,
+                module: None,
+                field: String::new(),
+                min: 0.0,
+                max: 1.0,
+                invert: false,
+                curve: MacroCurve::Linear,
+            },
+            midi_mode: MidiUiMode::Normal,
+            _workspace_notify: workspace_notify,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            MacrosMsg::WorkspaceChanged => {
+                true
+            }
+            MacrosMsg::SetName(name) => {
+                self.name = name;
+                false
+            }
+            MacrosMsg::Create => {
+                if self.name.is_empty() {
+                    return false;
+                }
+
+                self.props.session.update_workspace(WorkspaceOp::CreateMacro(MacroParams {
+                    name: self.name.clone(),
+                    value: 0.0,
+                    targets: Vec::new(),
+                }));
+
+                false
+            }
+            MacrosMsg::Delete(macro_id) => {
+                self.props.session.update_workspace(WorkspaceOp::DeleteMacro(macro_id));
+                false
+            }
+            MacrosMsg::SetValue(macro_id, value) => {
+                // update local state immediately so the fader tracks input
+                // while we wait for the server to round-trip:
+                if let Some(state) = self.props.session.workspace() {
+                    if let Some(params) = state.borrow_mut().macros.get_mut(&macro_id) {
+                        params.value = value;
+                    }
+                }
+
+                self.props.session.update_workspace(WorkspaceOp::SetMacroValue(macro_id, value));
+                true
+            }
+            MacrosMsg::ToggleMidiMode => {
+                self.midi_mode = match self.midi_mode {
+                    MidiUiMode::Normal => MidiUiMode::Configure,
+                    MidiUiMode::Configure => MidiUiMode::Normal,
+                };
+                true
+            }
+            MacrosMsg::SetTargetMacro(macro_id) => {
+                self.target.macro_id = macro_id;
+                false
+            }
+            MacrosMsg::SetTargetModule(module) => {
+                self.target.module = module;
+                false
+            }
+            MacrosMsg::SetTargetField(field) => {
+                self.target.field = field;
+                false
+            }
+            MacrosMsg::SetTargetMin(min) => {
+                self.target.min = min;
+                false
+            }
+            MacrosMsg::SetTargetMax(max) => {
+                self.target.max = max;
+                false
+            }
+            MacrosMsg::ToggleTargetInvert => {
+                self.target.invert = !self.target.invert;
+                true
+            }
+            MacrosMsg::SetTargetCurve(curve) => {
+                self.target.curve = curve;
+                false
+            }
+            MacrosMsg::AddTarget => {
+                let (macro_id, module) = match (self.target.macro_id, self.target.module) {
+                    (Some(macro_id), Some(module)) => (macro_id, module),
+                    _ => return false,
+                };
+
+                if self.target.field.is_empty() {
+                    return false;
+                }
+
+                let target = MacroTarget {
+                    module,
+                    field: self.target.field.clone(),
+                    min: self.target.min,
+                    max: self.target.max,
+                    invert: self.target.invert,
+                    curve: self.target.curve,
+                };
+
+                self.update_macro(macro_id, |params| params.targets.push(target));
+                false
+            }
+            MacrosMsg::RemoveTarget(macro_id, index) => {
+                self.update_macro(macro_id, |params| {
+                    if index < params.targets.len() {
+                        params.targets.remove(index);
+                    }
+                });
+                false
+            }
+        }
+    }
+
+    fn view(&self) -> Html {
+        let state = match self.props.session.workspace() {
+            Some(state) => state,
+            None => return html! {},
+        };
+
+        let state = state.borrow();
+
+        let midi_class = match self.midi_mode {
+            MidiUiMode::Normal => "media-library-main-button",
+            MidiUiMode::Configure => "media-library-main-button macros-midi-active",
+        };
+
+        html! {
+            <div class="macros">
+                <div class="media-library-main-button-row">
+                    <input type="text"
+                        placeholder="Macro name"
+                        onchange={self.link.batch_callback(|change| match change {
+                            ChangeData::Value(name) => vec![MacrosMsg::SetName(name)],
+                            _ => vec![],
+                        })}
+                    />
+                    <div class="media-library-main-button" onclick={self.link.callback(|_| MacrosMsg::Create)}>
+                        {"+ New Macro"}
+                    </div>
+                    <div class={midi_class} onclick={self.link.callback(|_| MacrosMsg::ToggleMidiMode)}>
+                        {"MIDI"}
+                    </div>
+                </div>
+
+                <div class="macros-controls">
+                    { for state.macros.iter().map(|(macro_id, params)| {
+                        let macro_id = *macro_id;
+
+                        html! {
+                            <div class="macros-control">
+                                <div class="macros-control-name">{&params.name}</div>
+                                <MidiRangeTarget
+                                    ui_mode={self.midi_mode}
+                                    target={MidiTarget::Macro(macro_id)}
+                                    onchange={self.link.callback(move |value| MacrosMsg::SetValue(macro_id, value))}
+                                >
+                                    <Fader
+                                        value={params.value}
+                                        onchange={self.link.callback(move |value| MacrosMsg::SetValue(macro_id, value))}
+                                    />
+                                </MidiRangeTarget>
+                                <button onclick={self.link.callback(move |_| MacrosMsg::Delete(macro_id))}>
+                                    {"Delete"}
+                                </button>
+                            </div>
+                        }
+                    }) }
+                </div>
+
+                {self.view_targets(&state)}
+                {self.view_target_form(&state)}
+            </div>
+        }
+    }
+}
+
+impl Macros {
+    fn update_macro(&self, macro_id: MacroId, f: impl FnOnce(&mut MacroParams)) {
+        let params = self.props.session.workspace()
+            .and_then(|state| state.borrow().macros.get(&macro_id).cloned());
+
+        if let Some(mut params) = params {
+            f(&mut params);
+            self.props.session.update_workspace(WorkspaceOp::UpdateMacro(macro_id, params));
+        }
+    }
+
+    fn view_targets(&self, state: &crate::session::WorkspaceState) -> Html {
+        html! {
+            <table class="media-library-table">
+                <tr class="table-heading">
+                    <th>{"Macro"}</th>
+                    <th>{"Module"}</th>
+                    <th>{"Field"}</th>
+                    <th>{"Range"}</th>
+                    <th>{"Curve"}</th>
+                    <th></th>
+                </tr>
+                { for state.macros.iter().flat_map(|(macro_id, params)| {
+                    let macro_id = *macro_id;
+
+                    params.targets.iter().enumerate().map(move |(index, target)| {
+                        let range = if target.invert {
+                            format!("{} - {}", target.max, target.min)
+                        } else {
+                            format!("{} - {}", target.min, target.max)
+                        };
+
+                        html! {
+                            <tr>
+                                <td>{&params.name}</td>
+                                <td>{state.module_name(target.module)}</td>
+                                <td>{&target.field}</td>
+                                <td>{range}</td>
+                                <td>{format!("{:?}", target.curve)}</td>
+                                <td>
+                                    <button onclick={self.link.callback(move |_| MacrosMsg::RemoveTarget(macro_id, index))}>
+                                        {"Remove"}
+                                    </button>
+                                </td>
+                            </tr>
+                        }
+                    })
+                }) }
+            </table>
+        }
+    }
+
+    fn view_target_form(&self, state: &crate::session::WorkspaceState) -> Html {
+        html! {
+            <div class="media-library-main-button-row">
+                <select onchange={self.link.batch_callback(|change| {
+                    select_id(change).map(|id| MacrosMsg::SetTargetMacro(id.map(MacroId))).into_iter().collect()
+                })}>
+                    <option value="">{"Macro..."}</option>
+                    { for state.macros.iter().map(|(macro_id, params)| html! {
+                        <option value={macro_id.0.to_string()}>
+                            {&params.name}
+                        </option>
+                    }) }
+                </select>
+                <select onchange={self.link.batch_callback(|change| {
+                    select_id(change).map(|id| MacrosMsg::SetTargetModule(id.map(ModuleId))).into_iter().collect()
+                })}>
+                    <option value="">{"Module..."}</option>
+                    { for state.modules.keys().map(|module_id| html! {
+                        <option value={module_id.0.to_string()}>
+                            {state.module_name(*module_id)}
+                        </option>
+                    }) }
+                </select>
+                <input type="text"
+                    placeholder="/channels/0/fader"
+                    value={self.target.field.clone()}
+                    onchange={self.link.batch_callback(|change| match change {
+                        ChangeData::Value(field) => vec![MacrosMsg::SetTargetField(field)],
+                        _ => vec![],
+                    })}
+                />
+                <label class="form-field">
+                    <span class="form-field-label">{"Min"}</span>
+                    <input type="number" step="0.01"
+                        value={self.target.min.to_string()}
+                        onchange={self.link.batch_callback(|change| number(change).map(MacrosMsg::SetTargetMin).into_iter().collect())}
+                    />
+                </label>
+                <label class="form-field">
+                    <span class="form-field-label">{"Max"}</span>
+                    <input type="number" step="0.01"
+                        value={self.target.max.to_string()}
+                        onchange={self.link.batch_callback(|change| number(change).map(MacrosMsg::SetTargetMax).into_iter().collect())}
+                    />
+                </label>
+                <label class="form-field">
+                    <span class="form-field-label">{"Invert"}</span>
+                    <input type="checkbox"
+                        checked={self.target.invert}
+                        onclick={self.link.callback(|_| MacrosMsg::ToggleTargetInvert)}
+                    />
+                </label>
+                <select onchange={self.link.batch_callback(|change| match change {
+                    ChangeData::Select(select) => match select.value().as_str() {
+                        "Exponential" => vec![MacrosMsg::SetTargetCurve(MacroCurve::Exponential)],
+                        _ => vec![MacrosMsg::SetTargetCurve(MacroCurve::Linear)],
+                    },
+                    _ => vec![],
+                })}>
+                    <option value="Linear">{"Linear"}</option>
+                    <option value="Exponential">{"Exponential"}</option>
+                </select>
+                <div class="media-library-main-button" onclick={self.link.callback(|_| MacrosMsg::AddTarget)}>
+                    {"+ Add Target"}
+                </div>
+            </div>
+        }
+    }
+}
+
+// Some(None) for the placeholder option, None if the change is not a select
+fn select_id(change: ChangeData) -> Option<Option<NonZeroUsize>> {
+    match change {
+        ChangeData::Select(select) => Some(select.value().parse().ok()),
+        _ => None,
+    }
+}
+
+fn number(change: ChangeData) -> Option<f64> {
+    match change {
+        ChangeData::Value(value) => value.parse().ok(),
+        _ => None,
+    }
+}
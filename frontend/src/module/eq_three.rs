@@ -1,6 +1,6 @@
 use yew::{html, ComponentLink, Html};
 
-use mixlab_protocol::{ModuleId, ModuleParams, EqThreeParams, Decibel};
+use mixlab_protocol::{ModuleId, ModuleParams, EqThreeParams, Decibel, MidiTarget};
 
 use crate::component::midi_target::{MidiRangeTarget, MidiUiMode};
 use crate::component::pure_module::{Pure, PureModule};
@@ -10,13 +10,14 @@ use crate::workspace::{Window, WindowMsg};
 pub type EqThree = Pure<EqThreeParams>;
 
 impl PureModule for EqThreeParams {
-    fn view(&self, _: ModuleId, module: ComponentLink<Window>, midi_mode: MidiUiMode) -> Html {
+    fn view(&self, id: ModuleId, module: ComponentLink<Window>, midi_mode: MidiUiMode) -> Html {
 
         html! {
             <>
                 <div>{"HI"}</div>
                 <MidiRangeTarget
                     ui_mode={midi_mode}
+                    target={MidiTarget::ModuleParam(id, "/gain_hi".to_owned())}
                     onchange={module.callback(wrap_decibel(update_params(self,
                         |params, value| EqThreeParams { gain_hi: value, ..params })))}
                 >
@@ -33,6 +34,7 @@ impl PureModule for EqThreeParams {
                 <div>{"MID"}</div>
                 <MidiRangeTarget
                     ui_mode={midi_mode}
+                    target={MidiTarget::ModuleParam(id, "/gain_mid".to_owned())}
                     onchange={module.callback(wrap_decibel(update_params(self,
                         |params, value| EqThreeParams { gain_mid: value, ..params })))}
                 >
@@ -49,6 +51,7 @@ impl PureModule for EqThreeParams {
                 <div>{"LO"}</div>
                 <MidiRangeTarget
                     ui_mode={midi_mode}
+                    target={MidiTarget::ModuleParam(id, "/gain_lo".to_owned())}
                     onchange={module.callback(wrap_decibel(update_params(self,
                         |params, value| EqThreeParams { gain_lo: value, ..params })))}
                 >
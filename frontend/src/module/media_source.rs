@@ -6,6 +6,7 @@ use yew_components::Select;
 
 use mixlab_protocol::{ModuleId, ModuleParams, MediaSourceParams, MediaLibrary, MediaId};
 
+use crate::library;
 use crate::util::notify;
 use crate::session::SessionRef;
 use crate::workspace::{Window, WindowMsg};
@@ -88,12 +89,22 @@ impl Component for MediaSource {
             }
         });
 
+        let preview = self.props.params.media_id.and_then(|id| {
+            self.library.iter()
+                .flat_map(|library| library.items.iter())
+                .find(|item| item.id == id)
+                .map(library::view_preview)
+        });
+
         html! {
-            <Select<MediaSourceItem>
-                options={options}
-                selected={selected}
-                on_change={self.link.callback(MediaSourceMsg::ChangeSource)}
-            />
+            <>
+                <Select<MediaSourceItem>
+                    options={options}
+                    selected={selected}
+                    on_change={self.link.callback(MediaSourceMsg::ChangeSource)}
+                />
+                { preview.unwrap_or_else(|| html! {}) }
+            </>
         }
     }
 }
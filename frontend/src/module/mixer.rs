@@ -1,6 +1,6 @@
 use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties, Callback};
 
-use mixlab_protocol::{ModuleId, MixerParams, MixerChannelParams, ModuleParams, Decibel};
+use mixlab_protocol::{ModuleId, MixerParams, MixerChannelParams, ModuleParams, Decibel, MidiTarget};
 
 use crate::component::midi_target::{MidiRangeTarget, MidiUiMode};
 use crate::control::{Fader, Rotary};
@@ -57,6 +57,8 @@ impl Component for Mixer {
                     .map(|(idx, channel)| {
                         html! {
                             <Channel
+                                module={self.props.id}
+                                index={idx}
                                 params={channel}
                                 onchange={self.link.callback(move |params|
                                     MixerMsg::ChannelChanged(idx, params))}
@@ -83,6 +85,8 @@ pub enum ChannelMsg {
 
 #[derive(Properties, Clone)]
 pub struct ChannelProps {
+    pub module: ModuleId,
+    pub index: usize,
     pub params: MixerChannelParams,
     pub onchange: Callback<MixerChannelParams>,
     pub midi_mode: MidiUiMode,
@@ -139,6 +143,7 @@ impl Component for Channel {
             <div class="mixer-channel">
                 <MidiRangeTarget
                     ui_mode={self.props.midi_mode}
+                    target={self.midi_target("gain")}
                     onchange={self.link.callback(|gain| {
                         ChannelMsg::GainChanged(Decibel(gain * 30.0 - 24.0))
                     })}
@@ -156,6 +161,7 @@ impl Component for Channel {
                 </div>
                 <MidiRangeTarget
                     ui_mode={self.props.midi_mode}
+                    target={self.midi_target("fader")}
                     onchange={self.link.callback(ChannelMsg::FaderChanged)}
                 >
                     <Fader
@@ -167,3 +173,10 @@ impl Component for Channel {
         }
     }
 }
+
+impl Channel {
+    fn midi_target(&self, field: &str) -> MidiTarget {
+        MidiTarget::ModuleParam(self.props.module,
+            format!("/channels/{}/{}", self.props.index, field))
+    }
+}
@@ -1,6 +1,6 @@
 use yew::{html, ComponentLink, Html, Callback, MouseEvent};
 
-use mixlab_protocol::{ModuleId, ModuleParams, VideoMixerParams, VIDEO_MIXER_CHANNELS};
+use mixlab_protocol::{ModuleId, ModuleParams, VideoMixerParams, MidiTarget, VIDEO_MIXER_CHANNELS};
 
 use crate::component::pure_module::{Pure, PureModule};
 use crate::component::midi_target::{MidiRangeTarget, MidiUiMode};
@@ -11,7 +11,7 @@ use crate::util::prevent_default;
 pub type VideoMixer = Pure<VideoMixerParams>;
 
 impl PureModule for VideoMixerParams {
-    fn view(&self, _: ModuleId, module: ComponentLink<Window>, midi_mode: MidiUiMode) -> Html {
+    fn view(&self, id: ModuleId, module: ComponentLink<Window>, midi_mode: MidiUiMode) -> Html {
         html! {
             <>
                 <div class="video-mixer">
@@ -31,6 +31,7 @@ impl PureModule for VideoMixerParams {
                     <div class="video-mixer-fader">
                         <MidiRangeTarget
                             ui_mode={midi_mode}
+                            target={MidiTarget::ModuleParam(id, "/fader".to_owned())}
                             onchange={module.callback(
                                 update_params(self, move |params, fader|
                                     VideoMixerParams { fader, ..params }))}
@@ -0,0 +1,159 @@
+use yew::events::ChangeData;
+use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties};
+
+use mixlab_protocol::{PresenceOp, Role};
+
+use crate::session::{self, SessionRef, PresenceState};
+use crate::util::notify;
+
+#[derive(Properties, Clone)]
+pub struct PresenceProps {
+    pub session: SessionRef,
+}
+
+pub struct PeerCursors {
+    props: PresenceProps,
+    presence: PresenceState,
+    _notify: notify::Handle,
+}
+
+impl Component for PeerCursors {
+    type Message = ();
+    type Properties = PresenceProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let notify = props.session.listen_presence(link.callback(|()| ()));
+        let presence = props.session.presence();
+
+        PeerCursors {
+            props,
+            presence,
+            _notify: notify,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        false
+    }
+
+    fn update(&mut self, _: ()) -> ShouldRender {
+        self.presence = self.props.session.presence();
+        true
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <>
+                { for self.presence.others().map(|(peer_id, peer)| {
+                    match peer.pointer {
+                        Some(pointer) => {
+                            let style = format!("left:{}px; top:{}px; border-color:{};",
+                                pointer.x, pointer.y, session::peer_color(peer_id));
+
+                            html! {
+                                <div class="peer-cursor" style={style}>
+                                    <div class="peer-cursor-name" style={format!("background-color:{};", session::peer_color(peer_id))}>
+                                        {&peer.name}
+                                    </div>
+                                </div>
+                            }
+                        }
+                        None => html! {},
+                    }
+                }) }
+            </>
+        }
+    }
+}
+
+pub struct PeerList {
+    link: ComponentLink<Self>,
+    props: PresenceProps,
+    presence: PresenceState,
+    _notify: notify::Handle,
+}
+
+pub enum PeerListMsg {
+    PresenceChanged,
+    SetName(String),
+}
+
+impl Component for PeerList {
+    type Message = PeerListMsg;
+    type Properties = PresenceProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let notify = props.session.listen_presence(link.callback(|()| PeerListMsg::PresenceChanged));
+        let presence = props.session.presence();
+
+        PeerList {
+            link,
+            props,
+            presence,
+            _notify: notify,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        false
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            PeerListMsg::PresenceChanged => {
+                let presence = self.props.session.presence();
+
+                // pointer movements are frequent and not shown in this list:
+                let changed = presence.you != self.presence.you ||
+                    presence.peers.len() != self.presence.peers.len() ||
+                    presence.peers.iter().zip(&self.presence.peers)
+                        .any(|((a_id, a), (b_id, b))| a_id != b_id || a.name != b.name || a.role != b.role);
+
+                self.presence = presence;
+                changed
+            }
+            PeerListMsg::SetName(name) => {
+                self.props.session.update_presence(PresenceOp::SetName(name));
+                false
+            }
+        }
+    }
+
+    fn view(&self) -> Html {
+        let you = self.presence.you.and_then(|id| self.presence.peers.get(&id));
+
+        html! {
+            <div class="peer-list">
+                <div class="peer-list-heading">{"Connected"}</div>
+                { match you {
+                    Some(you) => html! {
+                        <input type="text"
+                            class="peer-list-name-input"
+                            value={you.name.clone()}
+                            onchange={self.link.batch_callback(|change| match change {
+                                ChangeData::Value(name) => vec![PeerListMsg::SetName(name)],
+                                _ => vec![],
+                            })}
+                        />
+                    },
+                    None => html! {},
+                } }
+                { for self.presence.others().map(|(peer_id, peer)| {
+                    let role = match peer.role {
+                        Role::Operator => "",
+                        Role::Viewer => " (viewer)",
+                    };
+
+                    html! {
+                        <div class="peer-list-item">
+                            <span class="peer-list-swatch" style={format!("background-color:{};", session::peer_color(peer_id))} />
+                            {format!("{}{}", peer.name, role)}
+                        </div>
+                    }
+                }) }
+            </div>
+        }
+    }
+}
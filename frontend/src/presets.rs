@@ -0,0 +1,142 @@
+use std::rc::Rc;
+
+use yew::events::ChangeData;
+use yew::{html, Callback, Component, ComponentLink, Html, ShouldRender, Properties};
+
+use mixlab_protocol as protocol;
+use mixlab_protocol::{ModuleId, PresetOp, PresetRef};
+
+use crate::session::SessionRef;
+use crate::util::notify;
+
+/// Drops down from a module window's title bar, listing the presets for the
+/// module's kind
+pub struct PresetMenu {
+    link: ComponentLink<Self>,
+    props: PresetMenuProps,
+    library: Option<Rc<protocol::PresetLibrary>>,
+    name: String,
+    _presets_notify: notify::Handle,
+}
+
+#[derive(Properties, Clone)]
+pub struct PresetMenuProps {
+    pub session: SessionRef,
+    pub module: ModuleId,
+    // see ModuleParams::kind:
+    pub kind: String,
+    pub onclose: Callback<()>,
+}
+
+pub enum PresetMenuMsg {
+    Update(Rc<protocol::PresetLibrary>),
+    SetName(String),
+    Save,
+    Apply(PresetRef),
+    Delete(protocol::PresetId),
+}
+
+impl Component for PresetMenu {
+    type Message = PresetMenuMsg;
+    type Properties = PresetMenuProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let presets_notify = props.session.listen_presets(link.callback(PresetMenuMsg::Update));
+
+        PresetMenu {
+            link,
+            props,
+            library: None,
+            name: String::new(),
+            _presets_notify: presets_notify,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        true
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            PresetMenuMsg::Update(library) => {
+                self.library = Some(library);
+                true
+            }
+            PresetMenuMsg::SetName(name) => {
+                self.name = name;
+                false
+            }
+            PresetMenuMsg::Save => {
+                if self.name.is_empty() {
+                    return false;
+                }
+
+                self.props.session.update_presets(PresetOp::Save {
+                    module: self.props.module,
+                    name: self.name.clone(),
+                });
+
+                false
+            }
+            PresetMenuMsg::Apply(preset) => {
+                self.props.session.update_presets(PresetOp::Apply(self.props.module, preset));
+                self.props.onclose.emit(());
+                false
+            }
+            PresetMenuMsg::Delete(preset_id) => {
+                self.props.session.update_presets(PresetOp::Delete(preset_id));
+                false
+            }
+        }
+    }
+
+    fn view(&self) -> Html {
+        let presets = self.library.iter()
+            .flat_map(|library| library.presets.iter())
+            .filter(|preset| preset.kind == self.props.kind);
+
+        html! {
+            <div class="context-menu preset-menu">
+                <div class="context-menu-heading">{"Presets"}</div>
+                { for presets.map(|preset| {
+                    let preset_ref = preset.preset.clone();
+
+                    let delete = match preset.preset {
+                        PresetRef::Factory(_) => html! {},
+                        PresetRef::Saved(preset_id) => html! {
+                            <div class="preset-menu-delete"
+                                onclick={self.link.callback(move |_| PresetMenuMsg::Delete(preset_id))}
+                            >
+                                {"×"}
+                            </div>
+                        },
+                    };
+
+                    html! {
+                        <div class="context-menu-item preset-menu-item">
+                            <div class="preset-menu-name"
+                                onclick={self.link.callback(move |_| PresetMenuMsg::Apply(preset_ref.clone()))}
+                            >
+                                {&preset.name}
+                            </div>
+                            {delete}
+                        </div>
+                    }
+                }) }
+                <div class="preset-menu-save">
+                    <input type="text"
+                        placeholder="Preset name"
+                        onchange={self.link.batch_callback(|change| match change {
+                            ChangeData::Value(name) => vec![PresetMenuMsg::SetName(name)],
+                            _ => vec![],
+                        })}
+                    />
+                    <button onclick={self.link.callback(|_| PresetMenuMsg::Save)}>
+                        {"Save"}
+                    </button>
+                </div>
+            </div>
+        }
+    }
+}
@@ -0,0 +1,495 @@
+use std::collections::BTreeSet;
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+
+use gloo_events::EventListener;
+use js_sys::{Array, Reflect, JSON};
+use wasm_bindgen::JsValue;
+use web_sys::{Blob, XmlHttpRequest};
+use yew::events::ChangeData;
+use yew::{html, Callback, Component, ComponentLink, Html, ShouldRender, Properties};
+
+use mixlab_protocol as protocol;
+use mixlab_protocol::{ModuleId, ProjectOp, Role};
+
+use crate::session::SessionRef;
+use crate::util::{self, notify};
+
+pub struct Projects {
+    link: ComponentLink<Self>,
+    props: ProjectsProps,
+    library: Option<Rc<protocol::ProjectLibrary>>,
+    name: String,
+    all_media: bool,
+    import: Option<ImportState>,
+    picker: Option<ModulePicker>,
+    _notify: notify::Handle,
+}
+
+enum ImportState {
+    Importing(Request),
+    Failed(String),
+}
+
+// choosing modules of another project to import into the open one
+struct ModulePicker {
+    project: String,
+    modules: PickerModules,
+    selected: BTreeSet<ModuleId>,
+}
+
+enum PickerModules {
+    Loading(Request),
+    Loaded(Vec<SourceModule>),
+    Failed(String),
+}
+
+struct SourceModule {
+    id: ModuleId,
+    kind: String,
+    sources: Vec<ModuleId>,
+}
+
+#[derive(Properties, Clone)]
+pub struct ProjectsProps {
+    pub session: SessionRef,
+}
+
+pub enum ProjectsMsg {
+    Update(Rc<protocol::ProjectLibrary>),
+    SetName(String),
+    Create,
+    Open(String),
+    SetAllMedia(bool),
+    Import(ChangeData),
+    Imported(Result<Response, String>),
+    PickModules(String),
+    ModulesLoaded(Result<Response, String>),
+    ToggleModule(ModuleId),
+    ImportModules,
+    ClosePicker,
+}
+
+impl Component for Projects {
+    type Message = ProjectsMsg;
+    type Properties = ProjectsProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let notify = props.session.listen_projects(link.callback(ProjectsMsg::Update));
+
+        Projects {
+            link,
+            props,
+            library: None,
+            name: String::new(),
+            all_media: false,
+            import: None,
+            picker: None,
+            _notify: notify,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            ProjectsMsg::Update(library) => {
+                self.library = Some(library);
+                true
+            }
+            ProjectsMsg::SetName(name) => {
+                self.name = name;
+                false
+            }
+            ProjectsMsg::Create => {
+                if self.name.is_empty() {
+                    return false;
+                }
+
+                self.props.session.update_projects(ProjectOp::Create(self.name.clone()));
+                false
+            }
+            ProjectsMsg::Open(name) => {
+                self.props.session.update_projects(ProjectOp::Open(name));
+                false
+            }
+            ProjectsMsg::SetAllMedia(all_media) => {
+                self.all_media = all_media;
+                true
+            }
+            ProjectsMsg::Import(ChangeData::Files(files)) => {
+                let file = match files.get(0) {
+                    Some(file) => file,
+                    None => return false,
+                };
+
+                // the imported project takes the name entered, or else the
+                // name it was exported with:
+                let name = if self.name.is_empty() {
+                    bundle_name(&file.name())
+                } else {
+                    self.name.clone()
+                };
+
+                let url = format!("{}/_projects/import?name={}",
+                    util::origin(), String::from(js_sys::encode_uri_component(&name)));
+
+                let state = match Request::send("POST", &url, Some(&*file), self.link.callback(ProjectsMsg::Imported)) {
+                    Ok(request) => ImportState::Importing(request),
+                    Err(e) => ImportState::Failed(e),
+                };
+
+                self.import = Some(state);
+                true
+            }
+            ProjectsMsg::Import(_) => false,
+            ProjectsMsg::Imported(result) => {
+                // on success the server switches to the imported project and
+                // the library updates to match
+                self.import = result.and_then(|response| match response.status {
+                    204 => Ok(()),
+                    400 => Err("invalid project name".to_owned()),
+                    409 => Err("a project with that name already exists".to_owned()),
+                    422 => Err("not a project bundle".to_owned()),
+                    status => Err(format!("server error {}", status)),
+                }).err().map(ImportState::Failed);
+
+                true
+            }
+            ProjectsMsg::PickModules(project) => {
+                let url = format!("{}/_projects/modules?project={}",
+                    util::origin(), String::from(js_sys::encode_uri_component(&project)));
+
+                let modules = match Request::send("GET", &url, None, self.link.callback(ProjectsMsg::ModulesLoaded)) {
+                    Ok(request) => PickerModules::Loading(request),
+                    Err(e) => PickerModules::Failed(e),
+                };
+
+                self.picker = Some(ModulePicker {
+                    project,
+                    modules,
+                    selected: BTreeSet::new(),
+                });
+
+                true
+            }
+            ProjectsMsg::ModulesLoaded(result) => {
+                if let Some(picker) = &mut self.picker {
+                    picker.modules = match result.and_then(parse_modules) {
+                        Ok(modules) => PickerModules::Loaded(modules),
+                        Err(e) => PickerModules::Failed(e),
+                    };
+                }
+
+                true
+            }
+            ProjectsMsg::ToggleModule(module_id) => {
+                if let Some(picker) = &mut self.picker {
+                    if !picker.selected.remove(&module_id) {
+                        picker.selected.insert(module_id);
+                    }
+                }
+
+                true
+            }
+            ProjectsMsg::ImportModules => {
+                if let Some(picker) = self.picker.take() {
+                    if !picker.selected.is_empty() {
+                        let modules = picker.selected.into_iter().collect();
+                        self.props.session.update_projects(ProjectOp::ImportModules(picker.project, modules));
+                    }
+                }
+
+                true
+            }
+            ProjectsMsg::ClosePicker => {
+                self.picker = None;
+                true
+            }
+        }
+    }
+
+    fn view(&self) -> Html {
+        let library = match &self.library {
+            Some(library) => library,
+            None => return html! {},
+        };
+
+        let operator = self.props.session.role() == Role::Operator;
+
+        html! {
+            <div class="projects">
+                { if operator {
+                    html! {
+                        <>
+                            <div class="media-library-main-button-row">
+                                <input type="text"
+                                    placeholder="Project name"
+                                    onchange={self.link.batch_callback(|change| match change {
+                                        ChangeData::Value(name) => vec![ProjectsMsg::SetName(name)],
+                                        _ => vec![],
+                                    })}
+                                />
+                                <div class="media-library-main-button" onclick={self.link.callback(|_| ProjectsMsg::Create)}>
+                                    {"+ New Project"}
+                                </div>
+                                <label>
+                                    <div class="media-library-main-button">{"Import"}</div>
+                                    <input
+                                        type="file"
+                                        accept=".tar"
+                                        style="display:none"
+                                        onchange={self.link.callback(ProjectsMsg::Import)}
+                                    />
+                                </label>
+                            </div>
+                            <div class="media-library-main-button-row projects-export">
+                                <a class="media-library-main-button" href={self.export_url()} download="">
+                                    {"Export Open Project"}
+                                </a>
+                                <label>
+                                    <input type="checkbox"
+                                        checked={self.all_media}
+                                        onclick={self.link.callback({
+                                            let all_media = self.all_media;
+                                            move |_| ProjectsMsg::SetAllMedia(!all_media)
+                                        })}
+                                    />
+                                    {"Include unused media"}
+                                </label>
+                            </div>
+                            { match &self.import {
+                                Some(ImportState::Importing(_)) => html! {
+                                    <div class="projects-import-status">{"Importing..."}</div>
+                                },
+                                Some(ImportState::Failed(reason)) => html! {
+                                    <div class="projects-import-status">{format!("Import failed: {}", reason)}</div>
+                                },
+                                None => html! {},
+                            } }
+                        </>
+                    }
+                } else {
+                    html! {}
+                } }
+                <table class="media-library-table">
+                    <tr class="table-heading">
+                        <th>{"Project"}</th>
+                        <th></th>
+                    </tr>
+                    { for library.projects.iter().map(|name| {
+                        let active = *name == library.active;
+
+                        let action = if active {
+                            html! { <span class="projects-active">{"Open"}</span> }
+                        } else if operator {
+                            let open = name.clone();
+                            let pick = name.clone();
+                            html! {
+                                <>
+                                    <button onclick={self.link.callback(move |_| ProjectsMsg::Open(open.clone()))}>
+                                        {"Switch"}
+                                    </button>
+                                    <button onclick={self.link.callback(move |_| ProjectsMsg::PickModules(pick.clone()))}>
+                                        {"Import Modules"}
+                                    </button>
+                                </>
+                            }
+                        } else {
+                            html! {}
+                        };
+
+                        html! {
+                            <tr>
+                                <td>{name}</td>
+                                <td>{action}</td>
+                            </tr>
+                        }
+                    }) }
+                </table>
+                { self.view_picker() }
+            </div>
+        }
+    }
+}
+
+impl Projects {
+    fn export_url(&self) -> String {
+        let mut url = util::origin() + "/_projects/export";
+
+        if self.all_media {
+            url += "?all_media=true";
+        }
+
+        url
+    }
+
+    fn view_picker(&self) -> Html {
+        let picker = match &self.picker {
+            Some(picker) => picker,
+            None => return html! {},
+        };
+
+        let modules = match &picker.modules {
+            PickerModules::Loading(_) => html! {
+                <div>{"Loading..."}</div>
+            },
+            PickerModules::Failed(reason) => html! {
+                <div>{format!("Could not list modules: {}", reason)}</div>
+            },
+            PickerModules::Loaded(modules) => html! {
+                <table class="media-library-table">
+                    <tr class="table-heading">
+                        <th colspan={3}>{format!("Modules in {}", picker.project)}</th>
+                    </tr>
+                    { for modules.iter().map(|module| {
+                        let module_id = module.id;
+
+                        let sources = module.sources.iter()
+                            .map(|source| format!("#{}", source.0))
+                            .collect::<Vec<_>>();
+
+                        html! {
+                            <tr>
+                                <td>
+                                    <input type="checkbox"
+                                        checked={picker.selected.contains(&module_id)}
+                                        onclick={self.link.callback(move |_| ProjectsMsg::ToggleModule(module_id))}
+                                    />
+                                </td>
+                                <td>{format!("{} #{}", module.kind, module_id.0)}</td>
+                                <td>
+                                    { if sources.is_empty() {
+                                        String::new()
+                                    } else {
+                                        format!("fed by {}", sources.join(", "))
+                                    } }
+                                </td>
+                            </tr>
+                        }
+                    }) }
+                </table>
+            },
+        };
+
+        html! {
+            <div class="scenes-save">
+                {modules}
+                <div class="media-library-main-button-row">
+                    <div class="media-library-main-button" onclick={self.link.callback(|_| ProjectsMsg::ImportModules)}>
+                        {"+ Insert Selected"}
+                    </div>
+                    <div class="media-library-main-button" onclick={self.link.callback(|_| ProjectsMsg::ClosePicker)}>
+                        {"Cancel"}
+                    </div>
+                </div>
+            </div>
+        }
+    }
+}
+
+// bundles are exported as <project>.mixlab.tar
+fn bundle_name(filename: &str) -> String {
+    filename.trim_end_matches(".tar").trim_end_matches(".mixlab").to_owned()
+}
+
+fn parse_modules(response: Response) -> Result<Vec<SourceModule>, String> {
+    match response.status {
+        200 => {}
+        409 => return Err("that project is open".to_owned()),
+        status => return Err(format!("server error {}", status)),
+    }
+
+    let json = JSON::parse(&response.body).map_err(|e| format!("{:?}", e))?;
+
+    Array::from(&json).iter()
+        .map(|module| {
+            let id = module_id(&get(&module, "id")?)?;
+
+            let kind = get(&module, "kind")?.as_string()
+                .ok_or_else(|| "module kind not a string".to_owned())?;
+
+            let sources = Array::from(&get(&module, "sources")?).iter()
+                .map(|source| module_id(&source))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(SourceModule { id, kind, sources })
+        })
+        .collect()
+}
+
+fn get(object: &JsValue, key: &str) -> Result<JsValue, String> {
+    Reflect::get(object, &key.into()).map_err(|e| format!("{:?}", e))
+}
+
+fn module_id(value: &JsValue) -> Result<ModuleId, String> {
+    value.as_f64()
+        .and_then(|id| NonZeroUsize::new(id as usize))
+        .map(ModuleId)
+        .ok_or_else(|| "invalid module id".to_owned())
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+// a single request, aborted if dropped before it completes. bundles are sent
+// in one request too: unlike media uploads there is nothing to resume, as an
+// import which fails part way is discarded by the server
+struct Request {
+    xhr: XmlHttpRequest,
+    _listeners: Vec<EventListener>,
+}
+
+impl Request {
+    fn send(method: &str, url: &str, body: Option<&Blob>, callback: Callback<Result<Response, String>>) -> Result<Request, String> {
+        let xhr = XmlHttpRequest::new().map_err(|e| format!("{:?}", e))?;
+        xhr.open(method, url).map_err(|e| format!("{:?}", e))?;
+
+        let load = EventListener::new(&xhr, "load", {
+            let xhr = xhr.clone();
+            let callback = callback.clone();
+            move |_| {
+                let response = xhr.status()
+                    .and_then(|status| Ok(Response {
+                        status,
+                        body: xhr.response_text()?.unwrap_or_default(),
+                    }))
+                    .map_err(|e| format!("{:?}", e));
+
+                emit_later(&callback, response);
+            }
+        });
+
+        let error = EventListener::new(&xhr, "error", move |_| {
+            emit_later(&callback, Err("network error".to_owned()));
+        });
+
+        xhr.send_with_opt_blob(body).map_err(|e| format!("{:?}", e))?;
+
+        Ok(Request {
+            xhr,
+            _listeners: vec![load, error],
+        })
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        // nothing we can do in drop if abort fails
+        let _ = self.xhr.abort();
+    }
+}
+
+// the component drops the request, and with it the listener we are called
+// from, when it receives the response, so hand it over once the listener has
+// returned:
+fn emit_later(callback: &Callback<Result<Response, String>>, response: Result<Response, String>) {
+    let callback = callback.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        callback.emit(response);
+    });
+}
@@ -0,0 +1,291 @@
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+
+use yew::events::ChangeData;
+use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties};
+
+use mixlab_protocol as protocol;
+use mixlab_protocol::{ModuleId, MidiTarget, SceneId, SceneOp, SceneRecall, Microseconds};
+
+use crate::component::midi_target::{MidiRangeTarget, MidiUiMode};
+use crate::session::SessionRef;
+use crate::util::notify;
+
+pub struct Scenes {
+    link: ComponentLink<Self>,
+    props: ScenesProps,
+    library: Option<Rc<protocol::SceneLibrary>>,
+    name: String,
+    selected: BTreeSet<ModuleId>,
+    morph_secs: f64,
+    delay_secs: f64,
+    midi_mode: MidiUiMode,
+    // last value received from each scene's MIDI control:
+    midi_values: HashMap<SceneId, f64>,
+    _scenes_notify: notify::Handle,
+    _workspace_notify: notify::Handle,
+}
+
+#[derive(Properties, Clone)]
+pub struct ScenesProps {
+    pub session: SessionRef,
+}
+
+pub enum ScenesMsg {
+    Update(Rc<protocol::SceneLibrary>),
+    WorkspaceChanged,
+    SetName(String),
+    ToggleModule(ModuleId),
+    SetMorph(f64),
+    SetDelay(f64),
+    ToggleMidiMode,
+    Save,
+    Delete(SceneId),
+    Recall(SceneId),
+    MidiRecall(SceneId, f64),
+}
+
+impl Component for Scenes {
+    type Message = ScenesMsg;
+    type Properties = ScenesProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let scenes_notify = props.session.listen_scenes(link.callback(ScenesMsg::Update));
+        let workspace_notify = props.session.listen_workspace(link.callback(|()| ScenesMsg::WorkspaceChanged));
+
+        Scenes {
+            link,
+            props,
+            library: None,
+            name: String::new(),
+            selected: BTreeSet::new(),
+            morph_secs: 0.0,
+            delay_secs: 0.0,
+            midi_mode: MidiUiMode::Normal,
+            midi_values: HashMap::new(),
+            _scenes_notify: scenes_notify,
+            _workspace_notify: workspace_notify,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            ScenesMsg::Update(library) => {
+                self.library = Some(library);
+                true
+            }
+            ScenesMsg::WorkspaceChanged => {
+                true
+            }
+            ScenesMsg::SetName(name) => {
+                self.name = name;
+                false
+            }
+            ScenesMsg::ToggleModule(module_id) => {
+                if !self.selected.remove(&module_id) {
+                    self.selected.insert(module_id);
+                }
+                true
+            }
+            ScenesMsg::SetMorph(secs) => {
+                self.morph_secs = secs;
+                false
+            }
+            ScenesMsg::SetDelay(secs) => {
+                self.delay_secs = secs;
+                false
+            }
+            ScenesMsg::ToggleMidiMode => {
+                self.midi_mode = match self.midi_mode {
+                    MidiUiMode::Normal => MidiUiMode::Configure,
+                    MidiUiMode::Configure => MidiUiMode::Normal,
+                };
+                true
+            }
+            ScenesMsg::Save => {
+                if self.name.is_empty() || self.selected.is_empty() {
+                    return false;
+                }
+
+                self.props.session.update_scenes(SceneOp::Save {
+                    name: self.name.clone(),
+                    modules: self.selected.iter().copied().collect(),
+                });
+
+                false
+            }
+            ScenesMsg::Delete(scene_id) => {
+                // the server drops any MIDI mapping to the scene along with
+                // it, scene ids can be reused by the next scene saved:
+                self.props.session.update_scenes(SceneOp::Delete(scene_id));
+                self.midi_values.remove(&scene_id);
+                false
+            }
+            ScenesMsg::Recall(scene_id) => {
+                self.recall(scene_id);
+                false
+            }
+            ScenesMsg::MidiRecall(scene_id, value) => {
+                // treat MIDI controls as buttons, recalling when pressed.
+                // faders and knobs send a stream of values, so only recall
+                // on crossing the halfway point upwards:
+                let last = self.midi_values.insert(scene_id, value).unwrap_or(0.0);
+
+                if last < 0.5 && value >= 0.5 {
+                    self.recall(scene_id);
+                }
+                false
+            }
+        }
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <div class="scenes">
+                {self.view_save()}
+                {self.view_library()}
+            </div>
+        }
+    }
+}
+
+impl Scenes {
+    fn recall(&self, scene_id: SceneId) {
+        fn micros(secs: f64) -> Microseconds {
+            Microseconds((secs.max(0.0) * 1_000_000.0) as u64)
+        }
+
+        self.props.session.update_scenes(SceneOp::Recall(scene_id, SceneRecall {
+            delay: micros(self.delay_secs),
+            morph: micros(self.morph_secs),
+        }));
+    }
+
+    fn module_name(&self, module_id: ModuleId) -> String {
+        self.props.session.workspace()
+            .map(|state| state.borrow().module_name(module_id))
+            .unwrap_or_default()
+    }
+
+    fn view_save(&self) -> Html {
+        let modules = self.props.session.workspace()
+            .map(|state| state.borrow().modules.keys().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        html! {
+            <div class="scenes-save">
+                <table class="media-library-table">
+                    <tr class="table-heading">
+                        <th colspan={2}>{"Modules to capture"}</th>
+                    </tr>
+                    { for modules.into_iter().map(|module_id| {
+                        html! {
+                            <tr>
+                                <td>
+                                    <input type="checkbox"
+                                        checked={self.selected.contains(&module_id)}
+                                        onclick={self.link.callback(move |_| ScenesMsg::ToggleModule(module_id))}
+                                    />
+                                </td>
+                                <td>{self.module_name(module_id)}</td>
+                            </tr>
+                        }
+                    }) }
+                </table>
+                <div class="media-library-main-button-row">
+                    <input type="text"
+                        placeholder="Scene name"
+                        onchange={self.link.batch_callback(|change| match change {
+                            ChangeData::Value(name) => vec![ScenesMsg::SetName(name)],
+                            _ => vec![],
+                        })}
+                    />
+                    <div class="media-library-main-button" onclick={self.link.callback(|_| ScenesMsg::Save)}>
+                        {"+ Save Scene"}
+                    </div>
+                </div>
+            </div>
+        }
+    }
+
+    fn view_library(&self) -> Html {
+        let library = match &self.library {
+            Some(library) => library,
+            None => return html! {},
+        };
+
+        let midi_class = match self.midi_mode {
+            MidiUiMode::Normal => "media-library-main-button",
+            MidiUiMode::Configure => "media-library-main-button scenes-midi-active",
+        };
+
+        html! {
+            <>
+                <div class="media-library-main-button-row">
+                    <label class="form-field">
+                        <span class="form-field-label">{"Morph (s)"}</span>
+                        <input type="number" min="0" step="0.1"
+                            value={self.morph_secs.to_string()}
+                            onchange={self.link.batch_callback(|change| seconds(change).map(ScenesMsg::SetMorph).into_iter().collect())}
+                        />
+                    </label>
+                    <label class="form-field">
+                        <span class="form-field-label">{"Delay (s)"}</span>
+                        <input type="number" min="0" step="0.1"
+                            value={self.delay_secs.to_string()}
+                            onchange={self.link.batch_callback(|change| seconds(change).map(ScenesMsg::SetDelay).into_iter().collect())}
+                        />
+                    </label>
+                    <div class={midi_class} onclick={self.link.callback(|_| ScenesMsg::ToggleMidiMode)}>
+                        {"MIDI"}
+                    </div>
+                </div>
+                <table class="media-library-table">
+                    <tr class="table-heading">
+                        <th>{"Scene"}</th>
+                        <th>{"Modules"}</th>
+                        <th></th>
+                        <th></th>
+                    </tr>
+                    { for library.scenes.iter().map(|scene| {
+                        let scene_id = scene.id;
+
+                        html! {
+                            <tr>
+                                <td>{&scene.name}</td>
+                                <td>{scene.modules.len()}</td>
+                                <td>
+                                    <MidiRangeTarget
+                                        ui_mode={self.midi_mode}
+                                        target={MidiTarget::Scene(scene_id)}
+                                        onchange={self.link.callback(move |value| ScenesMsg::MidiRecall(scene_id, value))}
+                                    >
+                                        <button onclick={self.link.callback(move |_| ScenesMsg::Recall(scene_id))}>
+                                            {"Recall"}
+                                        </button>
+                                    </MidiRangeTarget>
+                                </td>
+                                <td>
+                                    <button onclick={self.link.callback(move |_| ScenesMsg::Delete(scene_id))}>
+                                        {"Delete"}
+                                    </button>
+                                </td>
+                            </tr>
+                        }
+                    }) }
+                </table>
+            </>
+        }
+    }
+}
+
+fn seconds(change: ChangeData) -> Option<f64> {
+    match change {
+        ChangeData::Value(value) => value.parse().ok(),
+        _ => None,
+    }
+}
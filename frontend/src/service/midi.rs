@@ -13,6 +13,9 @@ use wasm_bindgen_futures::JsFuture;
 use web_sys::{MidiInput, MidiMessageEvent, MidiConnectionEvent};
 use yew::Callback;
 
+use mixlab_protocol::{MidiBinding, MidiControl, MidiTarget};
+
+use crate::util::notify::{self, Notify};
 use crate::util::Sequence;
 
 struct MidiBroker {
@@ -22,16 +25,19 @@ struct MidiBroker {
     configuring: Option<ConfigureKind>,
     id_seq: Sequence,
     range_subscribers: BTreeMap<(MidiRangeId, SubscriptionId), Callback<u8>>,
+    // mirrors the mappings kept with the workspace on the server:
+    mappings: BTreeMap<MidiTarget, MidiRangeId>,
+    mapping_sink: Option<Callback<(MidiTarget, Option<MidiRangeId>)>>,
 }
 
+// mapping changes are broadcast outside of the RefCell, so that listeners
+// can call back into the broker:
 #[derive(Clone)]
-pub struct MidiBrokerRef(Rc<RefCell<MidiBroker>>);
+pub struct MidiBrokerRef(Rc<RefCell<MidiBroker>>, Rc<Notify<()>>);
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
-pub struct MidiRangeId(MidiInputId, u8);
-
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
-pub struct MidiNoteId(MidiInputId, u8);
+// controls are identified by input name rather than id, so that mappings
+// saved from one browser apply in others:
+pub type MidiRangeId = MidiBinding;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 struct SubscriptionId(NonZeroUsize);
@@ -47,6 +53,7 @@ impl SubscriptionId {
 }
 
 type MidiInputId = Rc<String>;
+type MidiDeviceName = Rc<String>;
 
 thread_local! {
     static BROKER: MidiBrokerRef = MidiBroker::new();
@@ -68,6 +75,48 @@ impl MidiBrokerRef {
         }
     }
 
+    /// Sets where mapping changes made in this browser are sent, for them to
+    /// come back through set_mapping once applied
+    pub fn set_mapping_sink(&self, sink: Callback<(MidiTarget, Option<MidiRangeId>)>) {
+        self.0.borrow_mut().mapping_sink = Some(sink);
+    }
+
+    /// Replaces all mappings, as when connecting to the server
+    pub fn set_mappings(&self, mappings: impl IntoIterator<Item = (MidiTarget, MidiRangeId)>) {
+        self.0.borrow_mut().mappings = mappings.into_iter().collect();
+        self.1.broadcast(());
+    }
+
+    pub fn set_mapping(&self, target: MidiTarget, range_id: Option<MidiRangeId>) {
+        {
+            let mut broker = self.0.borrow_mut();
+
+            match range_id {
+                Some(range_id) => { broker.mappings.insert(target, range_id); }
+                None => { broker.mappings.remove(&target); }
+            }
+        }
+
+        self.1.broadcast(());
+    }
+
+    pub fn mapping(&self, target: &MidiTarget) -> Option<MidiRangeId> {
+        self.0.borrow().mappings.get(target).cloned()
+    }
+
+    pub fn listen_mappings(&self, callback: Callback<()>) -> notify::Handle {
+        self.1.subscribe(callback)
+    }
+
+    /// Asks for a mapping to be made or removed, see set_mapping_sink
+    pub fn bind(&self, target: MidiTarget, range_id: Option<MidiRangeId>) {
+        let sink = self.0.borrow().mapping_sink.clone();
+
+        if let Some(sink) = sink {
+            sink.emit((target, range_id));
+        }
+    }
+
     pub fn subscribe_range(&self, range_id: MidiRangeId, callback: Callback<u8>) -> RangeSubscription {
         let key = {
             let mut broker = self.0.borrow_mut();
@@ -83,40 +132,55 @@ impl MidiBrokerRef {
         }
     }
 
-    fn on_message(&self, input_id: MidiInputId, event: &MidiMessageEvent) {
+    fn on_message(&self, device: MidiDeviceName, event: &MidiMessageEvent) {
         let data = event.data().expect("MidiMessageEvent::data");
 
-        // MIDI controller (range) change message
-        if data.len() == 3 && (data[0] & 0xf0) == 0xb0 {
-            let range_id = MidiRangeId(input_id, data[1] & 0x7f);
-            let value = data[2] & 0x7f;
+        // whether this message can complete configuring a target:
+        let (control, value, configures) = match data.as_slice() {
+            // MIDI controller (range) change message
+            &[status, cc, value] if (status & 0xf0) == 0xb0 => {
+                (MidiControl::Cc(cc & 0x7f), value & 0x7f, true)
+            }
+            // notes act as ranges too, following velocity while held. note on
+            // with zero velocity is note off:
+            &[status, note, velocity] if (status & 0xf0) == 0x90 => {
+                (MidiControl::Note(note & 0x7f), velocity & 0x7f, velocity > 0)
+            }
+            &[status, note, _] if (status & 0xf0) == 0x80 => {
+                (MidiControl::Note(note & 0x7f), 0, false)
+            }
+            _ => return,
+        };
 
-            let min_key = (range_id.clone(), SubscriptionId::min());
-            let max_key = (range_id.clone(), SubscriptionId::max());
+        let range_id = MidiRangeId { device: device.to_string(), control };
 
-            let mut subscribers = Vec::new();
-            let mut configuring = None;
+        let min_key = (range_id.clone(), SubscriptionId::min());
+        let max_key = (range_id.clone(), SubscriptionId::max());
 
-            {
-                let mut broker = self.0.borrow_mut();
+        let mut subscribers = Vec::new();
+        let mut configuring = None;
 
-                for (_, callback) in broker.range_subscribers.range(min_key..=max_key) {
-                    subscribers.push(callback.clone());
-                }
+        {
+            let mut broker = self.0.borrow_mut();
+
+            for (_, callback) in broker.range_subscribers.range(min_key..=max_key) {
+                subscribers.push(callback.clone());
+            }
 
+            if configures {
                 if let Some(ConfigureKind::Range(callback)) = &broker.configuring {
                     configuring = Some(callback.clone());
                     broker.configuring = None;
                 }
             }
+        }
 
-            for callback in subscribers {
-                callback.emit(value);
-            }
+        for callback in subscribers {
+            callback.emit(value);
+        }
 
-            if let Some(callback) = configuring {
-                callback.emit(Some((range_id, value)));
-            }
+        if let Some(callback) = configuring {
+            callback.emit(Some((range_id, value)));
         }
     }
 }
@@ -130,7 +194,9 @@ impl MidiBroker {
             configuring: None,
             id_seq: Sequence::new(),
             range_subscribers: BTreeMap::new(),
-        })));
+            mappings: BTreeMap::new(),
+            mapping_sink: None,
+        })), Rc::new(Notify::new()));
 
         wasm_bindgen_futures::spawn_local({
             let broker = broker.clone();
@@ -204,7 +270,6 @@ impl Drop for ConfigureTask {
 #[derive(Debug, Clone, PartialEq)]
 enum ConfigureKind {
     Range(Callback<Option<(MidiRangeId, u8)>>),
-    // Note(Callback<Option<MidiNoteId>>),
 }
 
 #[derive(Debug, From)]
@@ -231,15 +296,15 @@ async fn request_midi_access() -> Result<web_sys::MidiAccess, MidiError> {
 
 fn setup_input(broker: MidiBrokerRef, input: MidiInput) {
     let input_id = Rc::new(input.id());
+    let device = Rc::new(input.name().unwrap_or_else(|| input.id()));
 
     let event_listener = EventListener::new(&input, "midimessage", {
-        let input_id = input_id.clone();
         let broker = broker.clone();
         move |ev| {
             let message = ev.dyn_ref::<MidiMessageEvent>()
                 .expect("dyn_into MidiMessageEvent");
 
-            broker.on_message(input_id.clone(), message);
+            broker.on_message(device.clone(), message);
         }
     });
 
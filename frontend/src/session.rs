@@ -1,13 +1,14 @@
-use std::cell::RefCell;
-use std::collections::{BTreeMap, HashMap};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
 
 use yew::services::websocket::{WebSocketService, WebSocketStatus, WebSocketTask};
 use yew::format::Binary;
 use yew::Callback;
 
-use mixlab_protocol::{ServerMessage, ServerUpdate, ClientMessage, ClientSequence, ModuleId, ModuleParams, WindowGeometry, InputId, OutputId, Indication, Terminal, WorkspaceOp, WorkspaceMessage};
+use mixlab_protocol::{ServerMessage, ServerUpdate, ClientMessage, ClientSequence, ModuleId, ModuleParams, WindowGeometry, InputId, OutputId, Indication, Terminal, WorkspaceOp, WorkspaceMessage, MediaOp, SceneOp, PresetOp, ProjectOp, MacroId, MacroParams, Role, PeerId, Peer, PresenceOp, PresenceUpdate, ProtocolError, PROTOCOL_VERSION};
 
+use crate::service::midi;
 use crate::util;
 use crate::util::notify::{self, Notify};
 use crate::Sequence;
@@ -16,6 +17,8 @@ use crate::Sequence;
 pub struct Session {
     websocket: RefCell<Option<WebSocketTask>>,
     state: RefCell<Option<WorkspaceStateRef>>,
+    role: Cell<Role>,
+    presence: RefCell<PresenceState>,
     seq: RefCell<Seq>,
     notify: Notifiers,
 }
@@ -31,6 +34,10 @@ struct Notifiers {
     workspace: Notify<()>,
     performance: Notify<Rc<mixlab_protocol::PerformanceInfo>>,
     media: Notify<Rc<mixlab_protocol::MediaLibrary>>,
+    scenes: Notify<Rc<mixlab_protocol::SceneLibrary>>,
+    presets: Notify<Rc<mixlab_protocol::PresetLibrary>>,
+    projects: Notify<Rc<mixlab_protocol::ProjectLibrary>>,
+    presence: Notify<()>,
 }
 
 pub type SessionRef = Rc<Session>;
@@ -40,6 +47,11 @@ impl Session {
         let session = Rc::new(Session {
             websocket: RefCell::new(None),
             state: RefCell::new(None),
+            role: Cell::new(Role::Operator),
+            presence: RefCell::new(PresenceState {
+                you: None,
+                peers: BTreeMap::new(),
+            }),
             seq: RefCell::new(Seq {
                 client: Sequence::new(),
                 server: None,
@@ -48,6 +60,10 @@ impl Session {
                 workspace: Notify::new(),
                 performance: Notify::new(),
                 media: Notify::new(),
+                scenes: Notify::new(),
+                presets: Notify::new(),
+                projects: Notify::new(),
+                presence: Notify::new(),
             },
         });
 
@@ -59,10 +75,12 @@ impl Session {
                 move |msg: Binary| {
                     match msg {
                         Ok(buff) => {
-                            let msg = bincode::deserialize::<ServerMessage>(&buff)
-                                .expect("bincode::deserialize");
-
-                            session.on_server_message(msg);
+                            match bincode::deserialize::<ServerMessage>(&buff) {
+                                Ok(msg) => session.on_server_message(msg),
+                                Err(e) => {
+                                    crate::log!("could not decode server message: {:?}", e);
+                                }
+                            }
                         }
                         Err(e) => {
                             crate::log!("websocket recv error: {:?}", e);
@@ -77,6 +95,17 @@ impl Session {
 
         *session.websocket.borrow_mut() = Some(websocket);
 
+        // MIDI mappings made in this browser are applied by the server and
+        // come back to the broker as updates like everyone else's:
+        midi::broker().set_mapping_sink(Callback::from({
+            let session = Rc::downgrade(&session);
+            move |(target, binding)| {
+                if let Some(session) = session.upgrade() {
+                    session.update_workspace(WorkspaceOp::SetMidiMapping(target, binding));
+                }
+            }
+        }));
+
         session
     }
 
@@ -109,8 +138,46 @@ impl Session {
 
     fn on_server_message(&self, msg: ServerMessage) {
         match msg {
+            ServerMessage::Hello(version) => {
+                if version != PROTOCOL_VERSION {
+                    // the server has been upgraded since this frontend was
+                    // loaded, reload to pick up the matching one:
+                    crate::log!("protocol version mismatch, reloading");
+                    util::reload_page();
+                    return;
+                }
+
+                self.send_message(ClientMessage::Hello(PROTOCOL_VERSION));
+            }
+            ServerMessage::Error(ProtocolError::VersionMismatch) => {
+                crate::log!("server rejected protocol version, reloading");
+                util::reload_page();
+            }
+            ServerMessage::Error(ProtocolError::InvalidMessage) => {
+                crate::log!("server could not decode a message we sent");
+            }
+            ServerMessage::Role(role) => {
+                self.role.set(role);
+
+                // Role is the first message after the handshake, so this is
+                // our chance to tell the server who we are:
+                if let Some(name) = util::local_storage_get(DISPLAY_NAME_KEY) {
+                    self.update_presence(PresenceOp::SetName(name));
+                }
+            }
             ServerMessage::WorkspaceState(state) => {
+                midi::broker().set_mappings(state.midi.clone());
+                *self.state.borrow_mut() = Some(Rc::new(RefCell::new(state.into())));
+                self.notify.workspace.broadcast(());
+            }
+            ServerMessage::Resync(state, seq) => {
+                // we fell behind the server and missed some updates, replace
+                // everything we know with a fresh snapshot. the snapshot may
+                // not reflect updates of ours still in flight, their Sync
+                // messages arrive as usual:
+                midi::broker().set_mappings(state.midi.clone());
                 *self.state.borrow_mut() = Some(Rc::new(RefCell::new(state.into())));
+                self.seq.borrow_mut().server = seq;
                 self.notify.workspace.broadcast(());
             }
             ServerMessage::Sync(seq) => {
@@ -123,6 +190,11 @@ impl Session {
                     self.notify.workspace.broadcast(());
                 }
             }
+            ServerMessage::Update(ServerUpdate::SetMidiMapping(target, binding)) => {
+                // MIDI targets listen to the broker rather than the workspace
+                // state, and don't wait for our own updates to round-trip:
+                midi::broker().set_mapping(target, binding);
+            }
             ServerMessage::Update(op) => {
                 {
                     let state = self.state.borrow().as_ref().cloned()
@@ -148,6 +220,13 @@ impl Session {
                                 *geometry = new_geometry;
                             }
                         }
+                        ServerUpdate::SetBypass(id, bypass) => {
+                            if bypass {
+                                state.bypass.insert(id);
+                            } else {
+                                state.bypass.remove(&id);
+                            }
+                        }
                         ServerUpdate::UpdateModuleIndication(id, new_indication) => {
                             if let Some(indication) = state.indications.get_mut(&id) {
                                 *indication = new_indication;
@@ -156,6 +235,7 @@ impl Session {
                         ServerUpdate::DeleteModule(id) => {
                             state.modules.remove(&id);
                             state.geometry.remove(&id);
+                            state.bypass.remove(&id);
                             state.indications.remove(&id);
                             state.inputs.remove(&id);
                             state.outputs.remove(&id);
@@ -166,6 +246,15 @@ impl Session {
                         ServerUpdate::DeleteConnection(input) => {
                             state.connections.remove(&input);
                         }
+                        ServerUpdate::UpdateMacro(id, params) => {
+                            state.macros.insert(id, params);
+                        }
+                        ServerUpdate::DeleteMacro(id) => {
+                            state.macros.remove(&id);
+                        }
+                        ServerUpdate::SetMidiMapping(..) => {
+                            // handled above
+                        }
                     }
                 }
 
@@ -183,6 +272,35 @@ impl Session {
                 crate::log!("Receiving media library!");
                 self.notify.media.broadcast(Rc::new(library));
             }
+            ServerMessage::ProjectLibrary(projects) => {
+                self.notify.projects.broadcast(Rc::new(projects));
+            }
+            ServerMessage::SceneLibrary(scenes) => {
+                self.notify.scenes.broadcast(Rc::new(scenes));
+            }
+            ServerMessage::PresetLibrary(presets) => {
+                self.notify.presets.broadcast(Rc::new(presets));
+            }
+            ServerMessage::Presence(update) => {
+                {
+                    let mut presence = self.presence.borrow_mut();
+
+                    match update {
+                        PresenceUpdate::Peers { you, peers } => {
+                            presence.you = Some(you);
+                            presence.peers = peers.into_iter().collect();
+                        }
+                        PresenceUpdate::Update(id, peer) => {
+                            presence.peers.insert(id, peer);
+                        }
+                        PresenceUpdate::Leave(id) => {
+                            presence.peers.remove(&id);
+                        }
+                    }
+                }
+
+                self.notify.presence.broadcast(());
+            }
         }
     }
 
@@ -194,7 +312,17 @@ impl Session {
         self.notify.workspace.subscribe(callback)
     }
 
+    pub fn role(&self) -> Role {
+        self.role.get()
+    }
+
     pub fn update_workspace(&self, op: WorkspaceOp) {
+        if self.role() == Role::Viewer {
+            // server rejects changes from viewers, don't send any so that
+            // our sequence numbers stay in sync with the server's:
+            return;
+        }
+
         let msg = ClientMessage::Workspace(WorkspaceMessage {
             sequence: ClientSequence(self.seq.borrow_mut().client.next()),
             op: op,
@@ -211,6 +339,66 @@ impl Session {
         self.notify.media.subscribe(callback)
     }
 
+    pub fn update_media(&self, op: MediaOp) {
+        if self.role() == Role::Viewer {
+            return;
+        }
+
+        self.send_message(ClientMessage::Media(op));
+    }
+
+    pub fn listen_scenes(&self, callback: Callback<Rc<mixlab_protocol::SceneLibrary>>) -> notify::Handle {
+        self.notify.scenes.subscribe(callback)
+    }
+
+    pub fn update_scenes(&self, op: SceneOp) {
+        if self.role() == Role::Viewer {
+            return;
+        }
+
+        self.send_message(ClientMessage::Scene(op));
+    }
+
+    pub fn listen_presets(&self, callback: Callback<Rc<mixlab_protocol::PresetLibrary>>) -> notify::Handle {
+        self.notify.presets.subscribe(callback)
+    }
+
+    pub fn update_presets(&self, op: PresetOp) {
+        if self.role() == Role::Viewer {
+            return;
+        }
+
+        self.send_message(ClientMessage::Preset(op));
+    }
+
+    pub fn listen_projects(&self, callback: Callback<Rc<mixlab_protocol::ProjectLibrary>>) -> notify::Handle {
+        self.notify.projects.subscribe(callback)
+    }
+
+    pub fn update_projects(&self, op: ProjectOp) {
+        if self.role() == Role::Viewer {
+            return;
+        }
+
+        self.send_message(ClientMessage::Project(op));
+    }
+
+    pub fn presence(&self) -> PresenceState {
+        self.presence.borrow().clone()
+    }
+
+    pub fn listen_presence(&self, callback: Callback<()>) -> notify::Handle {
+        self.notify.presence.subscribe(callback)
+    }
+
+    pub fn update_presence(&self, op: PresenceOp) {
+        if let PresenceOp::SetName(name) = &op {
+            util::local_storage_set(DISPLAY_NAME_KEY, name);
+        }
+
+        self.send_message(ClientMessage::Presence(op));
+    }
+
     fn send_message(&self, msg: ClientMessage) {
         let packet = bincode::serialize(&msg)
             .expect("bincode::serialize");
@@ -222,6 +410,31 @@ impl Session {
     }
 }
 
+const DISPLAY_NAME_KEY: &str = "mixlab-display-name";
+
+#[derive(Debug, Clone)]
+pub struct PresenceState {
+    pub you: Option<PeerId>,
+    pub peers: BTreeMap<PeerId, Peer>,
+}
+
+impl PresenceState {
+    /// All connected peers other than ourselves
+    pub fn others(&self) -> impl Iterator<Item = (PeerId, &Peer)> {
+        let you = self.you;
+
+        self.peers.iter()
+            .filter(move |(id, _)| Some(**id) != you)
+            .map(|(id, peer)| (*id, peer))
+    }
+}
+
+/// Stable colour for a peer, used for their cursor and selections
+pub fn peer_color(id: PeerId) -> &'static str {
+    const COLORS: &[&str] = &["#e0607e", "#5fb48f", "#e09a4f", "#5f8fd4", "#b26fd1", "#c9b540"];
+    COLORS[id.0.get() % COLORS.len()]
+}
+
 pub type WorkspaceStateRef = Rc<RefCell<WorkspaceState>>;
 
 #[derive(Debug, Clone)]
@@ -229,10 +442,21 @@ pub struct WorkspaceState {
     // modules uses BTreeMap for consistent iteration order:
     pub modules: BTreeMap<ModuleId, ModuleParams>,
     pub geometry: HashMap<ModuleId, WindowGeometry>,
+    pub bypass: HashSet<ModuleId>,
     pub connections: HashMap<InputId, OutputId>,
     pub indications: HashMap<ModuleId, Indication>,
     pub inputs: HashMap<ModuleId, Vec<Terminal>>,
     pub outputs: HashMap<ModuleId, Vec<Terminal>>,
+    pub macros: BTreeMap<MacroId, MacroParams>,
+}
+
+impl WorkspaceState {
+    /// Short human readable label for a module, eg. "Mixer #3"
+    pub fn module_name(&self, module_id: ModuleId) -> String {
+        let name = self.modules.get(&module_id).map(|module| module.kind());
+
+        format!("{} #{}", name.unwrap_or("-"), module_id.0)
+    }
 }
 
 impl From<mixlab_protocol::WorkspaceState> for WorkspaceState {
@@ -240,10 +464,12 @@ impl From<mixlab_protocol::WorkspaceState> for WorkspaceState {
         WorkspaceState {
             modules: wstate.modules.into_iter().collect(),
             geometry: wstate.geometry.into_iter().collect(),
+            bypass: wstate.bypass.into_iter().collect(),
             indications: wstate.indications.into_iter().collect(),
             connections: wstate.connections.into_iter().collect(),
             inputs: wstate.inputs.into_iter().collect(),
             outputs: wstate.outputs.into_iter().collect(),
+            macros: wstate.macros.into_iter().collect(),
         }
     }
 }
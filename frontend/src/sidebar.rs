@@ -4,6 +4,7 @@ use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties};
 
 use mixlab_protocol::{PerformanceInfo, PerformanceAccount, TemporalWarningStatus, ModuleId};
 
+use crate::presence::PeerList;
 use crate::session::{SessionRef, WorkspaceStateRef};
 use crate::util::notify;
 
@@ -56,6 +57,7 @@ impl Component for Sidebar {
             <div class="sidebar">
                 <div class="sidebar-title">{"Mixlab"}</div>
                 {self.view_perf_info()}
+                <PeerList session={self.props.session.clone()} />
             </div>
         }
     }
@@ -65,10 +67,10 @@ impl Sidebar {
     fn module_name(&self, module_id: ModuleId) -> String {
         let workspace = self.props.workspace.borrow();
 
-        workspace.modules.get(&module_id).map(|module| {
-            format!("{:?}", module).chars()
-                .take_while(|c| c.is_alphanumeric()).collect::<String>()
-        }).unwrap_or("-".to_owned())
+        workspace.modules.get(&module_id)
+            .map(|module| module.kind())
+            .unwrap_or("-")
+            .to_owned()
     }
 
     fn view_perf_info(&self) -> Html {
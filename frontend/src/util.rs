@@ -76,6 +76,22 @@ pub fn websocket_origin() -> String {
     format!("{}//{}", proto, host)
 }
 
+pub fn reload_page() {
+    let _ = web_sys::window().unwrap().location().reload();
+}
+
+pub fn local_storage_get(key: &str) -> Option<String> {
+    web_sys::window()?.local_storage().ok()??.get_item(key).ok()?
+}
+
+pub fn local_storage_set(key: &str, value: &str) {
+    let storage = web_sys::window().and_then(|window| window.local_storage().ok().flatten());
+
+    if let Some(storage) = storage {
+        let _ = storage.set_item(key, value);
+    }
+}
+
 fn html_element_parent(mut element: Element) -> Option<HtmlElement> {
     loop {
         match element.dyn_ref::<HtmlElement>() {
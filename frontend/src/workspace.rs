@@ -5,7 +5,7 @@ use wasm_bindgen::JsCast;
 use web_sys::{CanvasRenderingContext2d, HtmlElement, HtmlCanvasElement, MouseEvent, Element};
 use yew::{html, Callback, Component, ComponentLink, Html, ShouldRender, Properties, NodeRef};
 
-use mixlab_protocol::{ModuleId, TerminalId, InputId, OutputId, ModuleParams, OscillatorParams, Waveform, WorkspaceOp, WindowGeometry, Coords, Indication, OutputDeviceParams, FmSineParams, AmplifierParams, GateState, LineType, EnvelopeParams, MixerParams, StreamInputParams, EqThreeParams, StreamOutputParams, VideoMixerParams, MediaSourceParams};
+use mixlab_protocol::{PresenceOp, ModuleId, TerminalId, InputId, OutputId, ModuleParams, OscillatorParams, Waveform, WorkspaceOp, WindowGeometry, Coords, Indication, OutputDeviceParams, FmSineParams, AmplifierParams, GateState, LineType, EnvelopeParams, MixerParams, StreamInputParams, EqThreeParams, StreamOutputParams, VideoMixerParams, MediaSourceParams};
 
 use crate::component::midi_target::MidiUiMode;
 use crate::module::amplifier::Amplifier;
@@ -22,8 +22,10 @@ use crate::module::stream_input::StreamInput;
 use crate::module::stream_output::StreamOutput;
 use crate::module::trigger::Trigger;
 use crate::module::video_mixer::VideoMixer;
-use crate::util::{self, stop_propagation, prevent_default, Sequence};
-use crate::session::{WorkspaceStateRef, WorkspaceState, SessionRef};
+use crate::presence::PeerCursors;
+use crate::presets::PresetMenu;
+use crate::util::{self, notify, stop_propagation, prevent_default, Sequence};
+use crate::session::{self, WorkspaceStateRef, WorkspaceState, SessionRef};
 use crate::{App, AppMsg};
 
 pub struct Workspace {
@@ -33,8 +35,20 @@ pub struct Workspace {
     gen_z_index: Sequence,
     mouse: MouseMode,
     window_refs: BTreeMap<ModuleId, WindowRef>,
+    peer_selections: BTreeMap<ModuleId, PeerSelection>,
+    last_pointer_update: f64,
+    _presence_notify: notify::Handle,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerSelection {
+    names: String,
+    color: &'static str,
+}
+
+// minimum interval between pointer position updates sent to the server:
+const POINTER_UPDATE_INTERVAL_MS: f64 = 50.0;
+
 #[derive(Properties, Clone)]
 pub struct WorkspaceProps {
     pub app: ComponentLink<App>,
@@ -57,13 +71,16 @@ pub struct Drag {
 #[derive(Debug)]
 pub enum WorkspaceMsg {
     Rerender,
+    PresenceChanged,
     DragStart(ModuleId, MouseEvent),
     MouseDown(MouseEvent),
     MouseUp(MouseEvent),
     MouseMove(MouseEvent),
+    MouseLeave,
     SelectTerminal(TerminalId, TerminalRef),
     ClearTerminal(TerminalId),
     DeleteWindow(ModuleId),
+    SetBypass(ModuleId, bool),
     UpdateModuleParams(ModuleId, ModuleParams),
     CreateModule(ModuleParams, Coords),
 }
@@ -73,6 +90,9 @@ impl Component for Workspace {
     type Properties = WorkspaceProps;
 
     fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let presence_notify = props.session.listen_presence(
+            link.callback(|()| WorkspaceMsg::PresenceChanged));
+
         let mut workspace = Workspace {
             link,
             props,
@@ -80,9 +100,13 @@ impl Component for Workspace {
             gen_z_index: Sequence::new(),
             mouse: MouseMode::Normal,
             window_refs: BTreeMap::new(),
+            peer_selections: BTreeMap::new(),
+            last_pointer_update: 0.0,
+            _presence_notify: presence_notify,
         };
 
         workspace.update_state();
+        workspace.peer_selections = workspace.peer_selections();
 
         workspace
     }
@@ -98,7 +122,22 @@ impl Component for Workspace {
             WorkspaceMsg::Rerender => {
                 true
             }
+            WorkspaceMsg::PresenceChanged => {
+                // most presence changes are pointer movements, which are
+                // rendered by PeerCursors. only re-render the workspace if
+                // selections have changed:
+                let peer_selections = self.peer_selections();
+
+                if peer_selections != self.peer_selections {
+                    self.peer_selections = peer_selections;
+                    true
+                } else {
+                    false
+                }
+            }
             WorkspaceMsg::DragStart(module, ev) => {
+                self.props.session.update_presence(PresenceOp::Select(Some(module)));
+
                 let mut state = self.props.state.borrow_mut();
 
                 if let Some(geom) = state.geometry.get_mut(&module) {
@@ -132,7 +171,12 @@ impl Component for Workspace {
                     true
                 } else {
                     match self.mouse {
-                        MouseMode::Normal | MouseMode::Drag(_) => {
+                        MouseMode::Normal => {
+                            // clicking on the workspace background deselects:
+                            self.props.session.update_presence(PresenceOp::Select(None));
+                            false
+                        }
+                        MouseMode::Drag(_) => {
                             false
                         }
                         MouseMode::Connect(..) | MouseMode::ContextMenu(_) => {
@@ -165,23 +209,31 @@ impl Component for Workspace {
                 }
             }
             WorkspaceMsg::MouseMove(ev) => {
+                let workspace_coords = self.workspace_coords(&ev);
+                let now = js_sys::Date::now();
+
+                if now - self.last_pointer_update >= POINTER_UPDATE_INTERVAL_MS {
+                    if let Some(coords) = workspace_coords {
+                        self.last_pointer_update = now;
+                        self.props.session.update_presence(PresenceOp::Pointer(Some(coords)));
+                    }
+                }
+
                 match &mut self.mouse {
                     MouseMode::Normal | MouseMode::ContextMenu(_) => false,
                     MouseMode::Drag(ref mut drag) => {
                         drag_event(&mut self.props.state.borrow_mut(), &self.window_refs, drag, ev)
                     }
                     MouseMode::Connect(_, _, ref mut coords) => {
-                        let workspace = self.workspace_ref.cast::<HtmlElement>().unwrap();
-                        let target = ev.target().and_then(|target| target.dyn_into::<Element>().ok()).unwrap();
-                        let target_offset_coords = util::offset_coords_in(workspace, target).expect("offset_coords_in");
-                        *coords = Some(target_offset_coords.add(Coords {
-                            x: ev.offset_x(),
-                            y: ev.offset_y(),
-                        }));
+                        *coords = Some(workspace_coords.expect("offset_coords_in"));
                         true
                     }
                 }
             }
+            WorkspaceMsg::MouseLeave => {
+                self.props.session.update_presence(PresenceOp::Pointer(None));
+                false
+            }
             WorkspaceMsg::SelectTerminal(terminal_id, terminal_ref) => {
                 match &self.mouse {
                     MouseMode::Normal | MouseMode::ContextMenu(_) => {
@@ -265,6 +317,21 @@ impl Component for Workspace {
 
                 true
             }
+            WorkspaceMsg::SetBypass(module, bypass) => {
+                let mut state = self.props.state.borrow_mut();
+
+                if bypass {
+                    state.bypass.insert(module);
+                } else {
+                    state.bypass.remove(&module);
+                }
+
+                self.props.app.send_message(
+                    AppMsg::ClientUpdate(
+                        WorkspaceOp::SetBypass(module, bypass)));
+
+                true
+            }
             WorkspaceMsg::UpdateModuleParams(module, params) => {
                 let mut state = self.props.state.borrow_mut();
 
@@ -353,6 +420,7 @@ impl Component for Workspace {
             <div class="workspace"
                 ref={self.workspace_ref.clone()}
                 onmousemove={self.link.callback(WorkspaceMsg::MouseMove)}
+                onmouseleave={self.link.callback(|_| WorkspaceMsg::MouseLeave)}
                 oncontextmenu={prevent_default()}
             >
                 <div class="workspace-event-target"
@@ -366,9 +434,11 @@ impl Component for Workspace {
                     let geometry = state.geometry.get(id);
                     let workspace = self.link.clone();
                     let indication = state.indications.get(id);
+                    let bypass = state.bypass.contains(id);
+                    let selected_by = self.peer_selections.get(id).cloned();
 
                     if let (Some(module), Some(geometry)) = (module, geometry) {
-                        let name = format!("{:?}", module).chars().take_while(|c| c.is_alphanumeric()).collect::<String>();
+                        let name = module.kind().to_owned();
                         html! { <Window
                             id={id}
                             module={module}
@@ -376,6 +446,8 @@ impl Component for Workspace {
                             name={name}
                             workspace={workspace}
                             geometry={geometry}
+                            bypass={bypass}
+                            selected_by={selected_by}
                             indication={indication.cloned()}
                             session={self.props.session.clone()}
                         /> }
@@ -386,6 +458,8 @@ impl Component for Workspace {
 
                 <Connections connections={connections} />
 
+                <PeerCursors session={self.props.session.clone()} />
+
                 {self.view_context_menu()}
             </div>
         }
@@ -443,6 +517,39 @@ impl Workspace {
         }
     }
 
+    // position of a mouse event relative to the workspace's content
+    fn workspace_coords(&self, ev: &MouseEvent) -> Option<Coords> {
+        let workspace = self.workspace_ref.cast::<HtmlElement>()?;
+        let target = ev.target().and_then(|target| target.dyn_into::<Element>().ok())?;
+        let target_offset_coords = util::offset_coords_in(workspace, target)?;
+
+        Some(target_offset_coords.add(Coords {
+            x: ev.offset_x(),
+            y: ev.offset_y(),
+        }))
+    }
+
+    fn peer_selections(&self) -> BTreeMap<ModuleId, PeerSelection> {
+        let presence = self.props.session.presence();
+        let mut selections = BTreeMap::<ModuleId, PeerSelection>::new();
+
+        for (peer_id, peer) in presence.others() {
+            if let Some(module_id) = peer.selected {
+                selections.entry(module_id)
+                    .and_modify(|selection| {
+                        selection.names.push_str(", ");
+                        selection.names.push_str(&peer.name);
+                    })
+                    .or_insert_with(|| PeerSelection {
+                        names: peer.name.clone(),
+                        color: session::peer_color(peer_id),
+                    });
+            }
+        }
+
+        selections
+    }
+
     fn screen_coords_for_terminal(&self, terminal_id: TerminalId) -> Option<Coords> {
         let state = self.props.state.borrow();
         let geometry = state.geometry.get(&terminal_id.module_id())?;
@@ -513,14 +620,17 @@ pub struct Window {
     link: ComponentLink<Self>,
     props: WindowProps,
     midi_mode: MidiUiMode,
+    presets_open: bool,
 }
 
 pub enum WindowMsg {
     DragStart(MouseEvent),
     TerminalMouseDown(MouseEvent, TerminalId, TerminalRef),
     Delete,
+    SetBypass(bool),
     UpdateParams(ModuleParams),
     SetMidiMode(MidiUiMode),
+    TogglePresets,
 }
 
 #[derive(Properties, Clone, Debug)]
@@ -528,6 +638,8 @@ pub struct WindowProps {
     pub id: ModuleId,
     pub module: ModuleParams,
     pub geometry: WindowGeometry,
+    pub bypass: bool,
+    pub selected_by: Option<PeerSelection>,
     pub name: String,
     pub workspace: ComponentLink<Workspace>,
     pub refs: WindowRef,
@@ -574,6 +686,7 @@ impl Component for Window {
             link,
             props,
             midi_mode: MidiUiMode::Normal,
+            presets_open: false,
         }
     }
 
@@ -604,6 +717,12 @@ impl Component for Window {
 
                 false
             }
+            WindowMsg::SetBypass(bypass) => {
+                self.props.workspace.send_message(
+                    WorkspaceMsg::SetBypass(self.props.id, bypass));
+
+                false
+            }
             WindowMsg::UpdateParams(params) => {
                 self.props.workspace.send_message(
                     WorkspaceMsg::UpdateModuleParams(self.props.id, params));
@@ -614,6 +733,10 @@ impl Component for Window {
                 self.midi_mode = new_midi_mode;
                 true
             }
+            WindowMsg::TogglePresets => {
+                self.presets_open = !self.presets_open;
+                true
+            }
         }
     }
 
@@ -623,18 +746,30 @@ impl Component for Window {
     }
 
     fn view(&self) -> Html {
-        let window_style = format!("left:{}px; top:{}px; z-index:{};",
+        let mut window_style = format!("left:{}px; top:{}px; z-index:{};",
             self.props.geometry.position.x,
             self.props.geometry.position.y,
             self.props.geometry.z_index);
 
+        let mut window_class = if self.props.bypass {
+            "module-window module-window-bypassed".to_owned()
+        } else {
+            "module-window".to_owned()
+        };
+
+        if let Some(selection) = &self.props.selected_by {
+            window_class.push_str(" module-window-peer-selected");
+            window_style.push_str(&format!(" outline-color:{};", selection.color));
+        }
+
         html! {
-            <div class="module-window"
+            <div class={window_class}
                 style={window_style}
                 ref={self.props.refs.module.clone()}
                 onmousedown={stop_propagation()}
                 oncontextmenu={stop_propagation()}
             >
+                {self.view_peer_selection()}
                 <div class="module-window-title"
                     onmousedown={self.link.callback(WindowMsg::DragStart)}
                     onmouseup={self.props.workspace.callback(WorkspaceMsg::MouseUp)}
@@ -643,10 +778,13 @@ impl Component for Window {
                         {&self.props.name}
                     </div>
                     {self.view_custom_title_buttons()}
+                    {self.view_presets_button()}
+                    {self.view_bypass_button()}
                     <div class="module-window-title-button module-window-title-delete" onmousedown={self.link.callback(|_| WindowMsg::Delete)}>
                         {"×"}
                     </div>
                 </div>
+                {self.view_preset_menu()}
                 <div class="module-window-content">
                     <div class="module-window-inputs">
                         {self.view_inputs()}
@@ -689,6 +827,63 @@ impl Window {
             _ => html! {},
         }
     }
+
+    fn view_peer_selection(&self) -> Html {
+        match &self.props.selected_by {
+            Some(selection) => html! {
+                <div class="module-window-peer-label" style={format!("background-color:{};", selection.color)}>
+                    {&selection.names}
+                </div>
+            },
+            None => html! {},
+        }
+    }
+
+    fn view_presets_button(&self) -> Html {
+        let class = if self.presets_open {
+            "module-window-title-button module-window-title-presets-btn module-window-title-presets-btn-active"
+        } else {
+            "module-window-title-button module-window-title-presets-btn"
+        };
+
+        html! {
+            <div class={class} onmousedown={self.link.callback(|_| WindowMsg::TogglePresets)}>
+                {"PRE"}
+            </div>
+        }
+    }
+
+    fn view_preset_menu(&self) -> Html {
+        if !self.presets_open {
+            return html! {};
+        }
+
+        html! {
+            <PresetMenu
+                session={self.props.session.clone()}
+                module={self.props.id}
+                kind={self.props.module.kind().to_owned()}
+                onclose={self.link.callback(|()| WindowMsg::TogglePresets)}
+            />
+        }
+    }
+
+    fn view_bypass_button(&self) -> Html {
+        let class = if self.props.bypass {
+            "module-window-title-button module-window-title-bypass-btn module-window-title-bypass-btn-active"
+        } else {
+            "module-window-title-button module-window-title-bypass-btn"
+        };
+
+        let new_bypass = !self.props.bypass;
+
+        html! {
+            <div class={class} onmousedown={self.link.callback(move |_| WindowMsg::SetBypass(new_bypass))}>
+                {"BYP"}
+            </div>
+        }
+    }
+
     fn view_inputs(&self) -> Html {
         self.view_terminals(
             self.props.refs.inputs.iter()
@@ -847,7 +1042,10 @@ impl Component for Terminal {
                         },
                         LineType::Video => html! {
                             <rect width="16" height="16" fill={ if self.hover { "#fef8e1" } else { "#fdf1bf" } } />
-                        }
+                        },
+                        LineType::Event => html! {
+                            <circle cx="8" cy="8" r="5" fill={ if self.hover { "#c5e3f6" } else { "#a9d3ef" } } />
+                        },
                     } }
                 </svg>
             </div>
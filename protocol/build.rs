@@ -0,0 +1,26 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// The protocol version is a hash of the protocol source. Server and frontend
+// compare versions when a session starts, so any change to the protocol is
+// picked up without anyone having to remember to bump a number.
+fn main() {
+    let source = fs::read("src/lib.rs").expect("read protocol source");
+
+    // FNV-1a, stable across platforms and compiler versions unlike the hasher
+    // in std, which matters because the frontend is built for a different
+    // target than the server:
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in source {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR");
+    let out_path = Path::new(&out_dir).join("protocol_version.rs");
+    fs::write(out_path, format!("{}", hash)).expect("write protocol version");
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+}
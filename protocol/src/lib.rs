@@ -12,21 +12,58 @@ pub type Sample = f32;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ServerMessage<'a> {
+    // Hello and Error must remain the first variants so that clients built
+    // against any version of the protocol can decode them:
+    Hello(ProtocolVersion),
+    Error(ProtocolError),
+    Role(Role),
     WorkspaceState(WorkspaceState),
     Update(ServerUpdate),
     Sync(ClientSequence),
     Performance(Cow<'a, PerformanceInfo>),
     MediaLibrary(MediaLibrary),
+    SceneLibrary(SceneLibrary),
+    Presence(PresenceUpdate),
+    // sent in place of updates the session fell too far behind on, along
+    // with the last client sequence the snapshot reflects:
+    Resync(WorkspaceState, Option<ClientSequence>),
+    ProjectLibrary(ProjectLibrary),
+    PresetLibrary(PresetLibrary),
+}
+
+pub const PROTOCOL_VERSION: ProtocolVersion =
+    ProtocolVersion(include!(concat!(env!("OUT_DIR"), "/protocol_version.rs")));
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolVersion(pub u64);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    // the client was built against a different protocol version, most likely
+    // a stale cached frontend, and must reload:
+    VersionMismatch,
+    // the server could not decode a message sent by the client:
+    InvalidMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Operator,
+    // viewers receive all updates but may not change the workspace:
+    Viewer,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WorkspaceState {
     pub modules: Vec<(ModuleId, ModuleParams)>,
     pub geometry: Vec<(ModuleId, WindowGeometry)>,
+    pub bypass: Vec<ModuleId>,
     pub indications: Vec<(ModuleId, Indication)>,
     pub connections: Vec<(InputId, OutputId)>,
     pub inputs: Vec<(ModuleId, Vec<Terminal>)>,
     pub outputs: Vec<(ModuleId, Vec<Terminal>)>,
+    pub macros: Vec<(MacroId, MacroParams)>,
+    pub midi: Vec<(MidiTarget, MidiBinding)>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -55,7 +92,7 @@ pub struct PerformanceMetric {
     pub last: Microseconds,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Default)]
 pub struct Microseconds(pub u64);
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -63,7 +100,7 @@ pub struct MediaLibrary {
     pub items: Vec<MediaItem>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MediaId(pub i64);
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -72,11 +109,160 @@ pub struct MediaItem {
     pub name: String,
     pub kind: String,
     pub size: usize,
+    // None for media uploaded before metadata was recorded:
+    pub metadata: Option<MediaMetadata>,
+    // whether previews are available from /_media/<id>/thumbnail and
+    // /_media/<id>/waveform.svg, they are generated after upload:
+    pub thumbnail: bool,
+    pub waveform: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MediaMetadata {
+    pub format: String,
+    pub duration: Option<Microseconds>,
+    pub streams: Vec<MediaStreamInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MediaStreamInfo {
+    pub index: usize,
+    pub codec: Option<String>,
+    pub kind: MediaStreamKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum MediaStreamKind {
+    Video { width: u32, height: u32, frame_rate: Option<f64> },
+    Audio { sample_rate: u32, channels: u32 },
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SceneLibrary {
+    pub scenes: Vec<SceneItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SceneId(pub i64);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SceneItem {
+    pub id: SceneId,
+    pub name: String,
+    pub modules: Vec<ModuleId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PresetLibrary {
+    pub presets: Vec<PresetItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresetId(pub i64);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum PresetRef {
+    // bundled with mixlab, named uniquely within their module kind:
+    Factory(String),
+    Saved(PresetId),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PresetItem {
+    pub preset: PresetRef,
+    // module kind the preset applies to, see ModuleParams::kind:
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProjectLibrary {
+    pub projects: Vec<String>,
+    pub active: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientMessage {
+    // must remain the first variant, see ServerMessage::Hello:
+    Hello(ProtocolVersion),
     Workspace(WorkspaceMessage),
+    Scene(SceneOp),
+    Presence(PresenceOp),
+    Project(ProjectOp),
+    Media(MediaOp),
+    Preset(PresetOp),
+}
+
+// switching project moves every connected session over to it:
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ProjectOp {
+    Open(String),
+    Create(String),
+    // copies the given modules of another project into the open one:
+    ImportModules(String, Vec<ModuleId>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct PeerId(pub NonZeroUsize);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Peer {
+    pub name: String,
+    pub role: Role,
+    pub selected: Option<ModuleId>,
+    // position in workspace coordinates, None when not over the workspace:
+    pub pointer: Option<Coords>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum PresenceUpdate {
+    // sent on connect and whenever a session falls behind, replaces all
+    // previously known peers:
+    Peers { you: PeerId, peers: Vec<(PeerId, Peer)> },
+    Update(PeerId, Peer),
+    Leave(PeerId),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum PresenceOp {
+    SetName(String),
+    Select(Option<ModuleId>),
+    Pointer(Option<Coords>),
+}
+
+// media in use by a module cannot be deleted, collecting garbage removes
+// storage left behind by deleted media and interrupted uploads:
+#[derive(Serialize, Deserialize, Debug)]
+pub enum MediaOp {
+    Rename(MediaId, String),
+    Delete(MediaId),
+    CollectGarbage,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SceneOp {
+    Save { name: String, modules: Vec<ModuleId> },
+    Delete(SceneId),
+    Recall(SceneId, SceneRecall),
+}
+
+// presets apply to modules of the kind they were saved from, see
+// ModuleParams::kind:
+#[derive(Serialize, Deserialize, Debug)]
+pub enum PresetOp {
+    Save { module: ModuleId, name: String },
+    Delete(PresetId),
+    Apply(ModuleId, PresetRef),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct SceneRecall {
+    // time to wait before starting the recall:
+    pub delay: Microseconds,
+    // time over which numeric params morph towards the scene, zero for an
+    // instant recall:
+    pub morph: Microseconds,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -93,9 +279,15 @@ pub enum WorkspaceOp {
     CreateModule(ModuleParams, WindowGeometry),
     UpdateModuleParams(ModuleId, ModuleParams),
     UpdateWindowGeometry(ModuleId, WindowGeometry),
+    SetBypass(ModuleId, bool),
     DeleteModule(ModuleId),
     CreateConnection(InputId, OutputId),
     DeleteConnection(InputId),
+    CreateMacro(MacroParams),
+    UpdateMacro(MacroId, MacroParams),
+    SetMacroValue(MacroId, f64),
+    DeleteMacro(MacroId),
+    SetMidiMapping(MidiTarget, Option<MidiBinding>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -111,14 +303,93 @@ pub enum ServerUpdate {
     UpdateModuleParams(ModuleId, ModuleParams),
     UpdateWindowGeometry(ModuleId, WindowGeometry),
     UpdateModuleIndication(ModuleId, Indication),
+    SetBypass(ModuleId, bool),
     DeleteModule(ModuleId),
     CreateConnection(InputId, OutputId),
     DeleteConnection(InputId),
+    UpdateMacro(MacroId, MacroParams),
+    DeleteMacro(MacroId),
+    SetMidiMapping(MidiTarget, Option<MidiBinding>),
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct ModuleId(pub NonZeroUsize);
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct MacroId(pub NonZeroUsize);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MacroParams {
+    pub name: String,
+    // normalised control value, 0.0 to 1.0:
+    pub value: f64,
+    pub targets: Vec<MacroTarget>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MacroTarget {
+    pub module: ModuleId,
+    // JSON pointer to a numeric field within the module's params, eg.
+    // "/fader" for VideoMixer or "/channels/0/fader" for Mixer:
+    pub field: String,
+    pub min: f64,
+    pub max: f64,
+    pub invert: bool,
+    pub curve: MacroCurve,
+}
+
+// MIDI controls are bound to controls in the UI, each of which acts on its
+// target as it would when operated directly:
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MidiTarget {
+    // JSON pointer to a field within the module's params, as in MacroTarget:
+    ModuleParam(ModuleId, String),
+    Macro(MacroId),
+    Scene(SceneId),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MidiBinding {
+    // input name as reported by the browser, which unlike the input id is
+    // the same across browsers and machines:
+    pub device: String,
+    pub control: MidiControl,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MidiControl {
+    Cc(u8),
+    Note(u8),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroCurve {
+    Linear,
+    Exponential,
+}
+
+impl MacroTarget {
+    /// Maps a normalised macro value onto this target's range
+    pub fn map(&self, value: f64) -> f64 {
+        const EXP_SHAPE: f64 = 4.0;
+
+        let value = value.max(0.0).min(1.0);
+
+        let value = if self.invert {
+            1.0 - value
+        } else {
+            value
+        };
+
+        let value = match self.curve {
+            MacroCurve::Linear => value,
+            MacroCurve::Exponential => (f64::exp(EXP_SHAPE * value) - 1.0) / (f64::exp(EXP_SHAPE) - 1.0),
+        };
+
+        self.min + (self.max - self.min) * value
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub enum TerminalId {
     Input(InputId),
@@ -178,6 +449,7 @@ pub enum LineType {
     Mono,
     Stereo,
     Video,
+    Event,
 }
 
 impl LineType {
@@ -210,6 +482,30 @@ pub enum ModuleParams {
     VideoMixer(VideoMixerParams),
 }
 
+impl ModuleParams {
+    /// Name of the module type, as shown in window titles
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ModuleParams::Amplifier(_) => "Amplifier",
+            ModuleParams::Envelope(_) => "Envelope",
+            ModuleParams::EqThree(_) => "EqThree",
+            ModuleParams::FmSine(_) => "FmSine",
+            ModuleParams::MediaSource(_) => "MediaSource",
+            ModuleParams::Mixer(_) => "Mixer",
+            ModuleParams::Monitor(_) => "Monitor",
+            ModuleParams::Oscillator(_) => "Oscillator",
+            ModuleParams::OutputDevice(_) => "OutputDevice",
+            ModuleParams::Plotter(_) => "Plotter",
+            ModuleParams::StereoPanner(_) => "StereoPanner",
+            ModuleParams::StereoSplitter(_) => "StereoSplitter",
+            ModuleParams::StreamInput(_) => "StreamInput",
+            ModuleParams::StreamOutput(_) => "StreamOutput",
+            ModuleParams::Trigger(_) => "Trigger",
+            ModuleParams::VideoMixer(_) => "VideoMixer",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Indication {
     Amplifier(()),
@@ -2,4 +2,10 @@ pub static MIGRATIONS: &'static [(i64, &'static str)] = &[
     (0, include_str!("migrations/0_init.sql")),
     (20200804, include_str!("migrations/20200804_create_media_tables.sql")),
     (20200805, include_str!("migrations/20200805_create_workspace_table.sql")),
+    (20200806, include_str!("migrations/20200806_create_scenes_table.sql")),
+    (20200807, include_str!("migrations/20200807_create_media_metadata_tables.sql")),
+    (20200808, include_str!("migrations/20200808_create_media_previews_table.sql")),
+    (20200809, include_str!("migrations/20200809_create_uploads_table.sql")),
+    (20200810, include_str!("migrations/20200810_add_stream_storage.sql")),
+    (20200811, include_str!("migrations/20200811_create_presets_table.sql")),
 ];
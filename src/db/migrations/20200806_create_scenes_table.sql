@@ -0,0 +1,5 @@
+CREATE TABLE scenes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    serialized TEXT NOT NULL
+);
@@ -0,0 +1,22 @@
+CREATE TABLE media_metadata (
+    media_id INTEGER PRIMARY KEY NOT NULL,
+    format TEXT NOT NULL,
+    duration_us INTEGER,
+    FOREIGN KEY (media_id) REFERENCES media (id)
+);
+
+CREATE TABLE media_streams (
+    media_id INTEGER NOT NULL,
+    stream_index INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    codec TEXT,
+    width INTEGER,
+    height INTEGER,
+    frame_rate REAL,
+    sample_rate INTEGER,
+    channels INTEGER,
+    FOREIGN KEY (media_id) REFERENCES media (id),
+    CONSTRAINT known_kind CHECK (kind IN ('video', 'audio', 'other'))
+);
+
+CREATE UNIQUE INDEX media_stream_idx ON media_streams (media_id, stream_index);
@@ -0,0 +1,12 @@
+ALTER TABLE streams ADD COLUMN hash TEXT;
+
+CREATE INDEX stream_hash_idx ON streams (hash);
+
+CREATE TABLE uploads (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    stream_id INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    FOREIGN KEY (stream_id) REFERENCES streams (id)
+);
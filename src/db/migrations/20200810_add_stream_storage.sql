@@ -0,0 +1 @@
+ALTER TABLE streams ADD COLUMN storage TEXT NOT NULL DEFAULT 'blobs';
@@ -0,0 +1,8 @@
+CREATE TABLE presets (
+    id INTEGER PRIMARY KEY NOT NULL,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    serialized TEXT NOT NULL
+);
+
+CREATE INDEX presets_kind_idx ON presets (kind);
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use rusqlite::{self, Connection, Row};
+use rusqlite::{self, Connection, OpenFlags, Row};
 use tokio::task;
 
 mod migrations;
@@ -62,3 +62,23 @@ pub async fn attach(path: PathBuf) -> Result<Connection, rusqlite::Error> {
     task::spawn_blocking(|| attach_blocking(path)).await
         .expect("join blocking task")
 }
+
+fn open_read_only_blocking(path: PathBuf) -> Result<Option<Connection>, rusqlite::Error> {
+    let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let latest = migrations::MIGRATIONS.iter().map(|(ver, _)| *ver).max();
+
+    if schema_version(&conn)? == latest {
+        Ok(Some(conn))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Opens an existing database without migrating it, for reading from other
+/// projects. Returns None if the database needs migrating first, which
+/// happens when the project is next opened
+pub async fn open_read_only(path: PathBuf) -> Result<Option<Connection>, rusqlite::Error> {
+    task::spawn_blocking(|| open_read_only_blocking(path)).await
+        .expect("join blocking task")
+}
@@ -12,20 +12,27 @@ use futures::stream::{Stream, StreamExt};
 use tokio::runtime;
 use tokio::sync::{oneshot, broadcast, watch};
 
-use mixlab_protocol::{ModuleId, InputId, OutputId, WorkspaceState, ServerUpdate, Indication, ClientSequence, WorkspaceMessage, WorkspaceOp, PerformanceInfo};
+use mixlab_protocol::{MediaId, MediaSourceParams, ModuleId, ModuleParams, MacroId, MacroParams, MidiTarget, InputId, OutputId, Terminal, WorkspaceState, ServerUpdate, Indication, ClientSequence, WorkspaceMessage, WorkspaceOp, PerformanceInfo};
 
+use crate::persist;
 use crate::project::ProjectBaseRef;
 use crate::util::Sequence;
 
 mod io;
+mod macro_control;
 mod module;
+mod morph;
 mod timing;
 mod workspace;
 
+#[cfg(test)]
+pub mod harness;
+
+use morph::Morph;
 use timing::{EngineStat, TickStat};
 use workspace::SyncWorkspace;
 
-pub use io::{InputRef, OutputRef, Output, VideoFrame};
+pub use io::{InputRef, OutputRef, Output, VideoFrame, Event, EventKind};
 pub use module::{ModuleCtx, DynModuleHost};
 pub use workspace::WorkspaceEmbryo;
 
@@ -54,9 +61,26 @@ pub const SAMPLE_RATE: usize = 44100;
 pub const TICKS_PER_SECOND: usize = 60;
 pub const SAMPLES_PER_TICK: usize = SAMPLE_RATE / TICKS_PER_SECOND;
 
+// morphs in progress send out params to clients every this many ticks:
+const MORPH_UPDATE_TICKS: u64 = 4;
+
 pub enum EngineMessage {
     ConnectSession(oneshot::Sender<(SessionId, WorkspaceState, EngineEvents)>),
+    Resync(oneshot::Sender<(WorkspaceState, EngineEvents)>),
     Workspace(SessionId, WorkspaceMessage),
+    CaptureParams(Vec<ModuleId>, oneshot::Sender<Vec<(ModuleId, ModuleParams)>>),
+    Morph(Vec<(ModuleId, ModuleParams)>, MorphTiming),
+    InsertModules(Vec<(ModuleId, persist::Module)>, oneshot::Sender<HashMap<ModuleId, ModuleId>>),
+    UnmapMidi(MidiTarget),
+    MediaInUse(MediaId, oneshot::Sender<bool>),
+    ForgetMedia(MediaId),
+    Ping(oneshot::Sender<()>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MorphTiming {
+    pub delay: Duration,
+    pub duration: Duration,
 }
 
 #[derive(Clone)]
@@ -84,6 +108,8 @@ pub fn start(tokio_runtime: runtime::Handle, workspace: WorkspaceEmbryo, base: P
                 log_tx,
                 perf_tx,
                 session_seq: Sequence::new(),
+                tick: 0,
+                morphs: Vec::new(),
                 workspace: workspace.spawn(base.clone()),
                 base,
             };
@@ -132,9 +158,62 @@ impl EngineHandle {
         }))
     }
 
+    /// Returns the current params of each of the given modules which exist
+    pub async fn capture_params(&self, modules: Vec<ModuleId>) -> Result<Vec<(ModuleId, ModuleParams)>, EngineError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx.try_send(EngineMessage::CaptureParams(modules, tx))?;
+        rx.await.map_err(|_| EngineError::Stopped)
+    }
+
+    /// Moves each module towards the given params over time. Only floating
+    /// point fields are interpolated, others change when the morph completes
+    pub fn morph_params(&self, params: Vec<(ModuleId, ModuleParams)>, timing: MorphTiming) -> Result<(), EngineError> {
+        Ok(self.cmd_tx.try_send(EngineMessage::Morph(params, timing))?)
+    }
+
+    /// Inserts modules saved elsewhere under new ids, keeping connections
+    /// between them. Returns the new id of each module by its old id
+    pub async fn insert_modules(&self, modules: Vec<(ModuleId, persist::Module)>) -> Result<HashMap<ModuleId, ModuleId>, EngineError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx.try_send(EngineMessage::InsertModules(modules, tx))?;
+        rx.await.map_err(|_| EngineError::Stopped)
+    }
+
+    /// Removes the MIDI mapping to a target kept outside of the workspace,
+    /// such as a scene, once the target is deleted
+    pub fn unmap_midi(&self, target: MidiTarget) -> Result<(), EngineError> {
+        Ok(self.cmd_tx.try_send(EngineMessage::UnmapMidi(target))?)
+    }
+
+    /// Whether any module in the workspace is playing the media
+    pub async fn media_in_use(&self, media_id: MediaId) -> Result<bool, EngineError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx.try_send(EngineMessage::MediaInUse(media_id, tx))?;
+        rx.await.map_err(|_| EngineError::Stopped)
+    }
+
+    /// Stops modules playing media which has been deleted. Modules may have
+    /// picked it up since media_in_use was checked
+    pub fn forget_media(&self, media_id: MediaId) -> Result<(), EngineError> {
+        Ok(self.cmd_tx.try_send(EngineMessage::ForgetMedia(media_id))?)
+    }
+
     pub fn performance_info(&self) -> impl Stream<Item = Arc<PerformanceInfo>> {
         self.perf_rx.clone().filter_map(|info| future::ready(info))
     }
+
+    /// Most recent performance info, if the engine has reported any yet
+    pub fn latest_performance_info(&self) -> Option<Arc<PerformanceInfo>> {
+        self.perf_rx.borrow().clone()
+    }
+
+    /// Resolves once the engine thread has processed a message sent now,
+    /// showing that it is alive and not stuck
+    pub async fn ping(&self) -> Result<(), EngineError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx.try_send(EngineMessage::Ping(tx))?;
+        rx.await.map_err(|_| EngineError::Stopped)
+    }
 }
 
 impl EngineSession {
@@ -142,6 +221,16 @@ impl EngineSession {
         self.session_id
     }
 
+    /// Takes a fresh snapshot of the workspace along with a new subscription
+    /// to engine events following on from it, for sessions which have lagged.
+    /// Any updates sent by this session beforehand are reflected in the
+    /// snapshot, as the engine processes messages in order.
+    pub async fn resync(&self) -> Result<(WorkspaceState, EngineEvents), EngineError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(EngineMessage::Resync(tx))?;
+        rx.await.map_err(|_| EngineError::Stopped)
+    }
+
     /// TODO - maybe pass log position in here and detect conflicts?
     pub fn update(&self, msg: WorkspaceMessage) -> Result<(), EngineError> {
         self.send_message(EngineMessage::Workspace(self.session_id, msg))
@@ -157,6 +246,8 @@ pub struct Engine {
     log_tx: broadcast::Sender<EngineEvent>,
     perf_tx: watch::Sender<Option<Arc<PerformanceInfo>>>,
     session_seq: Sequence,
+    tick: u64,
+    morphs: Vec<Morph>,
     workspace: SyncWorkspace,
     base: ProjectBaseRef,
 }
@@ -170,10 +261,14 @@ impl Engine {
         loop {
             let this_tick = tick;
             tick += 1;
+            self.tick = this_tick;
 
             // we don't simply calculate `tick * TICK_BUDGET` here to prevent loss of precision over time:
             let scheduled_tick_end = start + Duration::from_millis((tick * 1_000) / TICKS_PER_SECOND as u64);
 
+            // apply any scheduled or in progress param morphs
+            self.run_morphs(this_tick);
+
             // run tick
             let indications = stat.record_tick(scheduled_tick_end,
                 |tick_stat| self.run_tick(this_tick, tick_stat));
@@ -220,9 +315,209 @@ impl Engine {
             EngineMessage::ConnectSession(tx) => {
                 let _ = tx.send(self.connect_session());
             }
+            EngineMessage::Resync(tx) => {
+                let log_rx = self.log_tx.subscribe();
+                let _ = tx.send((self.dump_state(), log_rx));
+            }
             EngineMessage::Workspace(session, msg) => {
                 self.client_update(session, msg, stat);
             }
+            EngineMessage::CaptureParams(modules, tx) => {
+                let workspace = self.workspace.borrow();
+
+                let params = modules.into_iter()
+                    .filter_map(|module_id| {
+                        workspace.modules.get(&module_id)
+                            .map(|module| (module_id, module.params()))
+                    })
+                    .collect();
+
+                let _ = tx.send(params);
+            }
+            EngineMessage::Morph(params, timing) => {
+                self.schedule_morph(params, timing);
+            }
+            EngineMessage::InsertModules(modules, tx) => {
+                let _ = tx.send(self.insert_modules(modules));
+            }
+            EngineMessage::UnmapMidi(target) => {
+                for op in self.unmap_midi(|mapped| *mapped == target) {
+                    self.log_op(op);
+                }
+            }
+            EngineMessage::MediaInUse(media_id, tx) => {
+                let _ = tx.send(!self.modules_playing(media_id).is_empty());
+            }
+            EngineMessage::ForgetMedia(media_id) => {
+                self.forget_media(media_id);
+            }
+            EngineMessage::Ping(tx) => {
+                let _ = tx.send(());
+            }
+        }
+    }
+
+    fn insert_modules(&mut self, modules: Vec<(ModuleId, persist::Module)>) -> HashMap<ModuleId, ModuleId> {
+        let mut ids = HashMap::new();
+        let mut operations = Vec::new();
+
+        {
+            let mut workspace = self.workspace.borrow_mut();
+
+            for (old_id, saved) in &modules {
+                let id = ModuleId(workspace.module_seq.next());
+                let (module, indication) = module::host(saved.params.clone(), self.base.clone());
+                let inputs = module.inputs().to_vec();
+                let outputs = module.outputs().to_vec();
+                workspace.modules.insert(id, module);
+                workspace.geometry.insert(id, saved.geometry.clone());
+                workspace.indications.insert(id, indication.clone());
+
+                operations.push(ServerUpdate::CreateModule {
+                    id,
+                    params: saved.params.clone(),
+                    geometry: saved.geometry.clone(),
+                    indication,
+                    inputs,
+                    outputs,
+                });
+
+                if saved.bypass {
+                    workspace.bypassed.insert(id);
+                    operations.push(ServerUpdate::SetBypass(id, true));
+                }
+
+                ids.insert(*old_id, id);
+            }
+
+            // connect after creating every module, as with loading a saved
+            // workspace. connections from modules not inserted are dropped:
+            for (old_id, saved) in &modules {
+                for (index, output) in saved.inputs.iter().enumerate() {
+                    let output = match output {
+                        Some(output) => output,
+                        None => continue,
+                    };
+
+                    let output_module = match ids.get(&output.module_id()) {
+                        Some(module_id) => *module_id,
+                        None => continue,
+                    };
+
+                    let input = InputId(ids[old_id], index);
+                    let output = OutputId(output_module, output.index());
+
+                    if workspace.connect(input, output).is_ok() {
+                        operations.push(ServerUpdate::CreateConnection(input, output));
+                    }
+                }
+            }
+        }
+
+        for op in operations {
+            self.log_op(op);
+        }
+
+        ids
+    }
+
+    fn schedule_morph(&mut self, params: Vec<(ModuleId, ModuleParams)>, timing: MorphTiming) {
+        fn duration_ticks(duration: Duration) -> u64 {
+            (duration.as_micros() * TICKS_PER_SECOND as u128 / 1_000_000) as u64
+        }
+
+        let start_tick = self.tick + 1 + duration_ticks(timing.delay);
+        let duration_ticks = duration_ticks(timing.duration);
+
+        for (module_id, params) in params {
+            self.morphs.push(Morph::new(module_id, params, start_tick, duration_ticks));
+        }
+
+        // keep morphs in start order so that later morphs supersede earlier
+        // ones in run_morphs:
+        self.morphs.sort_by_key(|morph| morph.start_tick());
+    }
+
+    fn run_morphs(&mut self, tick: u64) {
+        if !self.morphs.iter().any(|morph| morph.is_due(tick)) {
+            return;
+        }
+
+        // a morph which has started supersedes any earlier started morph of
+        // the same module:
+        let mut latest = HashMap::new();
+
+        for (idx, morph) in self.morphs.iter().enumerate() {
+            if morph.is_due(tick) {
+                latest.insert(morph.module_id(), idx);
+            }
+        }
+
+        for (idx, morph) in self.morphs.iter_mut().enumerate() {
+            if morph.is_due(tick) && latest.get(&morph.module_id()) != Some(&idx) {
+                morph.abort();
+            }
+        }
+
+        // module params change every tick while morphing, so are only
+        // persisted once a morph ends:
+        let mut updated = HashSet::new();
+        let mut finished = Vec::new();
+
+        {
+            let workspace = self.workspace.borrow_mut_without_sync();
+
+            for morph in &mut self.morphs {
+                let module = match workspace.modules.get_mut(&morph.module_id()) {
+                    Some(module) => module,
+                    None => {
+                        morph.abort();
+                        continue;
+                    }
+                };
+
+                let stepped = match morph.step(tick, module.params()) {
+                    Some(params) => {
+                        module.update(params);
+                        true
+                    }
+                    None => false,
+                };
+
+                // ending morphs always send out where they left the module:
+                let due_update = stepped && (tick - morph.start_tick()) % MORPH_UPDATE_TICKS == 0;
+
+                if morph.is_finished() || due_update {
+                    updated.insert(morph.module_id());
+                }
+
+                if morph.is_finished() {
+                    finished.push(morph.module_id());
+                }
+            }
+        }
+
+        if self.morphs.iter().any(|morph| morph.is_finished()) {
+            self.workspace.sync();
+        }
+
+        self.morphs.retain(|morph| !morph.is_finished());
+
+        for module_id in updated {
+            let params = match self.workspace.borrow().modules.get(&module_id) {
+                Some(module) => module.params(),
+                None => continue,
+            };
+
+            self.log_op(ServerUpdate::UpdateModuleParams(module_id, params));
+        }
+
+        // morphs only change the shape of params, eg. the channels of a
+        // mixer, as they finish:
+        for module_id in finished {
+            for op in self.unmap_missing_fields(module_id) {
+                self.log_op(op);
+            }
         }
     }
 
@@ -237,10 +532,13 @@ impl Engine {
         let mut state = WorkspaceState {
             modules: Vec::new(),
             geometry: Vec::new(),
+            bypass: Vec::new(),
             indications: Vec::new(),
             connections: Vec::new(),
             inputs: Vec::new(),
             outputs: Vec::new(),
+            macros: Vec::new(),
+            midi: Vec::new(),
         };
 
         let workspace = self.workspace.borrow();
@@ -255,6 +553,10 @@ impl Engine {
             state.geometry.push((*module_id, geometry.clone()));
         }
 
+        for module_id in &workspace.bypassed {
+            state.bypass.push(*module_id);
+        }
+
         for (module_id, indication) in &workspace.indications {
             state.indications.push((*module_id, indication.clone()));
         }
@@ -263,6 +565,14 @@ impl Engine {
             state.connections.push((*input, *output));
         }
 
+        for (macro_id, params) in &workspace.macros {
+            state.macros.push((*macro_id, params.clone()));
+        }
+
+        for (target, binding) in &workspace.midi {
+            state.midi.push((target.clone(), binding.clone()));
+        }
+
         state
     }
 
@@ -274,6 +584,100 @@ impl Engine {
         let _ = self.log_tx.send(EngineEvent::Sync(clock));
     }
 
+    fn set_macro(&mut self, macro_id: MacroId, params: MacroParams) {
+        let changed = {
+            let mut workspace = self.workspace.borrow_mut();
+            workspace.macros.insert(macro_id, params.clone());
+            macro_control::apply(&mut workspace, &params)
+        };
+
+        self.log_op(ServerUpdate::UpdateMacro(macro_id, params));
+
+        for (module_id, module_params) in changed {
+            // macro changes take precedence over morphs, like client changes:
+            self.morphs.retain(|morph| morph.module_id() != module_id);
+            self.log_op(ServerUpdate::UpdateModuleParams(module_id, module_params));
+        }
+    }
+
+    /// Removes MIDI mappings to targets which no longer exist, returning the
+    /// operations to log
+    fn unmap_midi(&mut self, f: impl Fn(&MidiTarget) -> bool) -> Vec<ServerUpdate> {
+        let workspace = self.workspace.borrow();
+
+        let targets = workspace.midi.keys()
+            .filter(|target| f(target))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if targets.is_empty() {
+            return Vec::new();
+        }
+
+        let mut workspace = self.workspace.borrow_mut();
+
+        targets.into_iter()
+            .map(|target| {
+                workspace.midi.remove(&target);
+                ServerUpdate::SetMidiMapping(target, None)
+            })
+            .collect()
+    }
+
+    fn modules_playing(&self, media_id: MediaId) -> Vec<ModuleId> {
+        self.workspace.borrow().modules.iter()
+            .filter(|(_, module)| match module.params() {
+                ModuleParams::MediaSource(params) => params.media_id == Some(media_id),
+                _ => false,
+            })
+            .map(|(module_id, _)| *module_id)
+            .collect()
+    }
+
+    fn forget_media(&mut self, media_id: MediaId) {
+        let modules = self.modules_playing(media_id);
+
+        if modules.is_empty() {
+            return;
+        }
+
+        // a morph under way would only put the media back as it ends:
+        self.morphs.retain(|morph| !modules.contains(&morph.module_id()));
+
+        let operations = {
+            let mut workspace = self.workspace.borrow_mut();
+
+            modules.into_iter()
+                .filter_map(|module_id| {
+                    workspace.modules.get_mut(&module_id).map(|module| {
+                        module.update(ModuleParams::MediaSource(MediaSourceParams { media_id: None }));
+                        ServerUpdate::UpdateModuleParams(module_id, module.params())
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for op in operations {
+            self.log_op(op);
+        }
+    }
+
+    /// Removes MIDI mappings to fields the module's params no longer have,
+    /// such as those of channels removed from a mixer
+    fn unmap_missing_fields(&mut self, module_id: ModuleId) -> Vec<ServerUpdate> {
+        let params = match self.workspace.borrow().modules.get(&module_id) {
+            Some(module) => module.params(),
+            None => return Vec::new(),
+        };
+
+        self.unmap_midi(|target| match target {
+            MidiTarget::ModuleParam(target_module, field) => {
+                *target_module == module_id && !macro_control::has_field(&params, field)
+            }
+            _ => false,
+        })
+    }
+
     fn client_update(&mut self, session_id: SessionId, msg: WorkspaceMessage, stat: &mut EngineStat) {
         let clock = OpClock(session_id, msg.sequence);
 
@@ -305,6 +709,9 @@ impl Engine {
                 self.log_op(op);
             }
             WorkspaceOp::UpdateModuleParams(module_id, params) => {
+                // direct changes from a client take precedence over morphs:
+                self.morphs.retain(|morph| morph.module_id() != module_id);
+
                 let op = {
                     let mut workspace = self.workspace.borrow_mut();
 
@@ -316,6 +723,10 @@ impl Engine {
 
                 if let Some(op) = op {
                     self.log_op(op);
+
+                    for op in self.unmap_missing_fields(module_id) {
+                        self.log_op(op);
+                    }
                 }
             }
             WorkspaceOp::UpdateWindowGeometry(module_id, geometry) => {
@@ -332,6 +743,31 @@ impl Engine {
                     self.log_op(op);
                 }
             }
+            WorkspaceOp::SetBypass(module_id, bypass) => {
+                let op = {
+                    let mut workspace = self.workspace.borrow_mut();
+
+                    if workspace.modules.contains_key(&module_id) {
+                        if bypass {
+                            workspace.bypassed.insert(module_id);
+                        } else {
+                            workspace.bypassed.remove(&module_id);
+                        }
+
+                        Some(ServerUpdate::SetBypass(module_id, bypass))
+                    } else {
+                        None
+                    }
+                };
+
+                if let Some(op) = op {
+                    self.log_op(op);
+                }
+
+                if bypass {
+                    stat.remove_module(module_id);
+                }
+            }
             WorkspaceOp::DeleteModule(module_id) => {
                 let mut operations = Vec::new();
 
@@ -358,10 +794,16 @@ impl Engine {
 
                     if workspace.modules.contains_key(&module_id) {
                         workspace.modules.remove(&module_id);
+                        workspace.bypassed.remove(&module_id);
                         operations.push(ServerUpdate::DeleteModule(module_id));
                     }
                 }
 
+                operations.extend(self.unmap_midi(|target| match target {
+                    MidiTarget::ModuleParam(target_module, _) => *target_module == module_id,
+                    _ => false,
+                }));
+
                 for op in operations {
                     self.log_op(op);
                 }
@@ -392,6 +834,50 @@ impl Engine {
                     self.log_op(ServerUpdate::DeleteConnection(input_id));
                 }
             }
+            WorkspaceOp::CreateMacro(params) => {
+                let macro_id = MacroId(self.workspace.borrow_mut().macro_seq.next());
+                self.set_macro(macro_id, params);
+            }
+            WorkspaceOp::UpdateMacro(macro_id, params) => {
+                if self.workspace.borrow().macros.contains_key(&macro_id) {
+                    self.set_macro(macro_id, params);
+                }
+            }
+            WorkspaceOp::SetMacroValue(macro_id, value) => {
+                let params = self.workspace.borrow().macros.get(&macro_id).cloned();
+
+                if let Some(params) = params {
+                    self.set_macro(macro_id, MacroParams { value, ..params });
+                }
+            }
+            WorkspaceOp::DeleteMacro(macro_id) => {
+                let previous = self.workspace.borrow_mut().macros.remove(&macro_id);
+
+                if let Some(_) = previous {
+                    self.log_op(ServerUpdate::DeleteMacro(macro_id));
+
+                    for op in self.unmap_midi(|target| *target == MidiTarget::Macro(macro_id)) {
+                        self.log_op(op);
+                    }
+                }
+            }
+            WorkspaceOp::SetMidiMapping(target, binding) => {
+                let changed = {
+                    let mut workspace = self.workspace.borrow_mut();
+
+                    match &binding {
+                        Some(binding) => {
+                            let previous = workspace.midi.insert(target.clone(), binding.clone());
+                            previous.as_ref() != Some(binding)
+                        }
+                        None => workspace.midi.remove(&target).is_some(),
+                    }
+                };
+
+                if changed {
+                    self.log_op(ServerUpdate::SetMidiMapping(target, binding));
+                }
+            }
         }
 
         return self.sync_log(clock);
@@ -483,20 +969,26 @@ impl Engine {
                     })
                     .collect::<Vec<_>>();
 
-                let mut output_refs = output_buffers.iter_mut()
-                    .map(|output| output.as_output_ref())
-                    .collect::<Vec<_>>();
-
-                let t = tick * SAMPLES_PER_TICK as u64;
-
-                let result = stat.record_module(*module_id, || {
-                    module.run_tick(t, &input_refs, &mut output_refs)
-                });
-
-                match result {
-                    None => {}
-                    Some(indic) => {
-                        indications.push((*module_id, indic));
+                if workspace.bypassed.contains(module_id) {
+                    // bypassed modules do not run at all, their inputs are
+                    // passed straight through to their outputs instead
+                    bypass(module.inputs(), module.outputs(), &input_refs, &mut output_buffers);
+                } else {
+                    let mut output_refs = output_buffers.iter_mut()
+                        .map(|output| output.as_output_ref())
+                        .collect::<Vec<_>>();
+
+                    let t = tick * SAMPLES_PER_TICK as u64;
+
+                    let result = stat.record_module(*module_id, || {
+                        module.run_tick(t, &input_refs, &mut output_refs)
+                    });
+
+                    match result {
+                        None => {}
+                        Some(indic) => {
+                            indications.push((*module_id, indic));
+                        }
                     }
                 }
             }
@@ -509,3 +1001,20 @@ impl Engine {
         indications
     }
 }
+
+// routes each input to the first unused output of the same line type. any
+// outputs left over are silent, as they were allocated by from_line_type
+fn bypass(inputs: &[Terminal], outputs: &[Terminal], input_refs: &[InputRef], output_buffers: &mut [Output]) {
+    let mut used = vec![false; outputs.len()];
+
+    for (input, input_ref) in inputs.iter().zip(input_refs) {
+        let output_idx = outputs.iter()
+            .enumerate()
+            .position(|(idx, output)| !used[idx] && output.line_type() == input.line_type());
+
+        if let Some(output_idx) = output_idx {
+            used[output_idx] = true;
+            output_buffers[output_idx].pass_through(input_ref);
+        }
+    }
+}
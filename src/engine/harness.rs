@@ -0,0 +1,128 @@
+// Harness for running a single module outside of the engine in tests. Ticks
+// are driven explicitly by the test, SAMPLES_PER_TICK samples of input at a
+// time. There is no virtual clock: modules which read the time themselves,
+// such as output_device, still see wall time.
+
+use std::future::Future;
+
+use tokio::runtime::{self, Runtime};
+
+use crate::engine::{InputRef, Output, Sample, SAMPLES_PER_TICK};
+use crate::engine::module::ModuleHost;
+use crate::module::ModuleT;
+use crate::project::ProjectBase;
+
+pub struct Harness<M: ModuleT> {
+    // basic scheduler never makes progress on spawned tasks unless the
+    // harness is blocked on, which keeps async module work deterministic:
+    runtime: Runtime,
+    host: ModuleHost<M>,
+    tick: u64,
+}
+
+pub struct Tick<I> {
+    pub tick: u64,
+    pub outputs: Vec<Output>,
+    pub indication: Option<I>,
+}
+
+impl<M: ModuleT> Harness<M> {
+    pub fn new(params: M::Params) -> (Self, M::Indication) {
+        let mut runtime = runtime::Builder::new()
+            .enable_all()
+            .basic_scheduler()
+            .build()
+            .expect("build runtime");
+
+        let base = runtime.block_on(ProjectBase::in_memory())
+            .expect("create in-memory project");
+
+        let (host, indication) = runtime.enter(|| ModuleHost::new(params, base));
+
+        let harness = Harness {
+            runtime,
+            host,
+            tick: 0,
+        };
+
+        (harness, indication)
+    }
+
+    pub fn module(&self) -> &M {
+        self.host.module()
+    }
+
+    pub fn update(&mut self, params: M::Params) -> Option<M::Indication> {
+        self.host.module_mut().update(params)
+    }
+
+    /// Number of the next tick to be run
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Drives the harness runtime until the future completes, giving any
+    /// tasks spawned by the module a chance to run
+    pub fn block_on<F: Future>(&mut self, f: F) -> F::Output {
+        self.runtime.block_on(f)
+    }
+
+    pub fn run_tick(&mut self, inputs: &[InputRef]) -> Tick<M::Indication> {
+        assert_eq!(inputs.len(), self.module().inputs().len(), "harness: wrong number of inputs");
+
+        let mut outputs = self.module().outputs().iter()
+            .map(|output| Output::from_line_type(output.line_type()))
+            .collect::<Vec<_>>();
+
+        let indication = {
+            let mut output_refs = outputs.iter_mut()
+                .map(|output| output.as_output_ref())
+                .collect::<Vec<_>>();
+
+            let t = self.tick * SAMPLES_PER_TICK as u64;
+
+            self.host.run_tick(t, inputs, &mut output_refs)
+        };
+
+        let tick = Tick {
+            tick: self.tick,
+            outputs,
+            indication,
+        };
+
+        self.tick += 1;
+
+        tick
+    }
+
+    /// Runs n ticks, feeding the same inputs to each
+    pub fn run_ticks(&mut self, n: usize, inputs: &[InputRef]) -> Vec<Tick<M::Indication>> {
+        (0..n).map(|_| self.run_tick(inputs)).collect()
+    }
+
+    /// Feeds a mono signal to the first input of the module over as many
+    /// ticks as it takes, and returns the signal from its first output.
+    /// Any other inputs are left disconnected. The final tick is padded with
+    /// silence and the returned signal is the same length as the input.
+    pub fn run_mono(&mut self, signal: &[Sample]) -> Vec<Sample> {
+        let input_count = self.module().inputs().len();
+        let mut result = Vec::with_capacity(signal.len());
+
+        for chunk in signal.chunks(SAMPLES_PER_TICK) {
+            let mut buffer = chunk.to_vec();
+            buffer.resize(SAMPLES_PER_TICK, 0.0);
+
+            let mut inputs = (0..input_count)
+                .map(|_| InputRef::Disconnected)
+                .collect::<Vec<_>>();
+
+            inputs[0] = InputRef::Mono(&buffer);
+
+            let tick = self.run_tick(&inputs);
+            let output = tick.outputs[0].as_input_ref().expect_mono();
+            result.extend_from_slice(&output[0..chunk.len()]);
+        }
+
+        result
+    }
+}
@@ -16,11 +16,27 @@ pub struct VideoFrame {
     pub tick_offset: MediaDuration,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    // sample offset after enclosing tick begins:
+    pub offset: usize,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    NoteOn { note: u8, velocity: u8 },
+    NoteOff { note: u8 },
+    Trigger,
+    Value(f64),
+}
+
 pub enum InputRef<'a> {
     Disconnected,
     Mono(&'a [Sample]),
     Stereo(&'a [Sample]),
     Video(Option<&'a VideoFrame>),
+    Event(&'a [Event]),
 }
 
 impl<'a> InputRef<'a> {
@@ -29,7 +45,8 @@ impl<'a> InputRef<'a> {
             InputRef::Disconnected => false,
             InputRef::Mono(_) |
             InputRef::Stereo(_) |
-            InputRef::Video(_) => true,
+            InputRef::Video(_) |
+            InputRef::Event(_) => true,
         }
     }
 
@@ -39,6 +56,7 @@ impl<'a> InputRef<'a> {
             InputRef::Mono(buff) => buff,
             InputRef::Stereo(_) => panic!("expected mono input, got stereo"),
             InputRef::Video(_) => panic!("expected mono input, got avc"),
+            InputRef::Event(_) => panic!("expected mono input, got event"),
         }
     }
 
@@ -48,6 +66,7 @@ impl<'a> InputRef<'a> {
             InputRef::Stereo(buff) => buff,
             InputRef::Mono(_) => panic!("expected stereo input, got mono"),
             InputRef::Video(_) => panic!("expected stereo input, got avc"),
+            InputRef::Event(_) => panic!("expected stereo input, got event"),
         }
     }
 
@@ -57,6 +76,17 @@ impl<'a> InputRef<'a> {
             InputRef::Stereo(_) => panic!("expected stereo input, got stereo"),
             InputRef::Mono(_) => panic!("expected stereo input, got mono"),
             InputRef::Video(frame) => *frame,
+            InputRef::Event(_) => panic!("expected video input, got event"),
+        }
+    }
+
+    pub fn expect_events(&self) -> &'a [Event] {
+        match self {
+            InputRef::Disconnected => &[],
+            InputRef::Event(events) => events,
+            InputRef::Mono(_) => panic!("expected event input, got mono"),
+            InputRef::Stereo(_) => panic!("expected event input, got stereo"),
+            InputRef::Video(_) => panic!("expected event input, got video"),
         }
     }
 }
@@ -65,6 +95,7 @@ pub enum Output {
     Mono(Vec<Sample>),
     Stereo(Vec<Sample>),
     Video(Option<VideoFrame>),
+    Event(Vec<Event>),
 }
 
 impl Output {
@@ -73,6 +104,25 @@ impl Output {
             LineType::Mono => Output::Mono(vec![0.0; SAMPLES_PER_TICK]),
             LineType::Stereo => Output::Stereo(vec![0.0; SAMPLES_PER_TICK * CHANNELS]),
             LineType::Video => Output::Video(None),
+            LineType::Event => Output::Event(Vec::new()),
+        }
+    }
+
+    /// Copies an input of the same line type into this output. Does nothing
+    /// if the input is disconnected or of a different line type.
+    pub fn pass_through(&mut self, input: &InputRef) {
+        match (self, input) {
+            (Output::Mono(out), InputRef::Mono(buff)) |
+            (Output::Stereo(out), InputRef::Stereo(buff)) => {
+                out.copy_from_slice(buff);
+            }
+            (Output::Video(out), InputRef::Video(frame)) => {
+                *out = frame.cloned();
+            }
+            (Output::Event(out), InputRef::Event(events)) => {
+                out.extend_from_slice(events);
+            }
+            _ => {}
         }
     }
 
@@ -81,6 +131,7 @@ impl Output {
             Output::Mono(buff) => InputRef::Mono(buff),
             Output::Stereo(buff) => InputRef::Stereo(buff),
             Output::Video(packet) => InputRef::Video(packet.as_ref()),
+            Output::Event(events) => InputRef::Event(events),
         }
     }
 
@@ -89,6 +140,7 @@ impl Output {
             Output::Mono(buff) => OutputRef::Mono(buff),
             Output::Stereo(buff) => OutputRef::Stereo(buff),
             Output::Video(frame) => OutputRef::Video(frame),
+            Output::Event(events) => OutputRef::Event(events),
         }
     }
 }
@@ -96,7 +148,8 @@ impl Output {
 pub enum OutputRef<'a> {
     Mono(&'a mut [Sample]),
     Stereo(&'a mut [Sample]),
-    Video(&'a mut Option<VideoFrame>)
+    Video(&'a mut Option<VideoFrame>),
+    Event(&'a mut Vec<Event>),
 }
 
 impl<'a> OutputRef<'a> {
@@ -105,6 +158,7 @@ impl<'a> OutputRef<'a> {
             OutputRef::Mono(buff) => buff,
             OutputRef::Stereo(_) => panic!("expected mono output, got stereo"),
             OutputRef::Video(_) => panic!("expected mono output, got video"),
+            OutputRef::Event(_) => panic!("expected mono output, got event"),
         }
     }
 
@@ -113,6 +167,7 @@ impl<'a> OutputRef<'a> {
             OutputRef::Stereo(buff) => buff,
             OutputRef::Mono(_) => panic!("expected stereo output, got mono"),
             OutputRef::Video(_) => panic!("expected mono output, got video"),
+            OutputRef::Event(_) => panic!("expected stereo output, got event"),
         }
     }
 
@@ -121,6 +176,16 @@ impl<'a> OutputRef<'a> {
             OutputRef::Stereo(_) => panic!("expected stereo output, got video"),
             OutputRef::Mono(_) => panic!("expected mono input, got video"),
             OutputRef::Video(frame) => *frame,
+            OutputRef::Event(_) => panic!("expected video output, got event"),
+        }
+    }
+
+    pub fn expect_events(&mut self) -> &mut Vec<Event> {
+        match self {
+            OutputRef::Event(events) => *events,
+            OutputRef::Mono(_) => panic!("expected event output, got mono"),
+            OutputRef::Stereo(_) => panic!("expected event output, got stereo"),
+            OutputRef::Video(_) => panic!("expected event output, got video"),
         }
     }
 }
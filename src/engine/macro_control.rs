@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Number, Value};
+
+use mixlab_protocol::{ModuleId, ModuleParams, MacroParams, MacroTarget};
+
+use crate::engine::workspace::Workspace;
+
+/// Applies the macro's current value to each of its targets, returning the
+/// new params of every module which changed
+pub fn apply(workspace: &mut Workspace, params: &MacroParams) -> Vec<(ModuleId, ModuleParams)> {
+    let mut changed = BTreeMap::new();
+
+    for target in &params.targets {
+        let module = match workspace.modules.get_mut(&target.module) {
+            Some(module) => module,
+            None => continue,
+        };
+
+        if let Some(new_params) = set_field(&module.params(), target, params.value) {
+            module.update(new_params);
+            changed.insert(target.module, module.params());
+        }
+    }
+
+    changed.into_iter().collect()
+}
+
+/// Whether the params have a field at the pointer, as MIDI mappings and macro
+/// targets address them
+pub fn has_field(params: &ModuleParams, field: &str) -> bool {
+    let serialized = serde_json::to_value(params).expect("serialize ModuleParams");
+
+    match &serialized {
+        Value::Object(variant) if variant.len() == 1 => {
+            variant.values().next().and_then(|contents| contents.pointer(field)).is_some()
+        }
+        _ => false,
+    }
+}
+
+// returns None if the target field does not exist or is not a floating point
+// number, macros never change the shape of module params
+fn set_field(params: &ModuleParams, target: &MacroTarget, value: f64) -> Option<ModuleParams> {
+    let mut serialized = serde_json::to_value(params).expect("serialize ModuleParams");
+
+    {
+        // ModuleParams is externally tagged, so target fields are addressed
+        // relative to the variant's contents:
+        let contents = match &mut serialized {
+            Value::Object(variant) if variant.len() == 1 => variant.values_mut().next()?,
+            _ => return None,
+        };
+
+        let field = contents.pointer_mut(&target.field)?;
+
+        match field {
+            Value::Number(number) if number.is_f64() => {}
+            _ => return None,
+        }
+
+        *field = Value::Number(Number::from_f64(target.map(value))?);
+    }
+
+    serde_json::from_value(serialized).ok()
+}
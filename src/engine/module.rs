@@ -65,7 +65,7 @@ pub struct ModuleHost<M: ModuleT> {
 }
 
 impl<M: ModuleT> ModuleHost<M> {
-    fn new(params: M::Params, base: ProjectBaseRef) -> (Self, M::Indication) {
+    pub(in crate::engine) fn new(params: M::Params, base: ProjectBaseRef) -> (Self, M::Indication) {
         let (events_tx, events_rx) = mpsc::channel(2);
 
         let ctx = ModuleCtx {
@@ -83,6 +83,22 @@ impl<M: ModuleT> ModuleHost<M> {
 
         (host, indication)
     }
+
+    pub(in crate::engine) fn module(&self) -> &M {
+        &self.module
+    }
+
+    pub(in crate::engine) fn module_mut(&mut self) -> &mut M {
+        &mut self.module
+    }
+
+    pub(in crate::engine) fn run_tick(&mut self, t: u64, inputs: &[InputRef], outputs: &mut [OutputRef]) -> Option<M::Indication> {
+        if let Some(ev) = self.events.try_recv().ok() {
+            self.module.receive_event(ev);
+        }
+
+        self.module.run_tick(t, inputs, outputs)
+    }
 }
 
 pub trait DynModuleHostT {
@@ -110,11 +126,7 @@ macro_rules! gen_dyn_module_impls {
                 }
 
                 fn run_tick(&mut self, t: u64, inputs: &[InputRef], outputs: &mut [OutputRef]) -> Option<Indication> {
-                    if let Some(ev) = self.events.try_recv().ok() {
-                        self.module.receive_event(ev);
-                    }
-
-                    self.module.run_tick(t, inputs, outputs)
+                    ModuleHost::run_tick(self, t, inputs, outputs)
                         .map(Indication::$module)
                 }
 
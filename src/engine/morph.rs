@@ -0,0 +1,119 @@
+use std::mem;
+
+use serde_json::{Number, Value};
+
+use mixlab_protocol::{ModuleId, ModuleParams};
+
+#[derive(Debug)]
+pub struct Morph {
+    module_id: ModuleId,
+    // params the module had when the morph started, captured on first step:
+    from: Option<Value>,
+    to: ModuleParams,
+    start_tick: u64,
+    duration_ticks: u64,
+    finished: bool,
+}
+
+impl Morph {
+    pub fn new(module_id: ModuleId, to: ModuleParams, start_tick: u64, duration_ticks: u64) -> Self {
+        Morph {
+            module_id,
+            from: None,
+            to,
+            start_tick,
+            duration_ticks,
+            finished: false,
+        }
+    }
+
+    pub fn module_id(&self) -> ModuleId {
+        self.module_id
+    }
+
+    pub fn start_tick(&self) -> u64 {
+        self.start_tick
+    }
+
+    pub fn is_due(&self, tick: u64) -> bool {
+        tick >= self.start_tick
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn abort(&mut self) {
+        self.finished = true;
+    }
+
+    /// Returns the params the module should take on for this tick, or None if
+    /// the morph has not started yet or no longer applies to this module
+    pub fn step(&mut self, tick: u64, current: ModuleParams) -> Option<ModuleParams> {
+        if !self.is_due(tick) || self.finished {
+            return None;
+        }
+
+        // module may have been replaced with a different kind of module:
+        if mem::discriminant(&current) != mem::discriminant(&self.to) {
+            self.finished = true;
+            return None;
+        }
+
+        let elapsed = tick - self.start_tick;
+
+        if elapsed >= self.duration_ticks {
+            self.finished = true;
+            return Some(self.to.clone());
+        }
+
+        let from = self.from.get_or_insert_with(|| {
+            serde_json::to_value(&current).expect("serialize ModuleParams")
+        });
+
+        let to = serde_json::to_value(&self.to).expect("serialize ModuleParams");
+        let progress = elapsed as f64 / self.duration_ticks as f64;
+
+        match serde_json::from_value(interpolate(from, &to, progress)) {
+            Ok(params) => Some(params),
+            Err(_) => {
+                self.finished = true;
+                Some(self.to.clone())
+            }
+        }
+    }
+}
+
+// only floating point values are interpolated. integers (which includes
+// sequence numbers and indexes) and all other values hold their starting
+// value until the morph completes
+fn interpolate(from: &Value, to: &Value, progress: f64) -> Value {
+    match (from, to) {
+        (Value::Number(a), Value::Number(b)) if a.is_f64() && b.is_f64() => {
+            let a = a.as_f64().unwrap();
+            let b = b.as_f64().unwrap();
+
+            Number::from_f64(a + (b - a) * progress)
+                .map(Value::Number)
+                .unwrap_or_else(|| to.clone())
+        }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            Value::Array(a.iter().zip(b)
+                .map(|(a, b)| interpolate(a, b, progress))
+                .collect())
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            Value::Object(b.iter()
+                .map(|(key, b)| {
+                    let value = match a.get(key) {
+                        Some(a) => interpolate(a, b, progress),
+                        None => b.clone(),
+                    };
+
+                    (key.clone(), value)
+                })
+                .collect())
+        }
+        _ => from.clone(),
+    }
+}
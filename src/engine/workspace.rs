@@ -1,9 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
 
 use tokio::sync::watch;
 
-use mixlab_protocol::{ModuleId, InputId, OutputId, TerminalId, WindowGeometry, Indication, LineType};
+use mixlab_protocol::{ModuleId, InputId, OutputId, TerminalId, WindowGeometry, Indication, LineType, MacroId, MacroParams, MidiTarget, MidiBinding};
 
 use crate::engine::module::{self, DynModuleHost};
 use crate::persist;
@@ -14,14 +14,19 @@ pub struct Workspace {
     pub(in crate::engine) module_seq: Sequence,
     pub(in crate::engine) modules: HashMap<ModuleId, DynModuleHost>,
     pub(in crate::engine) geometry: HashMap<ModuleId, WindowGeometry>,
+    pub(in crate::engine) bypassed: HashSet<ModuleId>,
     pub(in crate::engine) connections: HashMap<InputId, OutputId>,
     pub(in crate::engine) indications: HashMap<ModuleId, Indication>,
+    pub(in crate::engine) macro_seq: Sequence,
+    pub(in crate::engine) macros: HashMap<MacroId, MacroParams>,
+    pub(in crate::engine) midi: HashMap<MidiTarget, MidiBinding>,
 }
 
 impl Workspace {
     pub fn from_persist(save: &persist::Workspace, base: ProjectBaseRef) -> Self {
         let mut modules = HashMap::new();
         let mut geometry = HashMap::new();
+        let mut bypassed = HashSet::new();
         let mut indications = HashMap::new();
 
         // load modules and geometry
@@ -30,14 +35,22 @@ impl Workspace {
             modules.insert(*module_id, module);
             geometry.insert(*module_id, saved_module.geometry.clone());
             indications.insert(*module_id, indication);
+
+            if saved_module.bypass {
+                bypassed.insert(*module_id);
+            }
         }
 
         let mut workspace = Workspace {
             module_seq: save.module_seq.clone(),
             modules,
             geometry,
+            bypassed,
             connections: HashMap::new(),
             indications,
+            macro_seq: save.macro_seq.clone(),
+            macros: save.macros.clone(),
+            midi: save.midi.iter().cloned().collect(),
         };
 
         // load connections after loading all modules
@@ -66,6 +79,8 @@ impl Workspace {
                         .cloned()
                         .unwrap_or_default();
 
+                    let bypass = self.bypassed.contains(module_id);
+
                     let inputs = (0..module.inputs().len())
                         .map(|idx| InputId(*module_id, idx))
                         .map(|input_id| self.connections.get(&input_id).cloned())
@@ -74,10 +89,16 @@ impl Workspace {
                     (*module_id, persist::Module {
                         params,
                         geometry,
+                        bypass,
                         inputs,
                     })
                 })
-                .collect()
+                .collect(),
+            macro_seq: self.macro_seq.clone(),
+            macros: self.macros.clone(),
+            midi: self.midi.iter()
+                .map(|(target, binding)| (target.clone(), binding.clone()))
+                .collect(),
         }
     }
 
@@ -167,6 +188,13 @@ impl SyncWorkspace {
     pub fn borrow_mut_without_sync(&mut self) -> &mut Workspace {
         &mut self.workspace
     }
+
+    /// Persists changes made through borrow_mut_without_sync
+    pub fn sync(&mut self) {
+        let workspace = self.workspace.to_persist();
+        // nothing we can do if this fails
+        let _ = self.persist_tx.broadcast(workspace);
+    }
 }
 
 pub struct WorkspaceBorrowMut<'a> {
@@ -175,9 +203,7 @@ pub struct WorkspaceBorrowMut<'a> {
 
 impl<'a> Drop for WorkspaceBorrowMut<'a> {
     fn drop(&mut self) {
-        let workspace = self.sync.workspace.to_persist();
-        // nothing we can do if this fails
-        let _ = self.sync.persist_tx.broadcast(workspace);
+        self.sync.sync();
     }
 }
 
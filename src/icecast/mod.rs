@@ -66,6 +66,10 @@ pub fn listen(mountpoint: &str) -> Result<SourceRecv, ListenError> {
     MOUNTPOINTS.listen(mountpoint)
 }
 
+pub fn mountpoints() -> Vec<(String, bool)> {
+    MOUNTPOINTS.mountpoints()
+}
+
 #[derive(From, Debug)]
 enum DecodeThreadError {
     ListenerDisconnected,
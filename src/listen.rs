@@ -7,13 +7,17 @@ use futures::stream::{self, StreamExt};
 use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+
+pub mod tls;
 
 pub struct Listener {
     pub local_addr: SocketAddr,
     pub incoming: Receiver<Disambiguation>,
 }
 
-pub async fn start(addr: SocketAddr) -> Result<Listener, io::Error> {
+pub async fn start(addr: SocketAddr, tls: Option<TlsAcceptor>) -> Result<Listener, io::Error> {
     let mut listener = TcpListener::bind(&addr).await?;
     let local_addr = listener.local_addr()?;
 
@@ -34,7 +38,7 @@ pub async fn start(addr: SocketAddr) -> Result<Listener, io::Error> {
         while let Some(event) = events.next().await {
             match event {
                 Event::Listener(Ok(conn)) => {
-                    handle_connection(conn, disambiguated_tx.clone());
+                    handle_connection(conn, tls.clone(), disambiguated_tx.clone());
                 }
                 Event::Listener(Err(e)) => {
                     eprintln!("listen: {:?}", e);
@@ -56,14 +60,14 @@ pub async fn start(addr: SocketAddr) -> Result<Listener, io::Error> {
     })
 }
 
-fn handle_connection(conn: TcpStream, mut out: Sender<Disambiguation>) {
+fn handle_connection(conn: TcpStream, tls: Option<TlsAcceptor>, mut out: Sender<Disambiguation>) {
     if let Err(e) = conn.set_nodelay(true) {
         eprintln!("listen: set_nodelay: {:?}", e);
         return;
     }
 
     tokio::spawn(async move {
-        match disambiguate(conn).await {
+        match disambiguate(conn, tls).await {
             Ok(conn) => {
                 let _: Result<_, _> = out.send(conn).await;
             }
@@ -84,15 +88,75 @@ pub enum Disambiguation {
     Rtmp(PeekTcpStream),
 }
 
-pub async fn disambiguate(stream: TcpStream)
+pub async fn disambiguate(stream: TcpStream, tls: Option<TlsAcceptor>)
     -> Result<Disambiguation, io::Error>
 {
-    let stream = PeekTcpStream::new(stream).await?;
+    let stream = PeekTcpStream::new(Conn::Tcp(stream)).await?;
+
+    match (stream.peek(), tls) {
+        // handshake record containing a ClientHello, the protocol underneath
+        // is disambiguated once decrypted:
+        ([0x16, 0x03, ..], Some(tls)) => {
+            let stream = tls.accept(stream).await?;
+            let stream = PeekTcpStream::new(Conn::Tls(Box::new(stream))).await?;
+            Ok(disambiguate_protocol(stream))
+        }
+        (_, _) => Ok(disambiguate_protocol(stream)),
+    }
+}
 
+fn disambiguate_protocol(stream: PeekTcpStream) -> Disambiguation {
     match stream.peek() {
-        b"SOURCE " => Ok(Disambiguation::Icecast(stream)),
-        [0x03, ..] => Ok(Disambiguation::Rtmp(stream)),
-        _ => Ok(Disambiguation::Http(stream)),
+        b"SOURCE " => Disambiguation::Icecast(stream),
+        [0x03, ..] => Disambiguation::Rtmp(stream),
+        _ => Disambiguation::Http(stream),
+    }
+}
+
+// underlying connection of a PeekTcpStream, either plain TCP or TLS over TCP:
+#[derive(Debug)]
+pub enum Conn {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<PeekTcpStream>>),
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        match self.get_mut() {
+            Conn::Tcp(conn) => Pin::new(conn).poll_read(cx, buf),
+            Conn::Tls(conn) => Pin::new(conn.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        match self.get_mut() {
+            Conn::Tcp(conn) => Pin::new(conn).poll_write(cx, buf),
+            Conn::Tls(conn) => Pin::new(conn.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        match self.get_mut() {
+            Conn::Tcp(conn) => Pin::new(conn).poll_flush(cx),
+            Conn::Tls(conn) => Pin::new(conn.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        match self.get_mut() {
+            Conn::Tcp(conn) => Pin::new(conn).poll_shutdown(cx),
+            Conn::Tls(conn) => Pin::new(conn.as_mut()).poll_shutdown(cx),
+        }
     }
 }
 
@@ -100,11 +164,11 @@ pub async fn disambiguate(stream: TcpStream)
 pub struct PeekTcpStream {
     peek: [u8; 7],
     offset: u8,
-    conn: TcpStream,
+    conn: Conn,
 }
 
 impl PeekTcpStream {
-    pub async fn new(conn: TcpStream) -> Result<Self, io::Error> {
+    pub async fn new(conn: Conn) -> Result<Self, io::Error> {
         let mut stream = PeekTcpStream {
             peek: [0; 7],
             offset: 0,
@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use derive_more::From;
+use structopt::StructOpt;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{NoClientAuth, PrivateKey, ServerConfig, TLSError};
+
+#[derive(StructOpt)]
+pub struct TlsOpts {
+    /// PEM encoded certificate chain. When given along with --tls-key,
+    /// connections may use TLS for HTTPS, secure websockets and RTMPS
+    #[structopt(long, requires = "tls-key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM encoded private key for --tls-cert, in PKCS#8 or RSA format
+    #[structopt(long, requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+}
+
+#[derive(From, Debug)]
+pub enum TlsError {
+    Io(io::Error),
+    Rustls(TLSError),
+    InvalidCertificate,
+    NoCertificate,
+    InvalidPrivateKey,
+    NoPrivateKey,
+}
+
+/// Builds a TLS acceptor from the certificate and key files given on the
+/// command line, or returns None if TLS is not configured
+pub fn acceptor(opts: TlsOpts) -> Result<Option<TlsAcceptor>, TlsError> {
+    let (cert_path, key_path) = match (opts.tls_cert, opts.tls_key) {
+        (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+        _ => return Ok(None),
+    };
+
+    let certs = pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .map_err(|()| TlsError::InvalidCertificate)?;
+
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate);
+    }
+
+    let key = private_key(&key_path)?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, key)?;
+
+    Ok(Some(TlsAcceptor::from(Arc::new(config))))
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?))
+        .map_err(|()| TlsError::InvalidPrivateKey)?;
+
+    if let Some(key) = pkcs8.into_iter().next() {
+        return Ok(key);
+    }
+
+    // keys generated by older versions of openssl are often RSA format:
+    let rsa = pemfile::rsa_private_keys(&mut BufReader::new(File::open(path)?))
+        .map_err(|()| TlsError::InvalidPrivateKey)?;
+
+    rsa.into_iter().next().ok_or(TlsError::NoPrivateKey)
+}
@@ -2,6 +2,7 @@ mod db;
 mod engine;
 mod icecast;
 mod listen;
+mod osc;
 mod persist;
 mod project;
 mod rtmp;
@@ -14,18 +15,44 @@ mod video;
 #[macro_use]
 mod module;
 
+use std::env;
+use std::process;
+
 use structopt::StructOpt;
 
+use crate::project::bundle::{self, BundleError};
+
 #[derive(StructOpt)]
+#[structopt(after_help = "Run `mixlab export --help` or `mixlab import --help` \
+    for moving projects between machines as bundles")]
 struct Opts {
     #[structopt(flatten)]
     run: server::RunOpts,
 }
 
+#[derive(StructOpt)]
+#[structopt(name = "mixlab")]
+enum Command {
+    /// Writes a project and its media to a portable bundle
+    Export(bundle::ExportOpts),
+    /// Creates a new project from a bundle written by export
+    Import(bundle::ImportOpts),
+}
+
+enum Invocation {
+    Server(Opts),
+    Command(Command),
+}
+
 fn main() {
     env_logger::init();
 
-    let opts = Opts::from_args();
+    // subcommands are picked out by hand so that running the server still
+    // takes nothing more than a path:
+    let invocation = match env::args_os().nth(1) {
+        Some(arg) if arg == "export" || arg == "import" => Invocation::Command(Command::from_args()),
+        _ => Invocation::Server(Opts::from_args()),
+    };
 
     let mut runtime = tokio::runtime::Builder::new()
         .enable_all()
@@ -33,5 +60,22 @@ fn main() {
         .build()
         .unwrap();
 
-    runtime.block_on(server::run(opts.run));
+    match invocation {
+        Invocation::Server(opts) => {
+            runtime.block_on(server::run(opts.run));
+        }
+        Invocation::Command(Command::Export(opts)) => {
+            exit_on_error("export", runtime.block_on(bundle::export_command(opts)));
+        }
+        Invocation::Command(Command::Import(opts)) => {
+            exit_on_error("import", runtime.block_on(bundle::import_command(opts)));
+        }
+    }
+}
+
+fn exit_on_error(command: &str, result: Result<(), BundleError>) {
+    if let Err(e) = result {
+        eprintln!("{} failed: {:?}", command, e);
+        process::exit(1);
+    }
 }
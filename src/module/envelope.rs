@@ -127,3 +127,38 @@ impl ModuleT for Envelope {
         &self.outputs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::harness::Harness;
+    use mixlab_protocol::EnvelopeParams;
+    use super::Envelope;
+
+    #[test]
+    fn attack_decay_sustain_release() {
+        let (mut envelope, _) = Harness::<Envelope>::new(EnvelopeParams {
+            attack_ms: 10.0,
+            decay_ms: 10.0,
+            sustain_amplitude: 0.5,
+            release_ms: 10.0,
+        });
+
+        // gate open for 2000 samples, then closed for 1000. this spans several
+        // ticks so also checks that state carries across tick boundaries
+        let mut gate = vec![1.0; 2000];
+        gate.resize(3000, 0.0);
+
+        let output = envelope.run_mono(&gate);
+
+        assert_eq!(output[0], 0.0);
+
+        // 10ms attack at 44.1khz is 441 samples:
+        assert!(output[0..441].windows(2).all(|w| w[0] < w[1]));
+        assert!(output[441..882].windows(2).all(|w| w[0] >= w[1]));
+
+        assert_eq!(output[1500], 0.5);
+        assert_eq!(output[2000], 0.5);
+        assert!(output[2000..2441].windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(output[2999], 0.0);
+    }
+}
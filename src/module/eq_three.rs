@@ -126,7 +126,7 @@ impl LowPass {
 
 #[cfg(test)]
 mod tests {
-    use crate::module::{ModuleT, InputRef, OutputRef};
+    use crate::engine::harness::Harness;
     use mixlab_protocol::{Decibel, EqThreeParams};
     use super::EqThree;
 
@@ -151,15 +151,13 @@ mod tests {
     fn basic_smoke_test() {
         let input = bytes_to_f32s(include_bytes!("../../fixtures/module/eq_three/chronos.f32.raw"));
 
-        let (mut eq, _) = EqThree::create(EqThreeParams {
+        let (mut eq, _) = Harness::<EqThree>::new(EqThreeParams {
             gain_lo: Decibel(4.0),
             gain_mid: Decibel(0.0),
             gain_hi: Decibel(4.0),
         });
 
-        let mut output = vec![0.0; input.len()];
-
-        eq.run_tick(0, &[InputRef::Mono(&input)], &mut [OutputRef::Mono(&mut output)]);
+        let output = eq.run_mono(&input);
 
         let expected_output = bytes_to_f32s(include_bytes!("../../fixtures/module/eq_three/chronos-eq.f32.raw"));
 
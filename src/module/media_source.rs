@@ -168,6 +168,7 @@ enum DecodeError {
     CodecBuild(codec::BuildError),
     CodecOpen(codec::OpenError),
     NoFrames,
+    NoVideoStream,
     RecvFrame(RecvFrameError),
     Av(AvError),
     Io(<ReadStream as IoReader>::Error),
@@ -190,7 +191,10 @@ fn run_decode_thread(stream: ReadStream, tx: SyncSender<Frame>) -> Result<(), De
         // println!("            Time base: {}", stream.time_base());
     }
 
-    let video_stream = &container.streams()[0];
+    let video_index = container.best_stream::<Video>()
+        .ok_or(DecodeError::NoVideoStream)?;
+
+    let video_stream = &container.streams()[video_index];
     let video_time_base = video_stream.time_base();
     let video_codec_params = video_stream.codec_parameters();
 
@@ -200,6 +204,7 @@ fn run_decode_thread(stream: ReadStream, tx: SyncSender<Frame>) -> Result<(), De
 
     let mut play = PlaybackContext {
         container,
+        video_index,
         video_decode,
         video_time_base,
         throttle: MediaThrottle::new(),
@@ -219,6 +224,7 @@ fn run_decode_thread(stream: ReadStream, tx: SyncSender<Frame>) -> Result<(), De
 
 struct PlaybackContext {
     container: InputContainer<ReadStream>,
+    video_index: usize,
     video_decode: Decode<Video>,
     video_time_base: TimeBase,
     throttle: MediaThrottle,
@@ -234,7 +240,7 @@ fn play_once(play: &mut PlaybackContext, iter_start: MediaTime) -> Result<Option
         if !reached_end_of_stream {
             match play.container.read_packet()? {
                 Some(pkt) => {
-                    if pkt.stream_index() != 0 {
+                    if pkt.stream_index() as usize != play.video_index {
                         continue;
                     }
 
@@ -1,11 +1,12 @@
 use mixlab_protocol::{GateState, LineType, Terminal};
 
-use crate::engine::{self, InputRef, OutputRef};
+use crate::engine::{self, InputRef, OutputRef, Event, EventKind};
 use crate::module::ModuleT;
 
 #[derive(Debug)]
 pub struct Trigger {
     params: GateState,
+    fired: bool,
     inputs: Vec<Terminal>,
     outputs: Vec<Terminal>,
 }
@@ -18,8 +19,12 @@ impl ModuleT for Trigger {
     fn create(params: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
         (Self {
             params,
+            fired: false,
             inputs: vec![],
-            outputs: vec![LineType::Mono.unlabeled()]
+            outputs: vec![
+                LineType::Mono.labeled("Gate"),
+                LineType::Event.labeled("Event"),
+            ]
         }, ())
     }
 
@@ -33,17 +38,32 @@ impl ModuleT for Trigger {
     }
 
     fn run_tick(&mut self, _t: u64, _: &[InputRef], outputs: &mut [OutputRef]) -> Option<Self::Indication> {
-        let output = outputs[0].expect_mono();
+        let (gate, events) = match outputs {
+            [gate, events] => (gate.expect_mono(), events.expect_events()),
+            _ => unreachable!(),
+        };
 
         let value = match self.params {
             GateState::Open => 1.0,
             GateState::Closed => 0.0,
         };
 
-        for out in output.iter_mut() {
+        for out in gate.iter_mut() {
             *out = value;
         }
 
+        // fire a single trigger event on the first tick the gate is open:
+        match (&self.params, self.fired) {
+            (GateState::Open, false) => {
+                events.push(Event { offset: 0, kind: EventKind::Trigger });
+                self.fired = true;
+            }
+            (GateState::Closed, true) => {
+                self.fired = false;
+            }
+            _ => {}
+        }
+
         None
     }
 
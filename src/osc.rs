@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+
+use futures::stream::{self, StreamExt};
+use tokio::io;
+use tokio::net::UdpSocket;
+use tokio::net::udp::SendHalf;
+use tokio::sync::broadcast;
+
+use mixlab_protocol::{ClientSequence, ModuleId, ModuleParams, ServerUpdate, WorkspaceMessage, WorkspaceOp, WorkspaceState};
+
+use crate::engine::{EngineEvent, EngineSession};
+use crate::project::manager::ProjectManagerRef;
+use crate::project::ProjectHandle;
+use crate::util::Sequence;
+
+mod packet;
+mod params;
+
+use packet::Message;
+
+// large enough for the bundles sent by lighting desks:
+const MAX_PACKET: usize = 8192;
+
+// parameter feedback is sent to the addresses we most recently heard from:
+const MAX_PEERS: usize = 16;
+
+/// Starts listening for OSC messages addressed /module/<id>/<field> and
+/// applying them to module params. The field is a path into the module's
+/// params, eg. /module/3/gain or /module/4/bands/0/freq. Sending a message
+/// with no arguments replies with the current value of the field. Messages
+/// always apply to the active project.
+pub async fn start(addr: SocketAddr, projects: ProjectManagerRef) -> Result<SocketAddr, io::Error> {
+    let socket = UdpSocket::bind(addr).await?;
+    let local_addr = socket.local_addr()?;
+    tokio::spawn(run(socket, projects));
+    Ok(local_addr)
+}
+
+struct Osc {
+    engine: EngineSession,
+    seq: Sequence,
+    modules: HashMap<ModuleId, ModuleParams>,
+    // modules with updates of ours the engine has not yet synced, along with
+    // the sequence of the most recent one. our params are newer than the
+    // engine's for these:
+    pending: HashMap<ModuleId, NonZeroUsize>,
+    peers: Vec<SocketAddr>,
+    tx: SendHalf,
+}
+
+async fn run(socket: UdpSocket, projects: ProjectManagerRef) {
+    let mut projects = projects.watch();
+
+    let mut project = match projects.recv().await {
+        Some(project) => project,
+        None => return,
+    };
+
+    let (state, mut events, engine) = match project.connect_engine().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("osc: could not connect to engine: {:?}", e);
+            return;
+        }
+    };
+
+    let (rx, tx) = socket.split();
+
+    let mut osc = Osc {
+        engine,
+        seq: Sequence::new(),
+        modules: HashMap::new(),
+        pending: HashMap::new(),
+        peers: Vec::new(),
+        tx,
+    };
+
+    osc.load_state(state);
+
+    let mut packets = Box::pin(stream::unfold(rx, |mut rx| async move {
+        let mut buff = vec![0; MAX_PACKET];
+
+        let result = rx.recv_from(&mut buff).await
+            .map(|(len, addr)| {
+                buff.truncate(len);
+                (buff, addr)
+            });
+
+        Some((result, rx))
+    }));
+
+    enum Event {
+        Packet(Result<(Vec<u8>, SocketAddr), io::Error>),
+        Engine(Result<EngineEvent, broadcast::RecvError>),
+        Project(ProjectHandle),
+    }
+
+    loop {
+        let mut stream = stream::select(
+            packets.as_mut().map(Event::Packet),
+            stream::select(
+                events.by_ref().map(Event::Engine),
+                projects.by_ref().map(Event::Project)));
+
+        // runs until we lag behind the engine or the active project changes,
+        // either way we start over with a fresh engine session:
+        loop {
+            match stream.next().await {
+                Some(Event::Packet(Ok((packet, addr)))) => {
+                    osc.receive(&packet, addr).await;
+                }
+                Some(Event::Packet(Err(e))) => {
+                    // udp sockets report errors from previous sends here,
+                    // which are no reason to stop listening
+                    eprintln!("osc: recv error: {:?}", e);
+                }
+                Some(Event::Engine(Ok(event))) => {
+                    osc.engine_event(event).await;
+                }
+                Some(Event::Engine(Err(broadcast::RecvError::Lagged(_)))) => {
+                    break;
+                }
+                Some(Event::Project(next)) => {
+                    project = next;
+                    break;
+                }
+                Some(Event::Engine(Err(broadcast::RecvError::Closed))) | None => {
+                    return;
+                }
+            }
+        }
+
+        match project.connect_engine().await {
+            Ok((state, new_events, engine)) => {
+                osc.engine = engine;
+                osc.load_state(state);
+                events = new_events;
+            }
+            Err(e) => {
+                eprintln!("osc: could not connect to engine: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+impl Osc {
+    fn load_state(&mut self, state: WorkspaceState) {
+        self.modules = state.modules.into_iter().collect();
+        self.pending.clear();
+    }
+
+    async fn receive(&mut self, packet: &[u8], addr: SocketAddr) {
+        self.add_peer(addr);
+
+        let messages = match packet::decode(packet) {
+            Ok(messages) => messages,
+            Err(e) => {
+                eprintln!("osc: invalid packet from {}: {:?}", addr, e);
+                return;
+            }
+        };
+
+        for message in messages {
+            self.message(message, addr).await;
+        }
+    }
+
+    async fn message(&mut self, message: Message, addr: SocketAddr) {
+        let (module_id, field) = match parse_address(&message.address) {
+            Some(parsed) => parsed,
+            None => {
+                eprintln!("osc: unknown address {}", message.address);
+                return;
+            }
+        };
+
+        let params = match self.modules.get(&module_id) {
+            Some(params) => params,
+            None => return,
+        };
+
+        let arg = match message.args.first() {
+            Some(arg) => arg,
+            None => {
+                let value = params::get_field(params, field);
+
+                if let Some(value) = value {
+                    self.send(addr, Message { address: message.address.clone(), args: vec![value] }).await;
+                }
+
+                return;
+            }
+        };
+
+        let new_params = match params::set_field(params, field, arg) {
+            Some(new_params) => new_params,
+            None => {
+                eprintln!("osc: cannot set {} to {:?}", message.address, arg);
+                return;
+            }
+        };
+
+        let sequence = self.seq.next();
+
+        let msg = WorkspaceMessage {
+            sequence: ClientSequence(sequence),
+            op: WorkspaceOp::UpdateModuleParams(module_id, new_params.clone()),
+        };
+
+        if let Err(e) = self.engine.update(msg) {
+            eprintln!("osc: engine update failed: {:?}", e);
+            return;
+        }
+
+        // apply optimistically so that further messages build on this one
+        // rather than on the params the engine last told us about:
+        self.pending.insert(module_id, sequence);
+
+        if let Some(old_params) = self.modules.insert(module_id, new_params.clone()) {
+            // the sender already shows the new value:
+            self.feedback(module_id, &old_params, &new_params, Some(addr)).await;
+        }
+    }
+
+    async fn engine_event(&mut self, event: EngineEvent) {
+        match event {
+            EngineEvent::ServerUpdate(ServerUpdate::CreateModule { id, params, .. }) => {
+                self.modules.insert(id, params);
+            }
+            EngineEvent::ServerUpdate(ServerUpdate::UpdateModuleParams(id, params)) => {
+                if self.pending.contains_key(&id) {
+                    return;
+                }
+
+                if let Some(old_params) = self.modules.insert(id, params.clone()) {
+                    self.feedback(id, &old_params, &params, None).await;
+                }
+            }
+            EngineEvent::ServerUpdate(ServerUpdate::DeleteModule(id)) => {
+                self.modules.remove(&id);
+                self.pending.remove(&id);
+            }
+            EngineEvent::ServerUpdate(_) => {}
+            EngineEvent::Sync(clock) => {
+                if clock.0 == self.engine.session_id() {
+                    let synced = (clock.1).0;
+                    self.pending.retain(|_, sequence| *sequence > synced);
+                }
+            }
+        }
+    }
+
+    async fn feedback(&mut self, module_id: ModuleId, old: &ModuleParams, new: &ModuleParams, except: Option<SocketAddr>) {
+        for (field, value) in params::changed_fields(old, new) {
+            let message = Message {
+                address: format!("/module/{}{}", module_id.0, field),
+                args: vec![value],
+            };
+
+            for peer in self.peers.clone() {
+                if Some(peer) != except {
+                    self.send(peer, message.clone()).await;
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, addr: SocketAddr, message: Message) {
+        if let Err(e) = self.tx.send_to(&packet::encode(&message), &addr).await {
+            eprintln!("osc: could not send to {}: {:?}", addr, e);
+        }
+    }
+
+    fn add_peer(&mut self, addr: SocketAddr) {
+        self.peers.retain(|peer| *peer != addr);
+        self.peers.push(addr);
+
+        if self.peers.len() > MAX_PEERS {
+            self.peers.remove(0);
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Option<(ModuleId, &str)> {
+    const PREFIX: &str = "/module/";
+
+    if !address.starts_with(PREFIX) {
+        return None;
+    }
+
+    let rest = &address[PREFIX.len()..];
+    let (id, field) = rest.split_at(rest.find('/')?);
+    let id = id.parse().ok()?;
+
+    Some((ModuleId(id), field))
+}
@@ -0,0 +1,233 @@
+// Encoding and decoding of OSC 1.0 packets. Only the argument types sent by
+// common control surfaces are supported.
+
+use std::convert::TryInto;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub address: String,
+    pub args: Vec<Arg>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    True,
+    False,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Truncated,
+    InvalidString,
+    InvalidTypeTags,
+    UnsupportedType(char),
+}
+
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+
+/// Decodes a packet into the messages it contains. Bundles are flattened and
+/// their time tags ignored, messages are applied as soon as they arrive
+pub fn decode(packet: &[u8]) -> Result<Vec<Message>, DecodeError> {
+    let mut messages = Vec::new();
+    decode_into(packet, &mut messages)?;
+    Ok(messages)
+}
+
+fn decode_into(packet: &[u8], messages: &mut Vec<Message>) -> Result<(), DecodeError> {
+    if packet.starts_with(BUNDLE_TAG) {
+        // skip the time tag following the bundle tag:
+        let mut reader = Reader::new(packet);
+        reader.take(BUNDLE_TAG.len() + 8)?;
+
+        while !reader.is_empty() {
+            let len = reader.int()?;
+            let len = len.try_into().map_err(|_| DecodeError::Truncated)?;
+            decode_into(reader.take(len)?, messages)?;
+        }
+
+        Ok(())
+    } else {
+        messages.push(decode_message(packet)?);
+        Ok(())
+    }
+}
+
+fn decode_message(packet: &[u8]) -> Result<Message, DecodeError> {
+    let mut reader = Reader::new(packet);
+
+    let address = reader.string()?;
+
+    // very old implementations omit type tags entirely for messages without
+    // arguments:
+    if reader.is_empty() {
+        return Ok(Message { address, args: Vec::new() });
+    }
+
+    let type_tags = reader.string()?;
+
+    if !type_tags.starts_with(',') {
+        return Err(DecodeError::InvalidTypeTags);
+    }
+
+    let args = type_tags[1..].chars()
+        .map(|tag| match tag {
+            'i' => Ok(Arg::Int(reader.int()?)),
+            'h' => Ok(Arg::Long(i64::from_be_bytes(reader.bytes8()?))),
+            'f' => Ok(Arg::Float(f32::from_be_bytes(reader.bytes4()?))),
+            'd' => Ok(Arg::Double(f64::from_be_bytes(reader.bytes8()?))),
+            's' => Ok(Arg::String(reader.string()?)),
+            'T' => Ok(Arg::True),
+            'F' => Ok(Arg::False),
+            _ => Err(DecodeError::UnsupportedType(tag)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Message { address, args })
+}
+
+pub fn encode(message: &Message) -> Vec<u8> {
+    let mut packet = Vec::new();
+
+    write_string(&mut packet, &message.address);
+
+    let mut type_tags = String::from(",");
+    let mut args = Vec::new();
+
+    for arg in &message.args {
+        match arg {
+            Arg::Int(value) => {
+                type_tags.push('i');
+                args.extend_from_slice(&value.to_be_bytes());
+            }
+            Arg::Long(value) => {
+                type_tags.push('h');
+                args.extend_from_slice(&value.to_be_bytes());
+            }
+            Arg::Float(value) => {
+                type_tags.push('f');
+                args.extend_from_slice(&value.to_be_bytes());
+            }
+            Arg::Double(value) => {
+                type_tags.push('d');
+                args.extend_from_slice(&value.to_be_bytes());
+            }
+            Arg::String(value) => {
+                type_tags.push('s');
+                write_string(&mut args, value);
+            }
+            Arg::True => {
+                type_tags.push('T');
+            }
+            Arg::False => {
+                type_tags.push('F');
+            }
+        }
+    }
+
+    write_string(&mut packet, &type_tags);
+    packet.extend_from_slice(&args);
+    packet
+}
+
+// strings are null terminated and padded to a multiple of four bytes:
+fn write_string(buff: &mut Vec<u8>, s: &str) {
+    buff.extend_from_slice(s.as_bytes());
+    buff.push(0);
+
+    while buff.len() % 4 != 0 {
+        buff.push(0);
+    }
+}
+
+struct Reader<'a> {
+    buff: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(buff: &'a [u8]) -> Self {
+        Reader { buff }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buff.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.buff.len() {
+            return Err(DecodeError::Truncated);
+        }
+
+        let (taken, rest) = self.buff.split_at(len);
+        self.buff = rest;
+        Ok(taken)
+    }
+
+    fn bytes4(&mut self) -> Result<[u8; 4], DecodeError> {
+        Ok(self.take(4)?.try_into().expect("slice of 4 bytes"))
+    }
+
+    fn bytes8(&mut self) -> Result<[u8; 8], DecodeError> {
+        Ok(self.take(8)?.try_into().expect("slice of 8 bytes"))
+    }
+
+    fn int(&mut self) -> Result<i32, DecodeError> {
+        Ok(i32::from_be_bytes(self.bytes4()?))
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.buff.iter()
+            .position(|b| *b == 0)
+            .ok_or(DecodeError::InvalidString)?;
+
+        let padded_len = (len + 4) & !3;
+        let bytes = self.take(padded_len)?;
+
+        String::from_utf8(bytes[0..len].to_vec())
+            .map_err(|_| DecodeError::InvalidString)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_message() {
+        let message = Message {
+            address: "/module/1/gain".to_owned(),
+            args: vec![Arg::Float(0.5), Arg::Int(-3), Arg::String("hi".to_owned()), Arg::True],
+        };
+
+        let packet = encode(&message);
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(decode(&packet).unwrap(), vec![message]);
+    }
+
+    #[test]
+    fn decode_bundle() {
+        let a = Message { address: "/a".to_owned(), args: vec![Arg::Int(1)] };
+        let b = Message { address: "/b".to_owned(), args: vec![Arg::Double(2.0)] };
+
+        let mut packet = BUNDLE_TAG.to_vec();
+        packet.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        for message in &[&a, &b] {
+            let encoded = encode(message);
+            packet.extend_from_slice(&(encoded.len() as i32).to_be_bytes());
+            packet.extend_from_slice(&encoded);
+        }
+
+        assert_eq!(decode(&packet).unwrap(), vec![a, b]);
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let packet = encode(&Message { address: "/a".to_owned(), args: vec![Arg::Int(1)] });
+        assert!(decode(&packet[0..packet.len() - 2]).is_err());
+    }
+}
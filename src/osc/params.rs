@@ -0,0 +1,126 @@
+// Fields of module params are addressed by JSON pointer into the serialized
+// params, the same way macro targets are. For example /module/3/gain
+// addresses the gain field of module 3's params.
+
+use std::convert::TryInto;
+
+use serde_json::{Number, Value};
+
+use mixlab_protocol::ModuleParams;
+
+use crate::osc::packet::Arg;
+
+/// Sets the field at the pointer from an OSC argument, converting it to the
+/// type of the field. Returns None if there is no such field or the argument
+/// cannot be converted, fields never change type.
+pub fn set_field(params: &ModuleParams, pointer: &str, arg: &Arg) -> Option<ModuleParams> {
+    let mut serialized = serde_json::to_value(params).expect("serialize ModuleParams");
+
+    {
+        let field = contents_mut(&mut serialized)?.pointer_mut(pointer)?;
+        *field = convert(field, arg)?;
+    }
+
+    serde_json::from_value(serialized).ok()
+}
+
+/// Returns the current value of the field at the pointer
+pub fn get_field(params: &ModuleParams, pointer: &str) -> Option<Arg> {
+    let mut serialized = serde_json::to_value(params).expect("serialize ModuleParams");
+    to_arg(contents_mut(&mut serialized)?.pointer(pointer)?)
+}
+
+/// Returns the pointer and new value of every field which differs between
+/// the two params
+pub fn changed_fields(old: &ModuleParams, new: &ModuleParams) -> Vec<(String, Arg)> {
+    let mut old = serde_json::to_value(old).expect("serialize ModuleParams");
+    let mut new = serde_json::to_value(new).expect("serialize ModuleParams");
+
+    let mut changed = Vec::new();
+
+    if let (Some(old), Some(new)) = (contents_mut(&mut old), contents_mut(&mut new)) {
+        diff(String::new(), old, new, &mut changed);
+    }
+
+    changed
+}
+
+// ModuleParams is externally tagged, so fields are addressed relative to the
+// variant's contents:
+fn contents_mut(serialized: &mut Value) -> Option<&mut Value> {
+    match serialized {
+        Value::Object(variant) if variant.len() == 1 => variant.values_mut().next(),
+        _ => None,
+    }
+}
+
+fn diff(pointer: String, old: &Value, new: &Value, changed: &mut Vec<(String, Arg)>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, new_value) in new {
+                if let Some(old_value) = old.get(key) {
+                    diff(format!("{}/{}", pointer, key), old_value, new_value, changed);
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for (index, (old_value, new_value)) in old.iter().zip(new).enumerate() {
+                diff(format!("{}/{}", pointer, index), old_value, new_value, changed);
+            }
+        }
+        _ if old != new => {
+            if let Some(arg) = to_arg(new) {
+                changed.push((pointer, arg));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn convert(field: &Value, arg: &Arg) -> Option<Value> {
+    let number = match arg {
+        Arg::Int(value) => Some(*value as f64),
+        Arg::Long(value) => Some(*value as f64),
+        Arg::Float(value) => Some(*value as f64),
+        Arg::Double(value) => Some(*value),
+        _ => None,
+    };
+
+    match (field, arg) {
+        (Value::Bool(_), Arg::True) => Some(Value::Bool(true)),
+        (Value::Bool(_), Arg::False) => Some(Value::Bool(false)),
+        // toggles on most control surfaces send 1 and 0:
+        (Value::Bool(_), _) => Some(Value::Bool(number? != 0.0)),
+        (Value::String(_), Arg::String(value)) => Some(Value::String(value.clone())),
+        (Value::Number(field), _) if field.is_u64() => {
+            let number = number?.round();
+
+            if number < 0.0 {
+                return None;
+            }
+
+            Some(Value::Number(Number::from(number as u64)))
+        }
+        (Value::Number(field), _) if field.is_i64() => {
+            Some(Value::Number(Number::from(number?.round() as i64)))
+        }
+        (Value::Number(_), _) => {
+            Some(Value::Number(Number::from_f64(number?)?))
+        }
+        _ => None,
+    }
+}
+
+fn to_arg(value: &Value) -> Option<Arg> {
+    match value {
+        // many control surfaces do not understand the T and F types:
+        Value::Bool(value) => Some(Arg::Int(*value as i32)),
+        Value::Number(number) if number.is_f64() => Some(Arg::Float(number.as_f64()? as f32)),
+        Value::Number(number) => {
+            let number = number.as_i64()?;
+            Some(number.try_into().map(Arg::Int).unwrap_or(Arg::Long(number)))
+        }
+        Value::String(value) => Some(Arg::String(value.clone())),
+        _ => None,
+    }
+}
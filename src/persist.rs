@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use serde::{Serialize, Deserialize};
 
-use mixlab_protocol::{ModuleId, ModuleParams, OutputId, WindowGeometry};
+use mixlab_protocol::{ModuleId, ModuleParams, OutputId, WindowGeometry, MacroId, MacroParams, MidiTarget, MidiBinding};
 
 use crate::util::Sequence;
 
@@ -10,11 +10,20 @@ use crate::util::Sequence;
 pub struct Workspace {
     pub module_seq: Sequence,
     pub modules: HashMap<ModuleId, Module>,
+    #[serde(default)]
+    pub macro_seq: Sequence,
+    #[serde(default)]
+    pub macros: HashMap<MacroId, MacroParams>,
+    // a list as MidiTarget cannot be a JSON object key:
+    #[serde(default)]
+    pub midi: Vec<(MidiTarget, MidiBinding)>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Module {
     pub params: ModuleParams,
     pub geometry: WindowGeometry,
+    #[serde(default)]
+    pub bypass: bool,
     pub inputs: Vec<Option<OutputId>>,
 }
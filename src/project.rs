@@ -1,6 +1,9 @@
+use std::collections::HashSet;
 use std::fmt::{self, Debug};
+use std::ops::Deref;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use derive_more::From;
 use futures::stream::{Stream, StreamExt};
@@ -9,20 +12,34 @@ use tokio::sync::watch;
 use tokio::{io, task, runtime};
 
 use mixlab_protocol as protocol;
-use mixlab_protocol::{WorkspaceState, PerformanceInfo};
+use mixlab_protocol::{WorkspaceState, PerformanceInfo, MediaId, MidiTarget, ModuleId, SceneId, SceneRecall, PresetId, PresetRef, PeerId, PresenceUpdate, Role};
 
 use crate::db;
-use crate::engine::{self, EngineHandle, EngineEvents, EngineError, EngineSession, WorkspaceEmbryo};
+use crate::engine::{self, EngineHandle, EngineEvents, EngineError, EngineSession, MorphTiming, WorkspaceEmbryo};
 use crate::persist;
 
+use presence::{Presence, PresenceRef, PresenceEvents, PeerHandle};
+use stream::StorageBackend;
+use stream::object::ObjectStore;
+
+pub mod bundle;
 pub mod stream;
+pub mod manager;
 pub mod media;
+pub mod presence;
+pub mod preset;
+pub mod scene;
+pub mod subgraph;
 
 #[derive(Clone)]
-pub struct ProjectHandle {
+pub struct ProjectHandle(Arc<Project>);
+
+pub struct Project {
+    name: String,
     base: ProjectBaseRef,
     engine: EngineHandle,
     notify: NotifyRx,
+    presence: PresenceRef,
 }
 
 pub struct ProjectBase {
@@ -33,7 +50,19 @@ pub struct ProjectBase {
     // in the blocking context and pass it as an Arc rather than a reference
     database: Arc<std::sync::Mutex<Connection>>,
 
+    // streams currently being written, only touched while holding the
+    // database lock:
+    writing: std::sync::Mutex<HashSet<stream::StreamId>>,
+
+    storage: StorageBackend,
+    objects: ObjectStore,
+
     notify: NotifyTx,
+
+    // never sent on, the sender is only dropped along with the project, see
+    // Closed:
+    _closed_tx: watch::Sender<()>,
+    closed_rx: watch::Receiver<()>,
 }
 
 impl Debug for ProjectBase {
@@ -44,6 +73,24 @@ impl Debug for ProjectBase {
 
 pub type ProjectBaseRef = Arc<ProjectBase>;
 
+impl Deref for ProjectHandle {
+    type Target = Project;
+
+    fn deref(&self) -> &Project {
+        &self.0
+    }
+}
+
+/// Resolves once a project's base has been dropped, at which point its engine
+/// has stopped and nothing is using its database any longer
+pub struct Closed(watch::Receiver<()>);
+
+impl Closed {
+    pub async fn wait(mut self) {
+        while self.0.recv().await.is_some() {}
+    }
+}
+
 #[derive(From, Debug)]
 pub enum OpenError {
     Io(io::Error),
@@ -58,6 +105,10 @@ impl ProjectBase {
         f(&mut self.database.lock().expect("lock sqlite connection"))
     }
 
+    pub fn closed(&self) -> Closed {
+        Closed(self.closed_rx.clone())
+    }
+
     pub async fn with_database<T: Send + 'static>(&self, f: impl FnOnce(&mut Connection) -> T + Send + 'static) -> T {
         let conn = self.database.clone();
         task::spawn_blocking(move || {
@@ -65,18 +116,78 @@ impl ProjectBase {
         }).await.expect("blocking database section")
     }
 
-    async fn attach(path: PathBuf, notify: NotifyTx) -> Result<Self, rusqlite::Error> {
+    async fn attach(path: PathBuf, storage: StorageBackend, notify: NotifyTx) -> Result<Self, rusqlite::Error> {
         let mut sqlite_path = path.clone();
         sqlite_path.set_extension("mixlab");
         let database = db::attach(sqlite_path).await?;
+        let (closed_tx, closed_rx) = watch::channel(());
+
+        let mut objects_path = path.clone();
+        objects_path.set_extension("mixlab-objects");
 
         Ok(ProjectBase {
             path,
             database: Arc::new(std::sync::Mutex::new(database)),
+            writing: std::sync::Mutex::new(HashSet::new()),
+            storage,
+            objects: ObjectStore::new(objects_path),
             notify,
+            _closed_tx: closed_tx,
+            closed_rx,
         })
     }
 
+    /// Opens another project for reading alongside the open one. Returns None
+    /// if it was last opened by an older version and needs migrating first
+    async fn attach_read_only(path: PathBuf) -> Result<Option<Self>, rusqlite::Error> {
+        let mut sqlite_path = path.clone();
+        sqlite_path.set_extension("mixlab");
+
+        let database = match db::open_read_only(sqlite_path).await? {
+            Some(database) => database,
+            None => return Ok(None),
+        };
+
+        let mut objects_path = path.clone();
+        objects_path.set_extension("mixlab-objects");
+
+        // nothing is ever written, so neither storage nor notifications
+        // are of any consequence:
+        let (notify, _) = notify();
+        let (closed_tx, closed_rx) = watch::channel(());
+
+        Ok(Some(ProjectBase {
+            path,
+            database: Arc::new(std::sync::Mutex::new(database)),
+            writing: std::sync::Mutex::new(HashSet::new()),
+            storage: StorageBackend::Database,
+            objects: ObjectStore::new(objects_path),
+            notify,
+            _closed_tx: closed_tx,
+            closed_rx,
+        }))
+    }
+
+    /// Project backed by a fresh in-memory database, for tests
+    #[cfg(test)]
+    pub async fn in_memory() -> Result<ProjectBaseRef, rusqlite::Error> {
+        let path = PathBuf::from(":memory:");
+        let database = db::attach(path.clone()).await?;
+        let (notify, _) = notify();
+        let (closed_tx, closed_rx) = watch::channel(());
+
+        Ok(Arc::new(ProjectBase {
+            objects: ObjectStore::new(path.clone()),
+            path,
+            database: Arc::new(std::sync::Mutex::new(database)),
+            writing: std::sync::Mutex::new(HashSet::new()),
+            storage: StorageBackend::Database,
+            notify,
+            _closed_tx: closed_tx,
+            closed_rx,
+        }))
+    }
+
     async fn read_workspace(&self) -> Result<persist::Workspace, OpenError> {
         let serialized = self.with_database(|conn| -> Result<Option<Vec<u8>>, rusqlite::Error> {
             conn.query_row("SELECT serialized FROM workspace WHERE rowid = 1", rusqlite::NO_PARAMS,
@@ -106,12 +217,34 @@ impl ProjectBase {
     }
 }
 
-pub async fn open_or_create(path: PathBuf) -> Result<ProjectHandle, OpenError> {
+pub async fn open_or_create(path: PathBuf, storage: StorageBackend) -> Result<ProjectHandle, OpenError> {
     let (notify_tx, notify_rx) = notify();
-    let base = ProjectBase::attach(path, notify_tx).await?;
+    let base = Arc::new(ProjectBase::attach(path, storage, notify_tx).await?);
+    start(base, notify_rx).await
+}
+
+/// Creates a new project from a bundle written by export, see bundle. The
+/// project is closed again once imported, so that it can be moved into place.
+/// Previews the bundle had none for are generated once it is opened
+pub async fn import(path: PathBuf, storage: StorageBackend, bundle: impl std::io::Read + Send + 'static) -> Result<(), bundle::BundleError> {
+    let (notify_tx, _) = notify();
+    let (base, _) = bundle::create_from_bundle(path, storage, notify_tx, bundle).await?;
+    bundle::close(base).await;
+    Ok(())
+}
+
+async fn start(base: ProjectBaseRef, notify_rx: NotifyRx) -> Result<ProjectHandle, OpenError> {
+    let name = manager::project_name(&base.path).unwrap_or_default();
+    let storage = base.storage;
     let workspace = base.read_workspace().await?;
 
-    let base = Arc::new(base);
+    // only ever the case when asked for, media stays in the database unless
+    // files were passed explicitly:
+    if storage == StorageBackend::Files {
+        task::spawn(stream::migrate_to_files(base.clone()));
+    }
+
+    task::spawn(media::preview::backfill(base.clone()));
 
     // start engine update thread
     let (embryo, mut persist_rx) = WorkspaceEmbryo::new(workspace);
@@ -131,56 +264,230 @@ pub async fn open_or_create(path: PathBuf) -> Result<ProjectHandle, OpenError> {
         }
     });
 
-    Ok(ProjectHandle {
+    Ok(ProjectHandle(Arc::new(Project {
+        name,
         base,
         engine,
         notify: notify_rx,
-    })
+        presence: Presence::new(),
+    })))
 }
 
 impl ProjectHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub async fn connect_engine(&self) -> Result<(WorkspaceState, EngineEvents, EngineSession), EngineError> {
         self.engine.connect().await
     }
 
+    pub async fn ping_engine(&self) -> Result<(), EngineError> {
+        self.engine.ping().await
+    }
+
+    pub fn latest_performance_info(&self) -> Option<Arc<PerformanceInfo>> {
+        self.engine.latest_performance_info()
+    }
+
+    pub async fn check_database(&self) -> Result<(), rusqlite::Error> {
+        self.base.with_database(|conn| {
+            conn.query_row("SELECT 1", rusqlite::NO_PARAMS, |_| Ok(()))
+        }).await
+    }
+
     pub fn notifications(&self) -> impl Stream<Item = Notification> {
         let perf_info = self.engine.performance_info().map(Notification::PerformanceInfo);
         let media = self.notify.media.clone().map(|()| Notification::MediaLibrary);
-        futures::stream::select(perf_info, media)
+        let scenes = self.notify.scenes.clone().map(|()| Notification::SceneLibrary);
+        let presets = self.notify.presets.clone().map(|()| Notification::PresetLibrary);
+        futures::stream::select(perf_info, futures::stream::select(media, futures::stream::select(scenes, presets)))
+    }
+
+    pub fn join_presence(&self, role: Role) -> (PeerHandle, PresenceEvents) {
+        self.presence.join(role)
+    }
+
+    pub fn presence_snapshot(&self, you: PeerId) -> PresenceUpdate {
+        self.presence.snapshot(you)
     }
 
     pub async fn begin_media_upload(&self, info: media::UploadInfo) -> Result<media::MediaUpload, media::UploadError> {
         media::MediaUpload::new(self.base.clone(), info).await
     }
 
+    pub async fn create_upload(&self, info: media::UploadInfo) -> Result<media::upload::UploadId, media::UploadError> {
+        media::upload::create(&self.base, info).await
+    }
+
+    pub async fn upload_offset(&self, upload_id: media::upload::UploadId) -> Result<Option<u64>, media::UploadError> {
+        media::upload::offset(&self.base, upload_id).await
+    }
+
+    pub async fn resume_upload(&self, upload_id: media::upload::UploadId, offset: u64) -> Result<media::upload::ChunkUpload, media::UploadError> {
+        media::upload::resume(&self.base, upload_id, offset).await
+    }
+
+    pub async fn finalize_upload(&self, upload_id: media::upload::UploadId) -> Result<MediaId, media::UploadError> {
+        media::upload::finalize(&self.base, upload_id).await
+    }
+
+    pub async fn abort_upload(&self, upload_id: media::upload::UploadId) -> Result<(), media::UploadError> {
+        media::upload::abort(&self.base, upload_id).await
+    }
+
     pub async fn fetch_media_library(&self) -> Result<protocol::MediaLibrary, rusqlite::Error> {
         media::library(&self.base).await
     }
+
+    pub async fn open_media_download(&self, media_id: MediaId) -> Result<Option<media::MediaDownload>, rusqlite::Error> {
+        media::open_download(self.base.clone(), media_id).await
+    }
+
+    pub async fn media_thumbnail(&self, media_id: MediaId) -> Result<Option<Vec<u8>>, rusqlite::Error> {
+        media::preview::thumbnail(&self.base, media_id).await
+    }
+
+    pub async fn media_waveform(&self, media_id: MediaId) -> Result<Option<Vec<media::preview::Peak>>, rusqlite::Error> {
+        media::preview::waveform(&self.base, media_id).await
+    }
+
+    pub async fn rename_media(&self, media_id: MediaId, name: String) -> Result<(), media::MediaError> {
+        media::rename(&self.base, media_id, name).await
+    }
+
+    /// Refuses to delete media which a module in the workspace is playing,
+    /// or which a saved scene or preset refers to. Modules which pick the
+    /// media up while it is being deleted, for instance from a scene recalled
+    /// in the meantime, are stopped once it is gone.
+    pub async fn delete_media(&self, media_id: MediaId) -> Result<(), media::MediaError> {
+        if self.engine.media_in_use(media_id).await? {
+            return Err(media::MediaError::InUse);
+        }
+
+        media::delete(&self.base, media_id).await?;
+        self.engine.forget_media(media_id)?;
+        Ok(())
+    }
+
+    pub async fn collect_garbage(&self) -> Result<usize, rusqlite::Error> {
+        media::collect_garbage(&self.base).await
+    }
+
+    /// Inserts modules from another project into the workspace, see subgraph
+    pub async fn import_modules(&self, source: &subgraph::SourceProject, modules: Vec<ModuleId>) -> Result<(), subgraph::SubgraphError> {
+        let modules = subgraph::extract(&self.base, source, &modules).await?;
+        self.engine.insert_modules(modules).await?;
+        Ok(())
+    }
+
+    pub async fn export_bundle(&self, all_media: bool, out: impl std::io::Write + Send + 'static) -> Result<(), bundle::BundleError> {
+        bundle::export(&self.base, self.name.clone(), all_media, out).await
+    }
+
+    pub async fn save_scene(&self, name: String, modules: Vec<ModuleId>) -> Result<SceneId, scene::SceneError> {
+        let params = self.engine.capture_params(modules).await?;
+        Ok(scene::save(&self.base, name, &params).await?)
+    }
+
+    pub async fn delete_scene(&self, scene_id: SceneId) -> Result<(), scene::SceneError> {
+        scene::delete(&self.base, scene_id).await?;
+
+        // scene ids can be reused, don't leave a mapping behind for the next
+        // scene saved:
+        self.engine.unmap_midi(MidiTarget::Scene(scene_id))?;
+        Ok(())
+    }
+
+    pub async fn recall_scene(&self, scene_id: SceneId, recall: SceneRecall) -> Result<(), scene::SceneError> {
+        let params = scene::load(&self.base, scene_id).await?
+            .ok_or(scene::SceneError::NoSuchScene)?;
+
+        self.engine.morph_params(params, MorphTiming {
+            delay: Duration::from_micros(recall.delay.0),
+            duration: Duration::from_micros(recall.morph.0),
+        })?;
+
+        Ok(())
+    }
+
+    pub async fn fetch_scene_library(&self) -> Result<protocol::SceneLibrary, rusqlite::Error> {
+        scene::library(&self.base).await
+    }
+
+    pub async fn save_preset(&self, module_id: ModuleId, name: String) -> Result<PresetId, preset::PresetError> {
+        let (_, params) = self.engine.capture_params(vec![module_id]).await?
+            .pop()
+            .ok_or(preset::PresetError::NoSuchModule)?;
+
+        Ok(preset::save(&self.base, name, &params).await?)
+    }
+
+    pub async fn delete_preset(&self, preset_id: PresetId) -> Result<(), preset::PresetError> {
+        Ok(preset::delete(&self.base, preset_id).await?)
+    }
+
+    /// Presets only apply to modules of the kind they were saved from
+    pub async fn apply_preset(&self, module_id: ModuleId, preset_ref: PresetRef) -> Result<(), preset::PresetError> {
+        let (_, current) = self.engine.capture_params(vec![module_id]).await?
+            .pop()
+            .ok_or(preset::PresetError::NoSuchModule)?;
+
+        let params = preset::load(&self.base, current.kind(), preset_ref).await?
+            .ok_or(preset::PresetError::NoSuchPreset)?;
+
+        let params = preset::fit(&current, params);
+
+        // an instant morph, which is dropped if the module is replaced by one
+        // of a different kind in the meantime:
+        self.engine.morph_params(vec![(module_id, params)], MorphTiming {
+            delay: Duration::from_micros(0),
+            duration: Duration::from_micros(0),
+        })?;
+
+        Ok(())
+    }
+
+    pub async fn fetch_preset_library(&self) -> Result<protocol::PresetLibrary, rusqlite::Error> {
+        preset::library(&self.base).await
+    }
 }
 
 pub enum Notification {
     PerformanceInfo(Arc<PerformanceInfo>),
     MediaLibrary,
+    SceneLibrary,
+    PresetLibrary,
 }
 
 pub struct NotifyTx {
     media: watch::Sender<()>,
+    scenes: watch::Sender<()>,
+    presets: watch::Sender<()>,
 }
 
 #[derive(Clone)]
 pub struct NotifyRx {
     media: watch::Receiver<()>,
+    scenes: watch::Receiver<()>,
+    presets: watch::Receiver<()>,
 }
 
 pub fn notify() -> (NotifyTx, NotifyRx) {
     let (media_tx, media_rx) = watch::channel(());
+    let (scenes_tx, scenes_rx) = watch::channel(());
+    let (presets_tx, presets_rx) = watch::channel(());
 
     let tx = NotifyTx {
         media: media_tx,
+        scenes: scenes_tx,
+        presets: presets_tx,
     };
 
     let rx = NotifyRx {
         media: media_rx,
+        scenes: scenes_rx,
+        presets: presets_rx,
     };
 
     (tx, rx)
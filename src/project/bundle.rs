@@ -0,0 +1,586 @@
+// Portable bundles of a project, for moving a workspace and its media between
+// machines. A bundle is a tar archive of:
+//
+//   manifest.json             format version, project name and media items
+//   workspace.json            the workspace as persisted
+//   scenes.json               saved scenes
+//   presets.json              presets saved in the project
+//   media/<sha256>            content of each media item, once per content
+//   previews/<sha256>.png     thumbnail, when the media has one
+//   previews/<sha256>.json    waveform overview, when the media has one
+//
+// Media ids are local to a project, so importing assigns new ones and rewrites
+// the media source modules in the workspace, scenes and presets to match.
+// Scenes and presets keep their ids, MIDI mappings in the workspace refer to
+// scenes by id.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use derive_more::From;
+use futures::executor::block_on;
+use mixlab_codec::ffmpeg::IoReader;
+use mixlab_protocol::{MediaId, ModuleParams, PresetId, SceneId};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use tokio::sync::mpsc;
+use tokio::task;
+
+use crate::persist;
+use crate::project::{manager, notify, NotifyTx, OpenError, ProjectBase, ProjectBaseRef};
+use crate::project::media::{self, preview, UploadError, UploadInfo};
+use crate::project::media::preview::StoredPreviews;
+use crate::project::{preset, scene};
+use crate::project::scene::SceneParams;
+use crate::project::stream::{self, ReadStream, StorageBackend, StreamError, StreamId, WriteStream};
+
+const VERSION: u32 = 1;
+
+// bytes of media content passed from the tar reader at a time
+const IMPORT_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(From, Debug)]
+pub enum BundleError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Database(rusqlite::Error),
+    Stream(StreamError),
+    Media(UploadError),
+    Open(OpenError),
+    NoManifest,
+    NoWorkspace,
+    UnsupportedVersion(u32),
+    // carries the hash of the content missing from the bundle, or the id of
+    // the stream missing from the project:
+    MissingMedia(String),
+    NoSuchProject,
+    AlreadyExists,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    project: String,
+    media: Vec<ManifestMedia>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestMedia {
+    id: MediaId,
+    name: String,
+    kind: String,
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundledScene {
+    id: SceneId,
+    name: String,
+    modules: SceneParams,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundledPreset {
+    id: PresetId,
+    name: String,
+    params: ModuleParams,
+}
+
+#[derive(StructOpt)]
+pub struct ExportOpts {
+    /// Project to export
+    project_path: PathBuf,
+    /// Bundle to write
+    bundle_path: PathBuf,
+    /// Include everything in the media library, not only media used by the
+    /// workspace, scenes or presets
+    #[structopt(long)]
+    all_media: bool,
+}
+
+#[derive(StructOpt)]
+pub struct ImportOpts {
+    /// Bundle written by export
+    bundle_path: PathBuf,
+    /// Project to create, which must not exist yet
+    project_path: PathBuf,
+    /// Where to keep the imported media, as when running the server
+    #[structopt(long, default_value = "database")]
+    media_storage: StorageBackend,
+}
+
+pub async fn export_command(opts: ExportOpts) -> Result<(), BundleError> {
+    if !database_path(&opts.project_path).is_file() {
+        return Err(BundleError::NoSuchProject);
+    }
+
+    let name = manager::project_name(&opts.project_path).unwrap_or_default();
+
+    // exporting never writes new streams, so storage is of no consequence:
+    let (notify_tx, _) = notify();
+    let base = Arc::new(ProjectBase::attach(opts.project_path, StorageBackend::Database, notify_tx).await?);
+
+    let bundle = BufWriter::new(File::create(&opts.bundle_path)?);
+    export(&base, name, opts.all_media, bundle).await
+}
+
+pub async fn import_command(opts: ImportOpts) -> Result<(), BundleError> {
+    if database_path(&opts.project_path).exists() {
+        return Err(BundleError::AlreadyExists);
+    }
+
+    let bundle = BufReader::new(File::open(&opts.bundle_path)?);
+    let (notify_tx, _) = notify();
+    let (base, pending) = create_from_bundle(opts.project_path, opts.media_storage, notify_tx, bundle).await?;
+
+    // the process exits once the import returns, so generate previews for
+    // media the bundle had none for now rather than in the background:
+    for (media_id, stream_id) in pending {
+        preview::generate(base.clone(), media_id, stream_id).await;
+    }
+
+    Ok(())
+}
+
+/// Writes the project to `out` as a bundle. Only media used by the
+/// workspace, scenes or presets is included unless `all_media` is set
+pub async fn export(base: &ProjectBaseRef, project: String, all_media: bool, out: impl Write + Send + 'static) -> Result<(), BundleError> {
+    let workspace = base.read_workspace().await?;
+    let scenes = read_scenes(base).await?;
+    let presets = read_presets(base).await?;
+    let used = media_used(&workspace, &scenes, &presets);
+
+    let items = base.with_database(|conn| -> Result<Vec<_>, rusqlite::Error> {
+        conn.prepare("SELECT id, name, kind, stream_id FROM media ORDER BY id")?
+            .query_map(rusqlite::NO_PARAMS, |row| Ok((
+                MediaId(row.get(0)?),
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                StreamId(row.get(3)?),
+            )))?
+            .collect()
+    }).await?;
+
+    let mut manifest = Manifest { version: VERSION, project, media: Vec::new() };
+    let mut contents = Vec::new();
+    let mut hashes = HashSet::new();
+
+    for (media_id, name, kind, stream_id) in items {
+        if !all_media && !used.contains(&media_id) {
+            continue;
+        }
+
+        // media uploaded before streams were hashed is hashed here:
+        let sha256 = stream::seal(base.clone(), stream_id).await?
+            .ok_or_else(|| BundleError::MissingMedia(format!("{:?}", stream_id)))?;
+
+        let mut stream = ReadStream::open(base.clone(), stream_id).await?
+            .ok_or_else(|| BundleError::MissingMedia(sha256.clone()))?;
+
+        let size = IoReader::size(&mut stream)?;
+
+        manifest.media.push(ManifestMedia {
+            id: media_id,
+            name,
+            kind,
+            size,
+            sha256: sha256.clone(),
+        });
+
+        if hashes.insert(sha256.clone()) {
+            let previews = preview::load(base, media_id).await?;
+            contents.push(Content { sha256, size, stream, previews });
+        }
+    }
+
+    // ReadStream reads synchronously:
+    task::spawn_blocking(move || write_bundle(out, &manifest, &workspace, &scenes, &presets, contents)).await
+        .expect("join blocking task")
+}
+
+struct Content {
+    sha256: String,
+    size: u64,
+    stream: ReadStream,
+    previews: Option<StoredPreviews>,
+}
+
+fn write_bundle(out: impl Write, manifest: &Manifest, workspace: &persist::Workspace, scenes: &[BundledScene], presets: &[BundledPreset], contents: Vec<Content>) -> Result<(), BundleError> {
+    let mut tar = tar::Builder::new(out);
+
+    let manifest = serde_json::to_vec_pretty(manifest)?;
+    append(&mut tar, "manifest.json", manifest.len() as u64, &manifest[..])?;
+
+    let workspace = serde_json::to_vec_pretty(workspace)?;
+    append(&mut tar, "workspace.json", workspace.len() as u64, &workspace[..])?;
+
+    let scenes = serde_json::to_vec_pretty(scenes)?;
+    append(&mut tar, "scenes.json", scenes.len() as u64, &scenes[..])?;
+
+    let presets = serde_json::to_vec_pretty(presets)?;
+    append(&mut tar, "presets.json", presets.len() as u64, &presets[..])?;
+
+    for content in contents {
+        // the archive is corrupt if an entry is shorter than its header says,
+        // so a stream ending early is an error rather than a short entry:
+        let mut stream = content.stream.take(content.size);
+        let path = format!("media/{}", content.sha256);
+        append(&mut tar, &path, content.size, &mut stream)?;
+
+        if stream.limit() > 0 {
+            return Err(BundleError::MissingMedia(content.sha256));
+        }
+
+        if let Some(previews) = content.previews {
+            if let Some(thumbnail) = previews.thumbnail {
+                let path = format!("previews/{}.png", content.sha256);
+                append(&mut tar, &path, thumbnail.len() as u64, &thumbnail[..])?;
+            }
+
+            if let Some(waveform) = previews.waveform {
+                let path = format!("previews/{}.json", content.sha256);
+                append(&mut tar, &path, waveform.len() as u64, waveform.as_bytes())?;
+            }
+        }
+    }
+
+    tar.into_inner()?.flush()?;
+    Ok(())
+}
+
+fn append(tar: &mut tar::Builder<impl Write>, path: &str, size: u64, data: impl Read) -> Result<(), io::Error> {
+    let mtime = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    tar.append_data(&mut header, path, data)
+}
+
+/// Creates a new project at `path` from a bundle. Returns the media which
+/// still needs previews generating, which is left to the caller. If the
+/// import fails part way, whatever was created is removed again
+pub(super) async fn create_from_bundle(path: PathBuf, storage: StorageBackend, notify: NotifyTx, bundle: impl Read + Send + 'static) -> Result<(ProjectBaseRef, Vec<(MediaId, StreamId)>), BundleError> {
+    let base = Arc::new(ProjectBase::attach(path.clone(), storage, notify).await?);
+
+    match import(&base, bundle).await {
+        Ok(pending) => Ok((base, pending)),
+        Err(e) => {
+            close(base).await;
+            remove_project(&path);
+            Err(e)
+        }
+    }
+}
+
+enum Entry {
+    Manifest(Manifest),
+    Workspace(persist::Workspace),
+    Scenes(Vec<BundledScene>),
+    Presets(Vec<BundledPreset>),
+    // media content follows as Data entries:
+    Media(String),
+    Data(Vec<u8>),
+    Thumbnail(String, Vec<u8>),
+    Waveform(String, String),
+}
+
+/// Imports a bundle into a newly created project, replacing its workspace.
+/// Returns the media for which the bundle carried no previews
+async fn import(base: &ProjectBaseRef, bundle: impl Read + Send + 'static) -> Result<Vec<(MediaId, StreamId)>, BundleError> {
+    let (tx, mut rx) = mpsc::channel(4);
+    let reader = task::spawn_blocking(move || read_bundle(bundle, tx));
+
+    let mut manifest = None;
+    let mut workspace = None;
+    let mut scenes = Vec::new();
+    let mut presets = Vec::new();
+    let mut streams = HashMap::<String, WriteStream>::new();
+    let mut current: Option<(String, WriteStream)> = None;
+    let mut thumbnails = HashMap::new();
+    let mut waveforms = HashMap::new();
+
+    while let Some(entry) = rx.recv().await {
+        match entry {
+            Entry::Manifest(bundled) => {
+                if bundled.version > VERSION {
+                    return Err(BundleError::UnsupportedVersion(bundled.version));
+                }
+
+                manifest = Some(bundled);
+            }
+            Entry::Workspace(bundled) => {
+                workspace = Some(bundled);
+            }
+            Entry::Scenes(bundled) => {
+                scenes = bundled;
+            }
+            Entry::Presets(bundled) => {
+                presets = bundled;
+            }
+            Entry::Media(sha256) => {
+                if let Some((sha256, mut stream)) = current.take() {
+                    stream.finalize().await?;
+                    streams.insert(sha256, stream);
+                }
+
+                current = Some((sha256, stream::create(base.clone()).await?));
+            }
+            Entry::Data(data) => {
+                if let Some((_, stream)) = &mut current {
+                    stream.write(&data).await?;
+                }
+            }
+            Entry::Thumbnail(sha256, thumbnail) => {
+                thumbnails.insert(sha256, thumbnail);
+            }
+            Entry::Waveform(sha256, waveform) => {
+                waveforms.insert(sha256, waveform);
+            }
+        }
+    }
+
+    if let Some((sha256, mut stream)) = current.take() {
+        stream.finalize().await?;
+        streams.insert(sha256, stream);
+    }
+
+    reader.await.expect("join blocking task")?;
+
+    let manifest = manifest.ok_or(BundleError::NoManifest)?;
+    let mut workspace = workspace.ok_or(BundleError::NoWorkspace)?;
+
+    let mut media_ids = HashMap::new();
+    let mut used_streams = HashSet::new();
+    let mut pending = Vec::new();
+
+    for item in manifest.media {
+        let stream_id = streams.get(&item.sha256)
+            .map(|stream| stream.id())
+            .ok_or_else(|| BundleError::MissingMedia(item.sha256.clone()))?;
+
+        // bundles may come from anywhere, don't trust the kinds they carry:
+        let kind = if media::valid_kind(&item.kind) {
+            item.kind
+        } else {
+            media::DEFAULT_KIND.to_owned()
+        };
+
+        let info = UploadInfo { name: item.name, kind };
+        let created = media::insert_media(base, info, stream_id).await?;
+        used_streams.insert(created.stream_id);
+
+        if !created.have_previews {
+            let previews = StoredPreviews {
+                thumbnail: thumbnails.get(&item.sha256).cloned(),
+                waveform: waveforms.get(&item.sha256).cloned(),
+            };
+
+            if previews.thumbnail.is_some() || previews.waveform.is_some() {
+                preview::store(base, created.media_id, previews).await?;
+            } else {
+                pending.push((created.media_id, created.stream_id));
+            }
+        }
+
+        media_ids.insert(item.id, created.media_id);
+    }
+
+    // streams no media ended up using are deleted as they drop:
+    for stream in streams.values_mut() {
+        if used_streams.contains(&stream.id()) {
+            stream.keep();
+        }
+    }
+
+    remap_media(&mut workspace, &mut scenes, &mut presets, &media_ids);
+    base.write_workspace(&workspace).await?;
+    write_library(base, scenes, presets).await?;
+
+    Ok(pending)
+}
+
+fn read_bundle(bundle: impl Read, mut tx: mpsc::Sender<Entry>) -> Result<(), BundleError> {
+    let mut archive = tar::Archive::new(bundle);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut parts = path.splitn(2, '/');
+
+        match (parts.next(), parts.next()) {
+            (Some("manifest.json"), None) => {
+                send(&mut tx, Entry::Manifest(serde_json::from_reader(&mut entry)?))?;
+            }
+            (Some("workspace.json"), None) => {
+                send(&mut tx, Entry::Workspace(serde_json::from_reader(&mut entry)?))?;
+            }
+            (Some("scenes.json"), None) => {
+                send(&mut tx, Entry::Scenes(serde_json::from_reader(&mut entry)?))?;
+            }
+            (Some("presets.json"), None) => {
+                send(&mut tx, Entry::Presets(serde_json::from_reader(&mut entry)?))?;
+            }
+            (Some("media"), Some(sha256)) => {
+                send(&mut tx, Entry::Media(sha256.to_owned()))?;
+
+                loop {
+                    let mut data = Vec::with_capacity(IMPORT_CHUNK_SIZE);
+                    (&mut entry).take(IMPORT_CHUNK_SIZE as u64).read_to_end(&mut data)?;
+
+                    if data.is_empty() {
+                        break;
+                    }
+
+                    send(&mut tx, Entry::Data(data))?;
+                }
+            }
+            (Some("previews"), Some(name)) if name.ends_with(".png") => {
+                let sha256 = name.trim_end_matches(".png").to_owned();
+                let mut thumbnail = Vec::new();
+                entry.read_to_end(&mut thumbnail)?;
+                send(&mut tx, Entry::Thumbnail(sha256, thumbnail))?;
+            }
+            (Some("previews"), Some(name)) if name.ends_with(".json") => {
+                let sha256 = name.trim_end_matches(".json").to_owned();
+                let mut waveform = String::new();
+                entry.read_to_string(&mut waveform)?;
+                send(&mut tx, Entry::Waveform(sha256, waveform))?;
+            }
+            _ => {
+                // written by a later version, or added by hand
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn send(tx: &mut mpsc::Sender<Entry>, entry: Entry) -> Result<(), io::Error> {
+    // the receiving end only goes away if the import has already failed:
+    block_on(tx.send(entry))
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "import abandoned"))
+}
+
+async fn read_scenes(base: &ProjectBaseRef) -> Result<Vec<BundledScene>, rusqlite::Error> {
+    let rows = base.with_database(|conn| -> Result<Vec<(SceneId, String, String)>, rusqlite::Error> {
+        conn.prepare("SELECT id, name, serialized FROM scenes ORDER BY id")?
+            .query_map(rusqlite::NO_PARAMS,
+                |row| Ok((SceneId(row.get(0)?), row.get(1)?, row.get(2)?)))?
+            .collect()
+    }).await?;
+
+    // scenes which cannot be parsed cannot be recalled either, so leave them:
+    Ok(rows.into_iter()
+        .filter_map(|(id, name, serialized)| {
+            scene::parse(id, &serialized).map(|modules| BundledScene { id, name, modules })
+        })
+        .collect())
+}
+
+async fn read_presets(base: &ProjectBaseRef) -> Result<Vec<BundledPreset>, rusqlite::Error> {
+    let rows = base.with_database(|conn| -> Result<Vec<(PresetId, String, String)>, rusqlite::Error> {
+        conn.prepare("SELECT id, name, serialized FROM presets ORDER BY id")?
+            .query_map(rusqlite::NO_PARAMS,
+                |row| Ok((PresetId(row.get(0)?), row.get(1)?, row.get(2)?)))?
+            .collect()
+    }).await?;
+
+    Ok(rows.into_iter()
+        .filter_map(|(id, name, serialized)| {
+            preset::parse(id, &serialized).map(|params| BundledPreset { id, name, params })
+        })
+        .collect())
+}
+
+async fn write_library(base: &ProjectBaseRef, scenes: Vec<BundledScene>, presets: Vec<BundledPreset>) -> Result<(), rusqlite::Error> {
+    base.with_database(move |conn| -> Result<(), rusqlite::Error> {
+        let txn = conn.transaction()?;
+
+        for scene in scenes {
+            let serialized = serde_json::to_string(&scene.modules).expect("serde_json::to_string");
+            txn.execute("INSERT INTO scenes (id, name, serialized) VALUES (?, ?, ?)",
+                params![scene.id.0, scene.name, serialized])?;
+        }
+
+        for preset in presets {
+            let serialized = serde_json::to_string(&preset.params).expect("serde_json::to_string");
+            txn.execute("INSERT INTO presets (id, kind, name, serialized) VALUES (?, ?, ?, ?)",
+                params![preset.id.0, preset.params.kind(), preset.name, serialized])?;
+        }
+
+        txn.commit()
+    }).await
+}
+
+fn media_used(workspace: &persist::Workspace, scenes: &[BundledScene], presets: &[BundledPreset]) -> HashSet<MediaId> {
+    workspace.modules.values().map(|module| &module.params)
+        .chain(scenes.iter().flat_map(|scene| scene.modules.iter().map(|(_, params)| params)))
+        .chain(presets.iter().map(|preset| &preset.params))
+        .filter_map(|params| match params {
+            ModuleParams::MediaSource(params) => params.media_id,
+            _ => None,
+        })
+        .collect()
+}
+
+fn remap_media(workspace: &mut persist::Workspace, scenes: &mut [BundledScene], presets: &mut [BundledPreset], media_ids: &HashMap<MediaId, MediaId>) {
+    let params = workspace.modules.values_mut().map(|module| &mut module.params)
+        .chain(scenes.iter_mut().flat_map(|scene| scene.modules.iter_mut().map(|(_, params)| params)))
+        .chain(presets.iter_mut().map(|preset| &mut preset.params));
+
+    for params in params {
+        if let ModuleParams::MediaSource(params) = params {
+            params.media_id = params.media_id.and_then(|id| media_ids.get(&id).copied());
+        }
+    }
+}
+
+fn database_path(project_path: &Path) -> PathBuf {
+    let mut path = project_path.to_owned();
+    path.set_extension("mixlab");
+    path
+}
+
+/// Waits for everything still holding the project, such as streams cleaning
+/// up after themselves, to let go of it, closing its database
+pub(super) async fn close(base: ProjectBaseRef) {
+    let closed = base.closed();
+    drop(base);
+    closed.wait().await;
+}
+
+/// Moves a closed project's database and object store to another path
+pub(super) fn move_project(from: &Path, to: &Path) -> Result<(), io::Error> {
+    fs::rename(database_path(from), database_path(to))?;
+
+    let objects = objects_path(from);
+
+    if objects.exists() {
+        fs::rename(objects, objects_path(to))?;
+    }
+
+    Ok(())
+}
+
+fn objects_path(project_path: &Path) -> PathBuf {
+    let mut path = project_path.to_owned();
+    path.set_extension("mixlab-objects");
+    path
+}
+
+// best effort, an import which failed part way leaves nothing worth keeping:
+pub(super) fn remove_project(project_path: &Path) {
+    let database = database_path(project_path);
+    let _ = fs::remove_file(&database);
+    let _ = fs::remove_dir_all(objects_path(project_path));
+}
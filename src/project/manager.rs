@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::SystemTime;
+
+use derive_more::From;
+use tokio::sync::{watch, Mutex};
+use tokio::{io, task};
+use uuid::Uuid;
+
+use mixlab_protocol::ProjectLibrary;
+
+use crate::project::{self, Closed, OpenError, Project, ProjectHandle};
+use crate::project::bundle::{self, BundleError};
+use crate::project::subgraph::{SourceProject, SubgraphError};
+use crate::project::stream::StorageBackend;
+
+// Manages the directory of projects the server was started in. Exactly one
+// project is active at a time, and everything connected to the server follows
+// the active project as it changes.
+pub struct ProjectManager {
+    dir: PathBuf,
+    storage: StorageBackend,
+    active_tx: watch::Sender<ProjectHandle>,
+    active_rx: watch::Receiver<ProjectHandle>,
+    // every project opened so far, keyed by path. serialises opening
+    // projects, so that concurrent requests to open the same project do not
+    // open it twice:
+    open_lock: Mutex<HashMap<PathBuf, OpenProject>>,
+}
+
+// a project may still be in use after it stops being the active one, by an
+// export for instance. it must never be opened a second time while it is, or
+// both engines would play out and write to the same database:
+struct OpenProject {
+    project: Weak<Project>,
+    closed: Closed,
+}
+
+pub type ProjectManagerRef = Arc<ProjectManager>;
+
+#[derive(From, Debug)]
+pub enum ProjectError {
+    Io(io::Error),
+    Open(OpenError),
+    Bundle(BundleError),
+    Subgraph(SubgraphError),
+    InvalidName,
+    NoSuchProject,
+    AlreadyExists,
+    // the active project cannot be a source of modules for itself:
+    Active,
+}
+
+const EXTENSION: &str = "mixlab";
+
+impl ProjectManager {
+    /// Starts managing projects. If the path is a directory, the most
+    /// recently modified project in it is opened, or a new one is created if
+    /// there are none. Otherwise the path is a project which is opened or
+    /// created, and its siblings are the other projects.
+    pub async fn start(path: PathBuf, storage: StorageBackend) -> Result<ProjectManagerRef, ProjectError> {
+        let (dir, initial) = if path.is_dir() {
+            let name = list_projects(path.clone()).await?
+                .into_iter()
+                .max_by_key(|(_, modified)| *modified)
+                .map(|(name, _)| name)
+                .unwrap_or_else(|| "untitled".to_owned());
+
+            (path.clone(), path.join(name))
+        } else {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+                _ => PathBuf::from("."),
+            };
+
+            (dir, path)
+        };
+
+        let mut open = HashMap::new();
+        let project = project::open_or_create(initial.clone(), storage).await?;
+        open.insert(initial, OpenProject::new(&project));
+
+        let (active_tx, active_rx) = watch::channel(project);
+
+        Ok(Arc::new(ProjectManager {
+            dir,
+            storage,
+            active_tx,
+            active_rx,
+            open_lock: Mutex::new(open),
+        }))
+    }
+
+    pub fn active(&self) -> ProjectHandle {
+        self.active_rx.borrow().clone()
+    }
+
+    /// Yields the active project immediately, and then again each time the
+    /// active project changes
+    pub fn watch(&self) -> watch::Receiver<ProjectHandle> {
+        self.active_rx.clone()
+    }
+
+    pub async fn library(&self) -> Result<ProjectLibrary, ProjectError> {
+        let mut projects = list_projects(self.dir.clone()).await?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+
+        projects.sort();
+
+        Ok(ProjectLibrary {
+            projects,
+            active: self.active().name().to_owned(),
+        })
+    }
+
+    /// Makes an existing project the active one
+    pub async fn open(&self, name: String) -> Result<(), ProjectError> {
+        let mut open = self.open_lock.lock().await;
+
+        validate_name(&name)?;
+
+        if self.active().name() == name {
+            return Ok(());
+        }
+
+        if !self.project_path(&name).is_file() {
+            return Err(ProjectError::NoSuchProject);
+        }
+
+        self.activate(&mut open, &name).await
+    }
+
+    /// Creates a new empty project and makes it the active one
+    pub async fn create(&self, name: String) -> Result<(), ProjectError> {
+        let mut open = self.open_lock.lock().await;
+
+        validate_name(&name)?;
+
+        if self.project_path(&name).exists() {
+            return Err(ProjectError::AlreadyExists);
+        }
+
+        self.activate(&mut open, &name).await
+    }
+
+    /// Creates a new project from a bundle and makes it the active one
+    pub async fn import(&self, name: String, bundle: impl std::io::Read + Send + 'static) -> Result<(), ProjectError> {
+        validate_name(&name)?;
+
+        if self.project_path(&name).exists() {
+            return Err(ProjectError::AlreadyExists);
+        }
+
+        // bundles can be large, so import under a hidden name without
+        // holding up other projects being opened, then move into place:
+        let staging = self.dir.join(format!(".import-{}", Uuid::new_v4()));
+        project::import(staging.clone(), self.storage, bundle).await?;
+
+        let mut open = self.open_lock.lock().await;
+
+        if self.project_path(&name).exists() {
+            bundle::remove_project(&staging);
+            return Err(ProjectError::AlreadyExists);
+        }
+
+        if let Err(e) = bundle::move_project(&staging, &self.dir.join(&name)) {
+            bundle::remove_project(&staging);
+            return Err(e.into());
+        }
+
+        self.activate(&mut open, &name).await
+    }
+
+    /// Opens another project for reading, to import modules from
+    pub async fn open_source(&self, name: &str) -> Result<SourceProject, ProjectError> {
+        validate_name(name)?;
+
+        if self.active().name() == name {
+            return Err(ProjectError::Active);
+        }
+
+        if !self.project_path(name).is_file() {
+            return Err(ProjectError::NoSuchProject);
+        }
+
+        Ok(SourceProject::open(self.dir.join(name)).await?)
+    }
+
+    async fn activate(&self, open: &mut HashMap<PathBuf, OpenProject>, name: &str) -> Result<(), ProjectError> {
+        // the previous project's engine stops once everything connected to
+        // it has moved over to the new one, and nothing else holds it:
+        let path = self.dir.join(name);
+
+        let project = match open.remove(&path) {
+            Some(previous) => match previous.project.upgrade() {
+                Some(project) => Some(ProjectHandle(project)),
+                None => {
+                    previous.closed.wait().await;
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let project = match project {
+            Some(project) => project,
+            None => project::open_or_create(path.clone(), self.storage).await?,
+        };
+
+        open.insert(path, OpenProject::new(&project));
+        let _ = self.active_tx.broadcast(project);
+        Ok(())
+    }
+
+    fn project_path(&self, name: &str) -> PathBuf {
+        let mut path = self.dir.join(name);
+        path.set_extension(EXTENSION);
+        path
+    }
+}
+
+impl OpenProject {
+    fn new(project: &ProjectHandle) -> Self {
+        OpenProject {
+            project: Arc::downgrade(&project.0),
+            closed: project.base.closed(),
+        }
+    }
+}
+
+// names become file names, and set_extension would replace anything after a
+// dot, so only allow a conservative set of characters:
+fn validate_name(name: &str) -> Result<(), ProjectError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with(' ')
+        && name.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ProjectError::InvalidName)
+    }
+}
+
+async fn list_projects(dir: PathBuf) -> Result<Vec<(String, SystemTime)>, io::Error> {
+    task::spawn_blocking(move || {
+        let mut projects = Vec::new();
+
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+
+            // projects part way through being imported, see import:
+            if path.file_name().and_then(|name| name.to_str()).map_or(false, |name| name.starts_with('.')) {
+                continue;
+            }
+
+            let name = match project_name(&path) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            let modified = entry.metadata()?.modified()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+
+            projects.push((name, modified));
+        }
+
+        Ok(projects)
+    }).await.expect("list projects")
+}
+
+pub fn project_name(path: &Path) -> Option<String> {
+    path.file_stem()?.to_str().map(str::to_owned)
+}
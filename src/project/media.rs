@@ -1,18 +1,36 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
 
 use derive_more::From;
-use mixlab_protocol::MediaId;
+use http::HeaderValue;
+use mixlab_protocol::{MediaId, MediaMetadata, MediaStreamInfo, MediaStreamKind, Microseconds, ModuleParams, PresetId, SceneId};
 use mixlab_protocol as protocol;
-use rusqlite::{params, OptionalExtension};
+use rusqlite::{params, Connection, OptionalExtension};
 
-use crate::project::ProjectBaseRef;
-use crate::project::stream::{self, ReadStream, WriteStream, StreamId};
+use crate::engine::EngineError;
+use crate::project::{preset, scene, ProjectBaseRef};
+use crate::project::stream::{self, ReadStream, WriteStream, StreamError, StreamId};
+
+pub mod preview;
+mod probe;
+pub mod upload;
+
+pub use probe::ProbeError;
+
+// kind of media whose kind is unknown or not a valid content-type:
+pub const DEFAULT_KIND: &str = "application/octet-stream";
 
 pub struct UploadInfo {
     pub name: String,
     pub kind: String,
 }
 
+/// Media is served with its kind as content-type, so kinds from clients and
+/// bundles must be a valid header value naming a type and subtype
+pub fn valid_kind(kind: &str) -> bool {
+    kind.contains('/') && HeaderValue::from_str(kind).is_ok()
+}
+
 pub struct MediaUpload {
     pub base: ProjectBaseRef,
     pub stream: WriteStream,
@@ -22,10 +40,31 @@ pub struct MediaUpload {
 #[derive(From, Debug)]
 pub enum UploadError {
     Database(rusqlite::Error),
+    Stream(StreamError),
+    Unreadable(ProbeError),
+    NoSuchUpload,
+    InvalidKind,
+    // another chunk is being received for the same upload:
+    Busy,
+    // carries the committed offset the chunk should have started at:
+    OffsetMismatch(u64),
+}
+
+#[derive(From, Debug)]
+pub enum MediaError {
+    Database(rusqlite::Error),
+    Engine(EngineError),
+    NoSuchMedia,
+    InvalidName,
+    InUse,
 }
 
 impl MediaUpload {
     pub async fn new(base: ProjectBaseRef, info: UploadInfo) -> Result<Self, UploadError> {
+        if !valid_kind(&info.kind) {
+            return Err(UploadError::InvalidKind);
+        }
+
         let stream = stream::create(base.clone()).await?;
 
         Ok(MediaUpload {
@@ -40,22 +79,102 @@ impl MediaUpload {
         Ok(())
     }
 
-    pub async fn finalize(self) -> Result<(), UploadError> {
+    /// Rejects uploads which are not media we can demux, deleting the
+    /// uploaded stream
+    pub async fn finalize(mut self) -> Result<MediaId, UploadError> {
         let stream_id = self.stream.finalize().await?;
-        let info = self.info;
 
-        self.base.with_database(move |conn| -> Result<(), rusqlite::Error> {
-            conn.execute(
-                    "INSERT INTO media (name, kind, stream_id) VALUES (?, ?, ?)",
-                    params![info.name, info.kind, stream_id.0])?;
+        let (media_id, media_stream_id) = create_media(&self.base, self.info, stream_id).await?;
 
-            Ok(())
-        }).await?;
+        // the upload's stream is deleted on drop if it duplicated an
+        // existing stream:
+        if media_stream_id == stream_id {
+            self.stream.keep();
+        }
 
-        let _ = self.base.notify.media.broadcast(());
+        Ok(media_id)
+    }
+}
 
-        Ok(())
+pub struct CreatedMedia {
+    pub media_id: MediaId,
+    pub stream_id: StreamId,
+    // copied from media sharing the same stream:
+    pub have_previews: bool,
+}
+
+/// Creates a media item from a fully written stream. If another media item
+/// already has identical content, the new item shares its stream, and the
+/// stream id returned differs from the one passed in. The caller is then
+/// responsible for deleting the passed stream.
+pub async fn create_media(base: &ProjectBaseRef, info: UploadInfo, stream_id: StreamId) -> Result<(MediaId, StreamId), UploadError> {
+    let created = insert_media(base, info, stream_id).await?;
+
+    if !created.have_previews {
+        tokio::spawn(preview::generate(base.clone(), created.media_id, created.stream_id));
     }
+
+    Ok((created.media_id, created.stream_id))
+}
+
+/// As create_media, but leaves generating previews to the caller
+pub async fn insert_media(base: &ProjectBaseRef, info: UploadInfo, stream_id: StreamId) -> Result<CreatedMedia, UploadError> {
+    let metadata = probe::probe(base.clone(), stream_id).await?;
+
+    let hash = stream::seal(base.clone(), stream_id).await?;
+
+    let (media_id, media_stream_id, have_previews) = base.with_database(move |conn| -> Result<_, rusqlite::Error> {
+        let txn = conn.transaction()?;
+
+        let existing = match &hash {
+            Some(hash) => txn.query_row(r"
+                    SELECT id FROM streams
+                    WHERE hash = ? AND id != ? AND id IN (SELECT stream_id FROM media)
+                    LIMIT 1
+                ",
+                params![hash, stream_id.0],
+                |row| Ok(StreamId(row.get(0)?))
+            ).optional()?,
+            None => None,
+        };
+
+        let media_stream_id = match existing {
+            Some(existing) => existing,
+            None => {
+                txn.execute("UPDATE streams SET hash = ? WHERE id = ?",
+                    params![hash, stream_id.0])?;
+
+                stream_id
+            }
+        };
+
+        txn.execute(
+                "INSERT INTO media (name, kind, stream_id) VALUES (?, ?, ?)",
+                params![info.name, info.kind, media_stream_id.0])?;
+
+        let media_id = MediaId(txn.last_insert_rowid());
+        insert_metadata(&txn, media_id, &metadata)?;
+
+        // identical content has identical previews, copy them if the media
+        // we share a stream with has them already:
+        let have_previews = txn.execute(r"
+                INSERT INTO media_previews (media_id, thumbnail, waveform)
+                SELECT ?, thumbnail, waveform FROM media_previews
+                WHERE media_id IN (SELECT id FROM media WHERE stream_id = ? AND id != ?)
+                LIMIT 1
+            ", params![media_id.0, media_stream_id.0, media_id.0])? > 0;
+
+        txn.commit()?;
+        Ok((media_id, media_stream_id, have_previews))
+    }).await?;
+
+    let _ = base.notify.media.broadcast(());
+
+    Ok(CreatedMedia {
+        media_id,
+        stream_id: media_stream_id,
+        have_previews,
+    })
 }
 
 pub async fn library(base: &ProjectBaseRef) -> Result<protocol::MediaLibrary, rusqlite::Error> {
@@ -68,9 +187,15 @@ pub async fn library(base: &ProjectBaseRef) -> Result<protocol::MediaLibrary, ru
     }
 
     let items = base.with_database(|conn| -> Result<Vec<protocol::MediaItem>, rusqlite::Error> {
+        let mut metadata = load_metadata(conn)?;
+
         conn.prepare(r"
-                SELECT media.id, media.name, media.kind, streams.size FROM media
+                SELECT media.id, media.name, media.kind, streams.size,
+                    media_previews.thumbnail IS NOT NULL,
+                    media_previews.waveform IS NOT NULL
+                FROM media
                 INNER JOIN streams ON streams.id = media.stream_id
+                LEFT JOIN media_previews ON media_previews.media_id = media.id
                 ORDER BY media.id DESC
             ")?
             .query_map(rusqlite::NO_PARAMS,
@@ -79,6 +204,9 @@ pub async fn library(base: &ProjectBaseRef) -> Result<protocol::MediaLibrary, ru
                     name: row.get(1)?,
                     kind: row.get(2)?,
                     size: row.get::<_, i64>(3)?.try_into().unwrap(),
+                    metadata: metadata.remove(&row.get::<_, i64>(0)?),
+                    thumbnail: row.get(4)?,
+                    waveform: row.get(5)?,
                 })
             )?
             .collect()
@@ -87,6 +215,85 @@ pub async fn library(base: &ProjectBaseRef) -> Result<protocol::MediaLibrary, ru
     Ok(protocol::MediaLibrary { items })
 }
 
+fn insert_metadata(conn: &Connection, media_id: MediaId, metadata: &MediaMetadata) -> Result<(), rusqlite::Error> {
+    let duration = metadata.duration.map(|duration| duration.0 as i64);
+
+    conn.execute("INSERT INTO media_metadata (media_id, format, duration_us) VALUES (?, ?, ?)",
+        params![media_id.0, metadata.format, duration])?;
+
+    for stream in &metadata.streams {
+        let index = stream.index as i64;
+
+        match &stream.kind {
+            MediaStreamKind::Video { width, height, frame_rate } => {
+                conn.execute(r"
+                    INSERT INTO media_streams (media_id, stream_index, kind, codec, width, height, frame_rate)
+                    VALUES (?, ?, 'video', ?, ?, ?, ?)
+                ", params![media_id.0, index, stream.codec, width, height, frame_rate])?;
+            }
+            MediaStreamKind::Audio { sample_rate, channels } => {
+                conn.execute(r"
+                    INSERT INTO media_streams (media_id, stream_index, kind, codec, sample_rate, channels)
+                    VALUES (?, ?, 'audio', ?, ?, ?)
+                ", params![media_id.0, index, stream.codec, sample_rate, channels])?;
+            }
+            MediaStreamKind::Other => {
+                conn.execute(r"
+                    INSERT INTO media_streams (media_id, stream_index, kind, codec)
+                    VALUES (?, ?, 'other', ?)
+                ", params![media_id.0, index, stream.codec])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn load_metadata(conn: &Connection) -> Result<HashMap<i64, MediaMetadata>, rusqlite::Error> {
+    let mut metadata = conn.prepare("SELECT media_id, format, duration_us FROM media_metadata")?
+        .query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((row.get::<_, i64>(0)?, MediaMetadata {
+                format: row.get(1)?,
+                duration: row.get::<_, Option<i64>>(2)?.map(|us| Microseconds(us as u64)),
+                streams: Vec::new(),
+            }))
+        })?
+        .collect::<Result<HashMap<_, _>, _>>()?;
+
+    let mut stmt = conn.prepare(r"
+        SELECT media_id, stream_index, kind, codec, width, height, frame_rate, sample_rate, channels
+        FROM media_streams
+        ORDER BY media_id ASC, stream_index ASC
+    ")?;
+
+    let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+
+    while let Some(row) = rows.next()? {
+        let kind = match row.get::<_, String>(2)?.as_str() {
+            "video" => MediaStreamKind::Video {
+                width: row.get(4)?,
+                height: row.get(5)?,
+                frame_rate: row.get(6)?,
+            },
+            "audio" => MediaStreamKind::Audio {
+                sample_rate: row.get(7)?,
+                channels: row.get(8)?,
+            },
+            _ => MediaStreamKind::Other,
+        };
+
+        if let Some(metadata) = metadata.get_mut(&row.get::<_, i64>(0)?) {
+            metadata.streams.push(MediaStreamInfo {
+                index: row.get::<_, i64>(1)? as usize,
+                codec: row.get(3)?,
+                kind,
+            });
+        }
+    }
+
+    Ok(metadata)
+}
+
 pub async fn open(base: ProjectBaseRef, media_id: MediaId) -> Result<Option<ReadStream>, rusqlite::Error> {
     let stream_id = base.with_database(move |conn| -> Result<Option<StreamId>, rusqlite::Error> {
         conn.query_row(r"SELECT media.stream_id FROM media WHERE id = ?",
@@ -100,3 +307,177 @@ pub async fn open(base: ProjectBaseRef, media_id: MediaId) -> Result<Option<Read
         None => Ok(None),
     }
 }
+
+/// A media item opened for download by the HTTP server
+pub struct MediaDownload {
+    pub name: String,
+    pub kind: String,
+    pub stream: ReadStream,
+}
+
+pub async fn open_download(base: ProjectBaseRef, media_id: MediaId) -> Result<Option<MediaDownload>, rusqlite::Error> {
+    let media = base.with_database(move |conn| -> Result<Option<(String, String, StreamId)>, rusqlite::Error> {
+        conn.query_row(r"SELECT name, kind, stream_id FROM media WHERE id = ?",
+            params![media_id.0],
+            |row| Ok((row.get(0)?, row.get(1)?, StreamId(row.get(2)?)))
+        ).optional()
+    }).await?;
+
+    let (name, kind, stream_id) = match media {
+        Some(media) => media,
+        None => return Ok(None),
+    };
+
+    Ok(ReadStream::open(base, stream_id).await?
+        .map(|stream| MediaDownload { name, kind, stream }))
+}
+
+pub async fn rename(base: &ProjectBaseRef, media_id: MediaId, name: String) -> Result<(), MediaError> {
+    let name = name.trim().to_string();
+
+    if name.is_empty() {
+        return Err(MediaError::InvalidName);
+    }
+
+    let updated = base.with_database(move |conn| -> Result<usize, rusqlite::Error> {
+        conn.execute("UPDATE media SET name = ? WHERE id = ?",
+            params![name, media_id.0])
+    }).await?;
+
+    if updated == 0 {
+        return Err(MediaError::NoSuchMedia);
+    }
+
+    let _ = base.notify.media.broadcast(());
+
+    Ok(())
+}
+
+/// Deletes a media item and its stream, unless a saved scene or preset refers
+/// to it. Does not check whether the workspace is playing the media, see
+/// ProjectHandle::delete_media
+pub async fn delete(base: &ProjectBaseRef, media_id: MediaId) -> Result<(), MediaError> {
+    let deleted = base.with_database(move |conn| -> Result<bool, MediaError> {
+        let txn = conn.transaction()?;
+
+        if saved_params_use(&txn, media_id)? {
+            return Err(MediaError::InUse);
+        }
+
+        let stream_id = txn.query_row("SELECT stream_id FROM media WHERE id = ?",
+            params![media_id.0],
+            |row| Ok(StreamId(row.get(0)?))
+        ).optional()?;
+
+        let stream_id = match stream_id {
+            Some(stream_id) => stream_id,
+            None => return Ok(false),
+        };
+
+        txn.execute("DELETE FROM media_previews WHERE media_id = ?", params![media_id.0])?;
+        txn.execute("DELETE FROM media_streams WHERE media_id = ?", params![media_id.0])?;
+        txn.execute("DELETE FROM media_metadata WHERE media_id = ?", params![media_id.0])?;
+        txn.execute("DELETE FROM media WHERE id = ?", params![media_id.0])?;
+
+        let shared = txn.query_row("SELECT EXISTS (SELECT 1 FROM media WHERE stream_id = ?)",
+            params![stream_id.0],
+            |row| row.get::<_, bool>(0))?;
+
+        if !shared {
+            stream::delete_blocking(&txn, stream_id)?;
+        }
+
+        txn.commit()?;
+        Ok(true)
+    }).await?;
+
+    if !deleted {
+        return Err(MediaError::NoSuchMedia);
+    }
+
+    let _ = base.notify.media.broadcast(());
+
+    Ok(())
+}
+
+// checked in the same transaction as deleting, so that a scene or preset
+// saved in the meantime cannot be left referring to missing media:
+fn saved_params_use(conn: &Connection, media_id: MediaId) -> Result<bool, rusqlite::Error> {
+    let uses = |params: &ModuleParams| match params {
+        ModuleParams::MediaSource(params) => params.media_id == Some(media_id),
+        _ => false,
+    };
+
+    let scenes = conn.prepare("SELECT id, serialized FROM scenes")?
+        .query_map(rusqlite::NO_PARAMS, |row| Ok((SceneId(row.get(0)?), row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let in_scene = scenes.iter()
+        .filter_map(|(scene_id, serialized)| scene::parse(*scene_id, serialized))
+        .any(|scene| scene.iter().any(|(_, params)| uses(params)));
+
+    if in_scene {
+        return Ok(true);
+    }
+
+    let presets = conn.prepare("SELECT id, serialized FROM presets WHERE kind = ?")?
+        .query_map(params![ModuleParams::MediaSource(Default::default()).kind()],
+            |row| Ok((PresetId(row.get(0)?), row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(presets.iter()
+        .filter_map(|(preset_id, serialized)| preset::parse(*preset_id, serialized))
+        .any(|params| uses(&params)))
+}
+
+/// Deletes streams not referenced by any media item or resumable upload,
+/// such as those left behind by uploads interrupted before this process last
+/// exited or resumable uploads which have expired, then
+/// vacuums the database to return the space to the filesystem. Returns the
+/// number of streams deleted.
+pub async fn collect_garbage(base: &ProjectBaseRef) -> Result<usize, rusqlite::Error> {
+    base.with_database({
+        let base = base.clone();
+        move |conn| -> Result<usize, rusqlite::Error> {
+            let txn = conn.transaction()?;
+
+            upload::expire_blocking(&txn)?;
+
+            let orphans = txn.prepare(r"
+                    SELECT id FROM streams
+                    WHERE id NOT IN (SELECT stream_id FROM media)
+                    AND id NOT IN (SELECT stream_id FROM uploads)
+                ")?
+                .query_map(rusqlite::NO_PARAMS, |row| Ok(StreamId(row.get(0)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let orphans = {
+                let writing = base.writing.lock().expect("lock writing streams");
+
+                orphans.into_iter()
+                    .filter(|stream_id| !writing.contains(stream_id))
+                    .collect::<Vec<_>>()
+            };
+
+            for stream_id in &orphans {
+                stream::delete_blocking(&txn, *stream_id)?;
+            }
+
+            txn.execute("DELETE FROM blobs WHERE stream_id NOT IN (SELECT id FROM streams)",
+                rusqlite::NO_PARAMS)?;
+
+            txn.commit()?;
+
+            // garbage collection is best effort on disk, the database is what
+            // matters:
+            if let Err(e) = stream::sweep_objects_blocking(&base, conn) {
+                eprintln!("media: could not sweep object store: {:?}", e);
+            }
+
+            // VACUUM cannot run inside a transaction:
+            conn.execute_batch("VACUUM")?;
+
+            Ok(orphans.len())
+        }
+    }).await
+}
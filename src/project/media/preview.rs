@@ -0,0 +1,486 @@
+// Thumbnails and waveform overviews shown in the media library and media
+// source modules. Generated in the background after upload, or when a project
+// opens for media which has none yet, so media may be briefly or permanently
+// without previews, eg. audio-only media has no thumbnail.
+
+use std::convert::TryFrom;
+
+use derive_more::From;
+use mixlab_codec::ffmpeg::codec::{self, CodecBuilder, Decode, RecvFrameError};
+use mixlab_codec::ffmpeg::media::{Audio, MediaType, Video};
+use mixlab_codec::ffmpeg::{AvError, AvFrame, AvIoError, AvIoReader, InputContainer, IoReader, PictureSettings, PixelFormat, SwsContext};
+use mixlab_protocol::MediaId;
+use rusqlite::{params, OptionalExtension};
+use tokio::task;
+
+use crate::project::ProjectBaseRef;
+use crate::project::stream::{ReadStream, StreamId};
+
+const THUMBNAIL_WIDTH: usize = 320;
+
+// decode at most this many frames looking for one which is not mostly black,
+// many videos fade in from black:
+const THUMBNAIL_MAX_FRAMES: usize = 30;
+const THUMBNAIL_MIN_BRIGHTNESS: f64 = 24.0;
+
+const WAVEFORM_PEAKS: usize = 256;
+
+// samples are first reduced to peaks over blocks of this many samples, and
+// those blocks are then merged into WAVEFORM_PEAKS peaks once the length of
+// the stream is known:
+const WAVEFORM_BLOCK_SAMPLES: usize = 1024;
+
+#[derive(From, Debug)]
+pub enum PreviewError {
+    Av(AvError),
+    Database(rusqlite::Error),
+    Io(<ReadStream as IoReader>::Error),
+    CodecBuild(codec::BuildError),
+    CodecOpen(codec::OpenError),
+    RecvFrame(RecvFrameError),
+    Png(png::EncodingError),
+    NoSuchStream,
+}
+
+impl From<AvIoError<ReadStream>> for PreviewError {
+    fn from(e: AvIoError<ReadStream>) -> PreviewError {
+        match e {
+            AvIoError::Av(e) => PreviewError::Av(e),
+            AvIoError::Io(e) => PreviewError::Io(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Peak {
+    pub min: f32,
+    pub max: f32,
+}
+
+struct Previews {
+    // PNG encoded:
+    thumbnail: Option<Vec<u8>>,
+    waveform: Option<Vec<Peak>>,
+}
+
+/// Generates and stores previews for a media item, logging any failure
+pub async fn generate(base: ProjectBaseRef, media_id: MediaId, stream_id: StreamId) {
+    match generate_previews(base, media_id, stream_id).await {
+        Ok(()) => {}
+        Err(e) => {
+            eprintln!("preview: could not generate previews for {:?}: {:?}", media_id, e);
+        }
+    }
+}
+
+async fn generate_previews(base: ProjectBaseRef, media_id: MediaId, stream_id: StreamId) -> Result<(), PreviewError> {
+    let stream = ReadStream::open(base.clone(), stream_id).await?
+        .ok_or(PreviewError::NoSuchStream)?;
+
+    // ReadStream reads from the database synchronously:
+    let previews = match task::spawn_blocking(move || decode_previews(stream)).await.expect("join blocking task") {
+        Ok(previews) => previews,
+        Err(e @ PreviewError::Av(_)) |
+        Err(e @ PreviewError::CodecBuild(_)) |
+        Err(e @ PreviewError::CodecOpen(_)) |
+        Err(e @ PreviewError::RecvFrame(_)) => {
+            // media which cannot be decoded now never will be, store it as
+            // having no previews so that backfill leaves it be:
+            eprintln!("preview: could not decode {:?}: {:?}", media_id, e);
+            Previews { thumbnail: None, waveform: None }
+        }
+        Err(e) => {
+            // failing to read the stream or to encode the thumbnail may well
+            // succeed another time, so leave it for backfill to retry when the
+            // project is next opened:
+            return Err(e);
+        }
+    };
+
+    let waveform = previews.waveform.map(|peaks| {
+        let pairs = peaks.iter().map(|peak| (peak.min, peak.max)).collect::<Vec<_>>();
+        serde_json::to_string(&pairs).expect("serde_json::to_string")
+    });
+
+    store(&base, media_id, StoredPreviews {
+        thumbnail: previews.thumbnail,
+        waveform,
+    }).await?;
+
+    Ok(())
+}
+
+/// Generates previews for media which has none, eg. media uploaded before
+/// previews were introduced. Works through one item at a time
+pub async fn backfill(base: ProjectBaseRef) {
+    let missing = base.with_database(|conn| -> Result<Vec<(MediaId, StreamId)>, rusqlite::Error> {
+        conn.prepare(r"
+                SELECT id, stream_id FROM media
+                WHERE id NOT IN (SELECT media_id FROM media_previews)
+                ORDER BY id
+            ")?
+            .query_map(rusqlite::NO_PARAMS, |row| Ok((MediaId(row.get(0)?), StreamId(row.get(1)?))))?
+            .collect()
+    }).await;
+
+    let missing = match missing {
+        Ok(missing) => missing,
+        Err(e) => {
+            eprintln!("preview: could not find media without previews: {:?}", e);
+            return;
+        }
+    };
+
+    for (media_id, stream_id) in missing {
+        generate(base.clone(), media_id, stream_id).await;
+    }
+}
+
+/// Previews as kept in the database, for carrying between projects
+pub struct StoredPreviews {
+    // PNG encoded:
+    pub thumbnail: Option<Vec<u8>>,
+    // JSON array of [min, max] pairs:
+    pub waveform: Option<String>,
+}
+
+pub async fn load(base: &ProjectBaseRef, media_id: MediaId) -> Result<Option<StoredPreviews>, rusqlite::Error> {
+    base.with_database(move |conn| {
+        conn.query_row("SELECT thumbnail, waveform FROM media_previews WHERE media_id = ?",
+            params![media_id.0],
+            |row| Ok(StoredPreviews {
+                thumbnail: row.get(0)?,
+                waveform: row.get(1)?,
+            })
+        ).optional()
+    }).await
+}
+
+pub async fn store(base: &ProjectBaseRef, media_id: MediaId, previews: StoredPreviews) -> Result<(), rusqlite::Error> {
+    let StoredPreviews { thumbnail, waveform } = previews;
+
+    base.with_database(move |conn| -> Result<(), rusqlite::Error> {
+        // the media item may have been deleted in the meantime, or had its
+        // previews generated by backfill, which come out the same:
+        conn.execute(r"
+                INSERT OR IGNORE INTO media_previews (media_id, thumbnail, waveform)
+                SELECT id, ?, ? FROM media WHERE id = ?
+            ", params![thumbnail, waveform, media_id.0])?;
+
+        Ok(())
+    }).await?;
+
+    let _ = base.notify.media.broadcast(());
+
+    Ok(())
+}
+
+pub async fn thumbnail(base: &ProjectBaseRef, media_id: MediaId) -> Result<Option<Vec<u8>>, rusqlite::Error> {
+    base.with_database(move |conn| {
+        conn.query_row("SELECT thumbnail FROM media_previews WHERE media_id = ?",
+            params![media_id.0],
+            |row| row.get::<_, Option<Vec<u8>>>(0)
+        ).optional().map(Option::flatten)
+    }).await
+}
+
+pub async fn waveform(base: &ProjectBaseRef, media_id: MediaId) -> Result<Option<Vec<Peak>>, rusqlite::Error> {
+    let serialized = base.with_database(move |conn| {
+        conn.query_row("SELECT waveform FROM media_previews WHERE media_id = ?",
+            params![media_id.0],
+            |row| row.get::<_, Option<String>>(0)
+        ).optional().map(Option::flatten)
+    }).await?;
+
+    Ok(serialized.and_then(|serialized| {
+        match serde_json::from_str::<Vec<(f32, f32)>>(&serialized) {
+            Ok(peaks) => Some(peaks.into_iter().map(|(min, max)| Peak { min, max }).collect()),
+            Err(e) => {
+                eprintln!("preview: could not parse waveform for {:?}: {:?}", media_id, e);
+                None
+            }
+        }
+    }))
+}
+
+fn decode_previews(stream: ReadStream) -> Result<Previews, PreviewError> {
+    let mut container = InputContainer::open(AvIoReader::new(stream))?;
+
+    // a stream we have no decoder for only goes without its own preview:
+    let mut video = container.best_stream::<Video>()
+        .and_then(|index| match open_decoder::<Video>(&container, index) {
+            Ok(decode) => Some(ThumbnailDecode::new(index, decode)),
+            Err(e) => {
+                eprintln!("preview: could not open video decoder: {:?}", e);
+                None
+            }
+        });
+
+    let mut audio = container.best_stream::<Audio>()
+        .and_then(|index| match open_decoder::<Audio>(&container, index) {
+            Ok(decode) => Some(WaveformDecode::new(index, decode)),
+            Err(e) => {
+                eprintln!("preview: could not open audio decoder: {:?}", e);
+                None
+            }
+        });
+
+    while let Some(pkt) = container.read_packet()? {
+        let index = usize::try_from(pkt.stream_index()).ok();
+
+        if let Some(video) = &mut video {
+            if Some(video.index) == index && !video.done() {
+                video.decode.send_packet(&pkt)?;
+                video.receive()?;
+            }
+        }
+
+        if let Some(audio) = &mut audio {
+            if Some(audio.index) == index {
+                audio.decode.send_packet(&pkt)?;
+                audio.receive()?;
+            }
+        }
+
+        let video_done = video.as_ref().map(ThumbnailDecode::done).unwrap_or(true);
+
+        if video_done && audio.is_none() {
+            break;
+        }
+    }
+
+    let thumbnail = match video {
+        Some(video) => video.finish()?,
+        None => None,
+    };
+
+    let waveform = match audio {
+        Some(audio) => audio.finish()?,
+        None => None,
+    };
+
+    Ok(Previews { thumbnail, waveform })
+}
+
+fn open_decoder<T: MediaType>(container: &InputContainer<ReadStream>, index: usize) -> Result<Decode<T>, PreviewError> {
+    let stream = &container.streams()[index];
+    let params = stream.codec_parameters();
+
+    Ok(CodecBuilder::<T>::new(params.codec_id, stream.time_base())?
+        .with_parameters(params)
+        .open_decoder()?)
+}
+
+struct ThumbnailDecode {
+    index: usize,
+    decode: Decode<Video>,
+    frames: usize,
+    best: Option<(f64, Vec<u8>, PictureSettings)>,
+}
+
+impl ThumbnailDecode {
+    fn new(index: usize, decode: Decode<Video>) -> Self {
+        ThumbnailDecode {
+            index,
+            decode,
+            frames: 0,
+            best: None,
+        }
+    }
+
+    fn done(&self) -> bool {
+        let bright_enough = self.best.as_ref()
+            .map(|(brightness, _, _)| *brightness >= THUMBNAIL_MIN_BRIGHTNESS)
+            .unwrap_or(false);
+
+        bright_enough || self.frames >= THUMBNAIL_MAX_FRAMES
+    }
+
+    fn receive(&mut self) -> Result<(), PreviewError> {
+        loop {
+            if self.done() {
+                return Ok(());
+            }
+
+            match self.decode.recv_frame() {
+                Ok(frame) => { self.consider(&frame); }
+                Err(RecvFrameError::NeedMoreInput) | Err(RecvFrameError::Eof) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn consider(&mut self, frame: &AvFrame<Video>) {
+        self.frames += 1;
+
+        let input = frame.picture_settings();
+
+        if input.width == 0 || input.height == 0 {
+            return;
+        }
+
+        let width = THUMBNAIL_WIDTH;
+        let height = ((input.height * width) / input.width).max(1);
+
+        let output = PictureSettings {
+            width,
+            height,
+            pixel_format: PixelFormat::rgb24(),
+        };
+
+        let mut scaled = AvFrame::<Video>::blank(&output);
+        let mut sws = SwsContext::new(input, output.clone());
+        sws.process(&frame.frame_data(), &mut scaled.frame_data_mut());
+
+        // copy out rows, dropping any padding at the end of each line:
+        let data = scaled.frame_data();
+        let row_bytes = width * 3;
+        let mut rgb = Vec::with_capacity(row_bytes * height);
+
+        for row in 0..height {
+            let line = unsafe {
+                std::slice::from_raw_parts(data.data(0).add(row * data.stride(0)), row_bytes)
+            };
+
+            rgb.extend_from_slice(line);
+        }
+
+        let brightness = rgb.iter().map(|byte| *byte as f64).sum::<f64>() / rgb.len() as f64;
+
+        let better = self.best.as_ref()
+            .map(|(best, _, _)| brightness > *best)
+            .unwrap_or(true);
+
+        if better {
+            self.best = Some((brightness, rgb, output));
+        }
+    }
+
+    fn finish(mut self) -> Result<Option<Vec<u8>>, PreviewError> {
+        if !self.done() {
+            self.decode.end_of_stream()?;
+            self.receive()?;
+        }
+
+        let (_, rgb, settings) = match self.best {
+            Some(best) => best,
+            None => return Ok(None),
+        };
+
+        let mut png_data = Vec::new();
+
+        {
+            let mut encoder = png::Encoder::new(&mut png_data, settings.width as u32, settings.height as u32);
+            encoder.set_color(png::ColorType::RGB);
+            encoder.set_depth(png::BitDepth::Eight);
+
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&rgb)?;
+        }
+
+        Ok(Some(png_data))
+    }
+}
+
+struct WaveformDecode {
+    index: usize,
+    decode: Decode<Audio>,
+    blocks: Vec<Peak>,
+    current: Option<Peak>,
+    current_samples: usize,
+}
+
+impl WaveformDecode {
+    fn new(index: usize, decode: Decode<Audio>) -> Self {
+        WaveformDecode {
+            index,
+            decode,
+            blocks: Vec::new(),
+            current: None,
+            current_samples: 0,
+        }
+    }
+
+    fn receive(&mut self) -> Result<(), PreviewError> {
+        loop {
+            match self.decode.recv_frame() {
+                Ok(frame) => { self.accumulate(&frame); }
+                Err(RecvFrameError::NeedMoreInput) | Err(RecvFrameError::Eof) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn accumulate(&mut self, frame: &AvFrame<Audio>) {
+        let samples = match frame.to_f32_interleaved() {
+            Some(samples) => samples,
+            None => return,
+        };
+
+        let channels = frame.channel_count().max(1);
+
+        // peaks are taken across all channels:
+        for sample in samples.chunks(channels) {
+            let peak = self.current.get_or_insert(Peak { min: 0.0, max: 0.0 });
+
+            for value in sample {
+                peak.min = peak.min.min(*value);
+                peak.max = peak.max.max(*value);
+            }
+
+            self.current_samples += 1;
+
+            if self.current_samples == WAVEFORM_BLOCK_SAMPLES {
+                self.blocks.extend(self.current.take());
+                self.current_samples = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Option<Vec<Peak>>, PreviewError> {
+        self.decode.end_of_stream()?;
+        self.receive()?;
+        self.blocks.extend(self.current.take());
+
+        if self.blocks.is_empty() {
+            return Ok(None);
+        }
+
+        let count = WAVEFORM_PEAKS.min(self.blocks.len());
+
+        let peaks = (0..count).map(|idx| {
+            let start = idx * self.blocks.len() / count;
+            let end = (idx + 1) * self.blocks.len() / count;
+
+            self.blocks[start..end].iter()
+                .fold(Peak { min: 0.0, max: 0.0 }, |acc, block| Peak {
+                    min: acc.min.min(block.min),
+                    max: acc.max.max(block.max),
+                })
+        }).collect();
+
+        Ok(Some(peaks))
+    }
+}
+
+/// Renders a waveform overview as an SVG which stretches to fill whatever
+/// box it is displayed in
+pub fn waveform_svg(peaks: &[Peak]) -> String {
+    let mut points = Vec::with_capacity(peaks.len() * 2);
+
+    // trace along the top of the waveform, then back along the bottom:
+    for (idx, peak) in peaks.iter().enumerate() {
+        points.push(format!("{}.5,{:.3}", idx, 1.0 - peak.max));
+    }
+
+    for (idx, peak) in peaks.iter().enumerate().rev() {
+        points.push(format!("{}.5,{:.3}", idx, 1.0 - peak.min));
+    }
+
+    format!(concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} 2" preserveAspectRatio="none">"#,
+            r##"<polygon fill="#8d8bb0" points="{}"/>"##,
+            r#"</svg>"#),
+        peaks.len(),
+        points.join(" "))
+}
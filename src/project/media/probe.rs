@@ -0,0 +1,80 @@
+use std::convert::TryFrom;
+
+use derive_more::From;
+use mixlab_codec::ffmpeg::{AvError, AvIoError, AvIoReader, IoReader, InputContainer, StreamKind};
+use mixlab_protocol::{MediaMetadata, MediaStreamInfo, MediaStreamKind, Microseconds};
+use tokio::task;
+
+use crate::project::ProjectBaseRef;
+use crate::project::stream::{ReadStream, StreamId};
+
+#[derive(From, Debug)]
+pub enum ProbeError {
+    Av(AvError),
+    Database(rusqlite::Error),
+    Io(<ReadStream as IoReader>::Error),
+    NoSuchStream,
+}
+
+impl From<AvIoError<ReadStream>> for ProbeError {
+    fn from(e: AvIoError<ReadStream>) -> ProbeError {
+        match e {
+            AvIoError::Av(e) => ProbeError::Av(e),
+            AvIoError::Io(e) => ProbeError::Io(e),
+        }
+    }
+}
+
+/// Demuxes the start of a stream to find its container format and the
+/// parameters of each stream within. Fails if the stream is not media ffmpeg
+/// can read
+pub async fn probe(base: ProjectBaseRef, stream_id: StreamId) -> Result<MediaMetadata, ProbeError> {
+    let stream = ReadStream::open(base, stream_id).await?
+        .ok_or(ProbeError::NoSuchStream)?;
+
+    // ReadStream reads from the database synchronously:
+    task::spawn_blocking(move || probe_blocking(stream)).await
+        .expect("join blocking task")
+}
+
+fn probe_blocking(stream: ReadStream) -> Result<MediaMetadata, ProbeError> {
+    let mut container = InputContainer::open(AvIoReader::new(stream))?;
+    container.find_stream_info()?;
+
+    let streams = container.streams().iter()
+        .enumerate()
+        .map(|(index, stream)| {
+            let params = stream.codec_parameters();
+
+            let kind = match stream.kind() {
+                StreamKind::Video => MediaStreamKind::Video {
+                    width: u32::try_from(params.width).unwrap_or(0),
+                    height: u32::try_from(params.height).unwrap_or(0),
+                    frame_rate: stream.frame_rate(),
+                },
+                StreamKind::Audio => MediaStreamKind::Audio {
+                    sample_rate: u32::try_from(params.sample_rate).unwrap_or(0),
+                    channels: u32::try_from(params.channels).unwrap_or(0),
+                },
+                StreamKind::Other => MediaStreamKind::Other,
+            };
+
+            MediaStreamInfo {
+                index,
+                codec: stream.codec_name().map(str::to_owned),
+                kind,
+            }
+        })
+        .collect();
+
+    let duration = container.duration()
+        .map(|duration| duration.round_to_base(1_000_000))
+        .and_then(|micros| u64::try_from(micros).ok())
+        .map(Microseconds);
+
+    Ok(MediaMetadata {
+        format: container.format_name().unwrap_or("unknown").to_owned(),
+        duration,
+        streams,
+    })
+}
@@ -0,0 +1,180 @@
+// Resumable uploads. Each chunk a client sends is committed to the upload's
+// stream before the request completes, so a client whose connection drops
+// can ask for the committed offset and carry on from there. Uploads left idle
+// for longer than EXPIRY are removed by garbage collection.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use mixlab_protocol::MediaId;
+use rusqlite::{params, Connection, OptionalExtension};
+use uuid::Uuid;
+
+use crate::project::ProjectBaseRef;
+use crate::project::stream::{self, StreamId, WriteStream};
+
+use super::{UploadError, UploadInfo};
+
+pub const EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadId(pub Uuid);
+
+/// Receives a single chunk of a resumable upload
+pub struct ChunkUpload {
+    stream: WriteStream,
+}
+
+impl ChunkUpload {
+    pub async fn receive_bytes(&mut self, bytes: &[u8]) -> Result<(), UploadError> {
+        self.stream.write(bytes).await?;
+        Ok(())
+    }
+
+    /// Commits all bytes received and returns the new committed offset.
+    /// Should be called even if the client disconnected part way through the
+    /// chunk, so that it need not resend what was received
+    pub async fn commit(mut self) -> Result<u64, UploadError> {
+        self.stream.finalize().await?;
+        Ok(self.stream.offset())
+    }
+}
+
+pub async fn create(base: &ProjectBaseRef, info: UploadInfo) -> Result<UploadId, UploadError> {
+    if !super::valid_kind(&info.kind) {
+        return Err(UploadError::InvalidKind);
+    }
+
+    let mut stream = stream::create(base.clone()).await?;
+    let stream_id = stream.id();
+    let upload_id = UploadId(Uuid::new_v4());
+
+    base.with_database(move |conn| {
+        conn.execute(r"
+                INSERT INTO uploads (id, name, kind, stream_id, updated_at)
+                VALUES (?, ?, ?, ?, ?)
+            ", params![upload_id.0.to_string(), info.name, info.kind, stream_id.0, now()])
+    }).await?;
+
+    // the stream is referenced by the upload now:
+    stream.keep();
+
+    Ok(upload_id)
+}
+
+/// Committed offset of an upload, or None if there is no such upload
+pub async fn offset(base: &ProjectBaseRef, upload_id: UploadId) -> Result<Option<u64>, UploadError> {
+    Ok(base.with_database(move |conn| {
+        conn.query_row(r"
+                SELECT streams.size FROM uploads
+                INNER JOIN streams ON streams.id = uploads.stream_id
+                WHERE uploads.id = ?
+            ",
+            params![upload_id.0.to_string()],
+            |row| row.get::<_, i64>(0)
+        ).optional()
+    }).await?.map(|size| size as u64))
+}
+
+/// Begins receiving a chunk, which must start at the committed offset
+pub async fn resume(base: &ProjectBaseRef, upload_id: UploadId, offset: u64) -> Result<ChunkUpload, UploadError> {
+    let stream_id = base.with_database(move |conn| -> Result<Option<StreamId>, rusqlite::Error> {
+        conn.execute("UPDATE uploads SET updated_at = ? WHERE id = ?",
+            params![now(), upload_id.0.to_string()])?;
+
+        stream_id(conn, upload_id)
+    }).await?.ok_or(UploadError::NoSuchUpload)?;
+
+    // the upload's stream exists, so it must be held by another chunk:
+    let mut stream = stream::resume(base.clone(), stream_id).await?
+        .ok_or(UploadError::Busy)?;
+
+    // the stream must survive this chunk even if the client disconnects:
+    stream.keep();
+
+    if stream.offset() != offset {
+        return Err(UploadError::OffsetMismatch(stream.offset()));
+    }
+
+    Ok(ChunkUpload { stream })
+}
+
+pub async fn finalize(base: &ProjectBaseRef, upload_id: UploadId) -> Result<MediaId, UploadError> {
+    let upload = base.with_database(move |conn| -> Result<Option<(UploadInfo, StreamId)>, rusqlite::Error> {
+        conn.query_row("SELECT name, kind, stream_id FROM uploads WHERE id = ?",
+            params![upload_id.0.to_string()],
+            |row| Ok((UploadInfo { name: row.get(0)?, kind: row.get(1)? }, StreamId(row.get(2)?)))
+        ).optional()
+    }).await?;
+
+    let (info, stream_id) = upload.ok_or(UploadError::NoSuchUpload)?;
+
+    // claim the stream as a writer would, so that no chunk can append to it
+    // while it is made into media. the claim is released on return, once the
+    // upload no longer refers to the stream:
+    let mut claim = stream::resume(base.clone(), stream_id).await?
+        .ok_or(UploadError::Busy)?;
+
+    claim.keep();
+
+    match super::create_media(base, info, stream_id).await {
+        Ok((media_id, media_stream_id)) => {
+            let duplicate = media_stream_id != stream_id;
+            remove(base, upload_id, duplicate).await?;
+            Ok(media_id)
+        }
+        Err(UploadError::Unreadable(e)) => {
+            // unreadable media will not become readable by resuming
+            remove(base, upload_id, true).await?;
+            Err(UploadError::Unreadable(e))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn abort(base: &ProjectBaseRef, upload_id: UploadId) -> Result<(), UploadError> {
+    if remove(base, upload_id, true).await? {
+        Ok(())
+    } else {
+        Err(UploadError::NoSuchUpload)
+    }
+}
+
+/// Deletes uploads idle for longer than EXPIRY, leaving their streams for
+/// garbage collection. Must be called within a transaction
+pub fn expire_blocking(conn: &Connection) -> Result<usize, rusqlite::Error> {
+    conn.execute("DELETE FROM uploads WHERE updated_at < ?",
+        params![now() - EXPIRY.as_secs() as i64])
+}
+
+async fn remove(base: &ProjectBaseRef, upload_id: UploadId, delete_stream: bool) -> Result<bool, UploadError> {
+    Ok(base.with_database(move |conn| -> Result<bool, rusqlite::Error> {
+        let txn = conn.transaction()?;
+
+        let stream_id = match stream_id(&txn, upload_id)? {
+            Some(stream_id) => stream_id,
+            None => return Ok(false),
+        };
+
+        txn.execute("DELETE FROM uploads WHERE id = ?", params![upload_id.0.to_string()])?;
+
+        if delete_stream {
+            stream::delete_blocking(&txn, stream_id)?;
+        }
+
+        txn.commit()?;
+        Ok(true)
+    }).await?)
+}
+
+fn stream_id(conn: &Connection, upload_id: UploadId) -> Result<Option<StreamId>, rusqlite::Error> {
+    conn.query_row("SELECT stream_id FROM uploads WHERE id = ?",
+        params![upload_id.0.to_string()],
+        |row| Ok(StreamId(row.get(0)?))
+    ).optional()
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
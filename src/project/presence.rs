@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+
+use mixlab_protocol::{PeerId, Peer, PresenceOp, PresenceUpdate, Role};
+
+use crate::util::Sequence;
+
+// presence is ephemeral and never persisted, it only describes who is
+// connected to the project right now
+#[derive(Debug)]
+pub struct Presence {
+    state: Mutex<PresenceState>,
+    tx: broadcast::Sender<PresenceUpdate>,
+}
+
+pub type PresenceRef = Arc<Presence>;
+
+#[derive(Debug)]
+struct PresenceState {
+    seq: Sequence,
+    peers: BTreeMap<PeerId, Peer>,
+}
+
+pub type PresenceEvents = broadcast::Receiver<PresenceUpdate>;
+
+#[derive(Debug)]
+pub struct PeerHandle {
+    id: PeerId,
+    presence: PresenceRef,
+}
+
+impl Presence {
+    pub fn new() -> PresenceRef {
+        let (tx, _) = broadcast::channel(64);
+
+        Arc::new(Presence {
+            state: Mutex::new(PresenceState {
+                seq: Sequence::new(),
+                peers: BTreeMap::new(),
+            }),
+            tx,
+        })
+    }
+
+    pub fn join(self: &PresenceRef, role: Role) -> (PeerHandle, PresenceEvents) {
+        let mut state = self.state.lock().expect("lock presence state");
+
+        let id = PeerId(state.seq.next());
+
+        let peer = Peer {
+            name: format!("User {}", id.0),
+            role,
+            selected: None,
+            pointer: None,
+        };
+
+        state.peers.insert(id, peer.clone());
+
+        // subscribe while holding the lock so that no updates are missed
+        // between the peers snapshot and the subscription:
+        let rx = self.tx.subscribe();
+        let _ = self.tx.send(PresenceUpdate::Update(id, peer));
+
+        let handle = PeerHandle {
+            id,
+            presence: self.clone(),
+        };
+
+        (handle, rx)
+    }
+
+    /// Returns all connected peers as seen by the given peer
+    pub fn snapshot(&self, you: PeerId) -> PresenceUpdate {
+        let state = self.state.lock().expect("lock presence state");
+
+        PresenceUpdate::Peers {
+            you,
+            peers: state.peers.iter()
+                .map(|(id, peer)| (*id, peer.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl PeerHandle {
+    pub fn id(&self) -> PeerId {
+        self.id
+    }
+
+    pub fn update(&self, op: PresenceOp) {
+        let mut state = self.presence.state.lock().expect("lock presence state");
+
+        let peer = match state.peers.get_mut(&self.id) {
+            Some(peer) => peer,
+            None => return,
+        };
+
+        match op {
+            PresenceOp::SetName(name) => {
+                let name = name.trim();
+
+                if name.is_empty() {
+                    return;
+                }
+
+                peer.name = name.chars().take(32).collect();
+            }
+            PresenceOp::Select(selected) => {
+                peer.selected = selected;
+            }
+            PresenceOp::Pointer(pointer) => {
+                peer.pointer = pointer;
+            }
+        }
+
+        let _ = self.presence.tx.send(PresenceUpdate::Update(self.id, peer.clone()));
+    }
+}
+
+impl Drop for PeerHandle {
+    fn drop(&mut self) {
+        let mut state = self.presence.state.lock().expect("lock presence state");
+        state.peers.remove(&self.id);
+        let _ = self.presence.tx.send(PresenceUpdate::Leave(self.id));
+    }
+}
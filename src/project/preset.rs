@@ -0,0 +1,168 @@
+// Named module params, kept per module kind for applying to any module of
+// that kind. Factory presets for the synthesis modules are bundled here and
+// listed alongside the ones saved in the project.
+
+use derive_more::From;
+use mixlab_protocol as protocol;
+use mixlab_protocol::{AmplifierParams, Decibel, EnvelopeParams, EqThreeParams, FmSineParams, ModuleParams, OscillatorParams, PresetId, PresetRef, Waveform};
+use rusqlite::{params, OptionalExtension};
+
+use crate::engine::EngineError;
+use crate::project::ProjectBaseRef;
+
+#[derive(From, Debug)]
+pub enum PresetError {
+    Database(rusqlite::Error),
+    Engine(EngineError),
+    NoSuchModule,
+    NoSuchPreset,
+}
+
+fn factory() -> Vec<(&'static str, ModuleParams)> {
+    fn oscillator(freq: f64, waveform: Waveform) -> ModuleParams {
+        ModuleParams::Oscillator(OscillatorParams { freq, waveform })
+    }
+
+    fn fm_sine(freq_lo: f64, freq_hi: f64) -> ModuleParams {
+        ModuleParams::FmSine(FmSineParams { freq_lo, freq_hi })
+    }
+
+    fn amplifier(amplitude: f64, mod_depth: f64) -> ModuleParams {
+        ModuleParams::Amplifier(AmplifierParams { amplitude, mod_depth })
+    }
+
+    fn envelope(attack_ms: f64, decay_ms: f64, sustain_amplitude: f64, release_ms: f64) -> ModuleParams {
+        ModuleParams::Envelope(EnvelopeParams { attack_ms, decay_ms, sustain_amplitude, release_ms })
+    }
+
+    fn eq_three(lo: f64, mid: f64, hi: f64) -> ModuleParams {
+        ModuleParams::EqThree(EqThreeParams { gain_lo: Decibel(lo), gain_mid: Decibel(mid), gain_hi: Decibel(hi) })
+    }
+
+    vec![
+        ("Tuning A", oscillator(440.0, Waveform::Sine)),
+        ("Sub Bass", oscillator(55.0, Waveform::Sine)),
+        ("Square Lead", oscillator(220.0, Waveform::Square)),
+        ("Saw Lead", oscillator(220.0, Waveform::Saw)),
+        ("Slow LFO", oscillator(0.5, Waveform::Triangle)),
+        ("Vibrato", fm_sine(435.0, 445.0)),
+        ("Siren", fm_sine(600.0, 1200.0)),
+        ("Sweep", fm_sine(40.0, 4000.0)),
+        ("Unity", amplifier(1.0, 0.0)),
+        ("Tremolo", amplifier(1.0, 0.5)),
+        ("Full Modulation", amplifier(1.0, 1.0)),
+        ("Pluck", envelope(2.0, 150.0, 0.0, 100.0)),
+        ("Organ", envelope(5.0, 0.0, 1.0, 20.0)),
+        ("Pad", envelope(800.0, 400.0, 0.8, 1500.0)),
+        ("Swell", envelope(2000.0, 0.0, 1.0, 2000.0)),
+        ("Flat", eq_three(0.0, 0.0, 0.0)),
+        ("Low Cut", eq_three(-24.0, 0.0, 0.0)),
+        ("Voice", eq_three(-6.0, 3.0, -3.0)),
+        ("Telephone", eq_three(-24.0, 6.0, -24.0)),
+    ]
+}
+
+/// Saves the params of a module as a preset for modules of its kind
+pub async fn save(base: &ProjectBaseRef, name: String, params: &ModuleParams) -> Result<PresetId, rusqlite::Error> {
+    let kind = params.kind();
+    let serialized = serde_json::to_string(params).expect("serde_json::to_string");
+
+    let preset_id = base.with_database(move |conn| -> Result<PresetId, rusqlite::Error> {
+        conn.execute("INSERT INTO presets (kind, name, serialized) VALUES (?, ?, ?)",
+            params![kind, name, serialized])?;
+
+        Ok(PresetId(conn.last_insert_rowid()))
+    }).await?;
+
+    let _ = base.notify.presets.broadcast(());
+
+    Ok(preset_id)
+}
+
+pub async fn delete(base: &ProjectBaseRef, preset_id: PresetId) -> Result<(), rusqlite::Error> {
+    base.with_database(move |conn| -> Result<(), rusqlite::Error> {
+        conn.execute("DELETE FROM presets WHERE id = ?", params![preset_id.0])?;
+        Ok(())
+    }).await?;
+
+    let _ = base.notify.presets.broadcast(());
+
+    Ok(())
+}
+
+/// Returns None if there is no such preset for modules of the given kind
+pub async fn load(base: &ProjectBaseRef, kind: &'static str, preset: PresetRef) -> Result<Option<ModuleParams>, rusqlite::Error> {
+    let preset_id = match preset {
+        PresetRef::Factory(name) => {
+            return Ok(factory().into_iter()
+                .find(|(factory_name, params)| *factory_name == name && params.kind() == kind)
+                .map(|(_, params)| params));
+        }
+        PresetRef::Saved(preset_id) => preset_id,
+    };
+
+    let serialized = base.with_database(move |conn| -> Result<Option<String>, rusqlite::Error> {
+        conn.query_row("SELECT serialized FROM presets WHERE id = ? AND kind = ?",
+            params![preset_id.0, kind],
+            |row| row.get(0)
+        ).optional()
+    }).await?;
+
+    Ok(serialized.and_then(|serialized| parse(preset_id, &serialized)))
+}
+
+pub async fn library(base: &ProjectBaseRef) -> Result<protocol::PresetLibrary, rusqlite::Error> {
+    let rows = base.with_database(|conn| -> Result<Vec<(PresetId, String, String)>, rusqlite::Error> {
+        conn.prepare("SELECT id, kind, name FROM presets ORDER BY kind ASC, name ASC, id ASC")?
+            .query_map(rusqlite::NO_PARAMS,
+                |row| Ok((PresetId(row.get(0)?), row.get(1)?, row.get(2)?)))?
+            .collect()
+    }).await?;
+
+    let factory = factory().into_iter()
+        .map(|(name, params)| protocol::PresetItem {
+            preset: PresetRef::Factory(name.to_owned()),
+            kind: params.kind().to_owned(),
+            name: name.to_owned(),
+        });
+
+    let saved = rows.into_iter()
+        .map(|(id, kind, name)| protocol::PresetItem {
+            preset: PresetRef::Saved(id),
+            kind,
+            name,
+        });
+
+    Ok(protocol::PresetLibrary { presets: factory.chain(saved).collect() })
+}
+
+/// Fits preset params to the module they are applied to. Params which are
+/// part of the module's structure or state rather than its sound are kept
+pub fn fit(current: &ModuleParams, preset: ModuleParams) -> ModuleParams {
+    match (current, preset) {
+        (ModuleParams::Mixer(current), ModuleParams::Mixer(mut preset)) => {
+            // channel count determines the mixer's inputs:
+            preset.channels.resize(current.channels.len(), Default::default());
+            ModuleParams::Mixer(preset)
+        }
+        (ModuleParams::StreamOutput(current), ModuleParams::StreamOutput(mut preset)) => {
+            // sequence numbers encode connect and disconnect requests, and
+            // stream output ignores params not newer than its own:
+            preset.seq = current.seq + 1;
+            preset.connect_seq = current.connect_seq;
+            preset.disconnect_seq = current.disconnect_seq;
+            ModuleParams::StreamOutput(preset)
+        }
+        (_, preset) => preset,
+    }
+}
+
+pub(super) fn parse(preset_id: PresetId, serialized: &str) -> Option<ModuleParams> {
+    match serde_json::from_str(serialized) {
+        Ok(params) => Some(params),
+        Err(e) => {
+            eprintln!("preset: could not parse {:?}: {:?}", preset_id, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use derive_more::From;
+use mixlab_protocol as protocol;
+use mixlab_protocol::{ModuleId, ModuleParams, SceneId};
+use rusqlite::{params, OptionalExtension};
+
+use crate::engine::EngineError;
+use crate::project::ProjectBaseRef;
+
+pub type SceneParams = Vec<(ModuleId, ModuleParams)>;
+
+#[derive(From, Debug)]
+pub enum SceneError {
+    Database(rusqlite::Error),
+    Engine(EngineError),
+    NoSuchScene,
+}
+
+pub async fn save(base: &ProjectBaseRef, name: String, scene: &SceneParams) -> Result<SceneId, rusqlite::Error> {
+    let serialized = serde_json::to_string(scene).expect("serde_json::to_string");
+
+    let scene_id = base.with_database(move |conn| -> Result<SceneId, rusqlite::Error> {
+        conn.execute("INSERT INTO scenes (name, serialized) VALUES (?, ?)",
+            params![name, serialized])?;
+
+        Ok(SceneId(conn.last_insert_rowid()))
+    }).await?;
+
+    let _ = base.notify.scenes.broadcast(());
+
+    Ok(scene_id)
+}
+
+pub async fn delete(base: &ProjectBaseRef, scene_id: SceneId) -> Result<(), rusqlite::Error> {
+    base.with_database(move |conn| -> Result<(), rusqlite::Error> {
+        conn.execute("DELETE FROM scenes WHERE id = ?", params![scene_id.0])?;
+        Ok(())
+    }).await?;
+
+    let _ = base.notify.scenes.broadcast(());
+
+    Ok(())
+}
+
+pub async fn load(base: &ProjectBaseRef, scene_id: SceneId) -> Result<Option<SceneParams>, rusqlite::Error> {
+    let serialized = base.with_database(move |conn| -> Result<Option<String>, rusqlite::Error> {
+        conn.query_row("SELECT serialized FROM scenes WHERE id = ?",
+            params![scene_id.0],
+            |row| row.get(0)
+        ).optional()
+    }).await?;
+
+    Ok(serialized.and_then(|serialized| parse(scene_id, &serialized)))
+}
+
+pub async fn library(base: &ProjectBaseRef) -> Result<protocol::SceneLibrary, rusqlite::Error> {
+    let rows = base.with_database(|conn| -> Result<Vec<(SceneId, String, String)>, rusqlite::Error> {
+        conn.prepare("SELECT id, name, serialized FROM scenes ORDER BY id ASC")?
+            .query_map(rusqlite::NO_PARAMS,
+                |row| Ok((SceneId(row.get(0)?), row.get(1)?, row.get(2)?)))?
+            .collect()
+    }).await?;
+
+    let scenes = rows.into_iter()
+        .map(|(id, name, serialized)| {
+            let modules = parse(id, &serialized)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(module_id, _)| module_id)
+                .collect();
+
+            protocol::SceneItem { id, name, modules }
+        })
+        .collect();
+
+    Ok(protocol::SceneLibrary { scenes })
+}
+
+pub(super) fn parse(scene_id: SceneId, serialized: &str) -> Option<SceneParams> {
+    match serde_json::from_str(serialized) {
+        Ok(scene) => Some(scene),
+        Err(e) => {
+            eprintln!("scene: could not parse {:?}: {:?}", scene_id, e);
+            None
+        }
+    }
+}
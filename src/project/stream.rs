@@ -1,42 +1,441 @@
 use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::io::SeekFrom;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
 use std::mem;
+use std::str::FromStr;
 
-use rusqlite::{params, OptionalExtension, types::ValueRef};
-use mixlab_codec::ffmpeg;
+use derive_more::From;
+use rusqlite::{params, Connection, OptionalExtension, types::ValueRef};
+use mixlab_codec::ffmpeg::{self, IoReader};
+use sha2::{Digest, Sha256};
+use tokio::task;
 
-use crate::project::ProjectBaseRef;
+use crate::project::{ProjectBase, ProjectBaseRef};
+
+pub mod object;
 
 const STREAM_BLOB_SIZE: usize = 1024 * 1024;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct StreamId(pub i64);
 
+#[derive(From, Debug)]
+pub enum StreamError {
+    Database(rusqlite::Error),
+    Io(io::Error),
+}
+
+/// Where new streams are written. Existing streams are read from wherever
+/// they were written, whichever backend is in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    // 1 MiB blobs in the project database
+    Database,
+    // files in the project's object store, see object.rs
+    Files,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "database" => Ok(StorageBackend::Database),
+            "files" => Ok(StorageBackend::Files),
+            _ => Err(format!("unknown storage backend {:?}, expected database or files", s)),
+        }
+    }
+}
+
+// how an individual stream is stored, the storage column of streams:
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Storage {
+    Blobs,
+    // in a scratch file while hash is null, in the object named by hash
+    // once sealed:
+    File,
+}
+
+impl Storage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Storage::Blobs => "blobs",
+            Storage::File => "file",
+        }
+    }
+
+    fn from_column(value: &str) -> Self {
+        match value {
+            "file" => Storage::File,
+            _ => Storage::Blobs,
+        }
+    }
+}
+
 pub async fn create(base: ProjectBaseRef) -> Result<WriteStream, rusqlite::Error> {
-    let stream_id = base.with_database(|conn| -> Result<StreamId, rusqlite::Error> {
-        conn.execute("INSERT INTO streams (size) VALUES (0)", rusqlite::NO_PARAMS)?;
-        Ok(StreamId(conn.last_insert_rowid()))
+    let storage = match base.storage {
+        StorageBackend::Database => Storage::Blobs,
+        StorageBackend::Files => Storage::File,
+    };
+
+    let stream_id = base.with_database({
+        let base = base.clone();
+        move |conn| -> Result<StreamId, rusqlite::Error> {
+            conn.execute("INSERT INTO streams (size, storage) VALUES (0, ?)",
+                params![storage.as_str()])?;
+
+            let stream_id = StreamId(conn.last_insert_rowid());
+
+            // register while still holding the database so that garbage
+            // collection never sees this stream unregistered:
+            base.writing.lock().expect("lock writing streams").insert(stream_id);
+
+            Ok(stream_id)
+        }
     }).await?;
 
     Ok(WriteStream {
         base,
         id: stream_id,
+        storage,
+        file: None,
         offset: 0,
         buff: Vec::with_capacity(STREAM_BLOB_SIZE),
+        keep: false,
     })
 }
 
-// TODO - automatically clean up write stream on drop if not explicitly finalized
+/// Reopens a stream for writing at its end, eg. to continue an upload.
+/// Returns None if the stream does not exist, is already being written, or
+/// has been sealed into the object store
+pub async fn resume(base: ProjectBaseRef, stream_id: StreamId) -> Result<Option<WriteStream>, rusqlite::Error> {
+    let stream = base.with_database({
+        let base = base.clone();
+        move |conn| -> Result<Option<(i64, Storage)>, rusqlite::Error> {
+            let stream = conn.query_row("SELECT size, storage, hash FROM streams WHERE id = ?",
+                params![stream_id.0],
+                |row| Ok((
+                    row.get::<_, i64>(0)?,
+                    Storage::from_column(&row.get::<_, String>(1)?),
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            ).optional()?;
+
+            match stream {
+                Some((_, Storage::File, Some(_))) => Ok(None),
+                Some((size, storage, _)) => {
+                    let mut writing = base.writing.lock().expect("lock writing streams");
+
+                    // only one writer may append to a stream at a time:
+                    if writing.insert(stream_id) {
+                        Ok(Some((size, storage)))
+                    } else {
+                        Ok(None)
+                    }
+                }
+                None => Ok(None),
+            }
+        }
+    }).await?;
+
+    Ok(stream.map(|(size, storage)| WriteStream {
+        base,
+        id: stream_id,
+        storage,
+        file: None,
+        offset: size,
+        buff: Vec::with_capacity(STREAM_BLOB_SIZE),
+        keep: false,
+    }))
+}
+
+/// SHA-256 of a complete stream's contents as lowercase hex. Streams written
+/// to files are moved into the object store under this hash, after which
+/// they can no longer be written
+pub async fn seal(base: ProjectBaseRef, stream_id: StreamId) -> Result<Option<String>, StreamError> {
+    let stream = base.with_database(move |conn| {
+        conn.query_row("SELECT storage, hash FROM streams WHERE id = ?",
+            params![stream_id.0],
+            |row| Ok((
+                Storage::from_column(&row.get::<_, String>(0)?),
+                row.get::<_, Option<String>>(1)?,
+            ))
+        ).optional()
+    }).await?;
+
+    let storage = match stream {
+        Some((Storage::File, Some(hash))) => return Ok(Some(hash)),
+        Some((storage, _)) => storage,
+        None => return Ok(None),
+    };
+
+    let stream = match ReadStream::open(base.clone(), stream_id).await? {
+        Some(stream) => stream,
+        None => return Ok(None),
+    };
+
+    // ReadStream reads synchronously, taking the database lock for each blob
+    // rather than for the whole stream when reading from the database:
+    let hash = task::spawn_blocking(move || hash_blocking(stream)).await
+        .expect("join blocking task")?;
+
+    if storage == Storage::File {
+        base.with_database({
+            let base = base.clone();
+            let hash = hash.clone();
+            move |conn| -> Result<(), StreamError> {
+                // readers look in scratch if the object is missing, so a
+                // crash between these two leaves the stream readable, and
+                // garbage collection finishes the move:
+                conn.execute("UPDATE streams SET hash = ? WHERE id = ?",
+                    params![hash, stream_id.0])?;
+
+                base.objects.commit_scratch(stream_id, &hash)?;
+                Ok(())
+            }
+        }).await?;
+    }
+
+    Ok(Some(hash))
+}
+
+fn hash_blocking(mut stream: ReadStream) -> Result<String, StreamError> {
+    let mut hasher = Sha256::new();
+    let mut buff = vec![0u8; STREAM_BLOB_SIZE];
+
+    loop {
+        let len = IoReader::read(&mut stream, &mut buff)?;
+
+        if len == 0 {
+            break;
+        }
+
+        hasher.update(&buff[..len]);
+    }
+
+    Ok(object::hex(&hasher.finalize()))
+}
+
+/// Deletes a stream and all of its blobs. Must be called within a
+/// transaction. Objects are shared between streams with identical content,
+/// so are left for garbage collection to remove
+pub fn delete_blocking(conn: &Connection, stream_id: StreamId) -> Result<(), rusqlite::Error> {
+    conn.execute("DELETE FROM blobs WHERE stream_id = ?", params![stream_id.0])?;
+    conn.execute("DELETE FROM streams WHERE id = ?", params![stream_id.0])?;
+    Ok(())
+}
+
+/// Removes objects and scratch files no longer referenced by any stream.
+/// Must be called with the database lock held, see object.rs
+pub fn sweep_objects_blocking(base: &ProjectBase, conn: &Connection) -> Result<usize, StreamError> {
+    let mut objects = HashSet::new();
+    let mut sealed = HashMap::new();
+    let mut unsealed = HashSet::new();
+
+    let mut stmt = conn.prepare("SELECT id, hash FROM streams WHERE storage = 'file'")?;
+    let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+
+    while let Some(row) = rows.next()? {
+        let stream_id = StreamId(row.get(0)?);
+
+        match row.get::<_, Option<String>>(1)? {
+            Some(hash) => {
+                objects.insert(hash.clone());
+                sealed.insert(stream_id, hash);
+            }
+            None => {
+                unsealed.insert(stream_id);
+            }
+        }
+    }
+
+    let writing = base.writing.lock().expect("lock writing streams");
+
+    let removed = base.objects.sweep(&objects, |stream_id| {
+        if writing.contains(&stream_id) || unsealed.contains(&stream_id) {
+            return true;
+        }
+
+        if let Some(hash) = sealed.get(&stream_id) {
+            // sealed, but not moved into the object store before a crash:
+            if let Err(e) = base.objects.commit_scratch(stream_id, hash) {
+                eprintln!("stream: could not move {:?} into object store: {:?}", stream_id, e);
+            }
+
+            return true;
+        }
+
+        false
+    })?;
+
+    Ok(removed)
+}
+
+/// Moves streams stored as blobs in the database into the object store one
+/// at a time, then vacuums the database to give back the space. Readers of a
+/// stream which is migrated switch over to its object once the blobs are gone
+pub async fn migrate_to_files(base: ProjectBaseRef) {
+    match migrate_blobs(&base).await {
+        Ok(0) => {}
+        Ok(count) => {
+            eprintln!("stream: moved {} streams from the database into the object store", count);
+        }
+        Err(e) => {
+            eprintln!("stream: could not migrate streams to object store: {:?}", e);
+        }
+    }
+}
+
+async fn migrate_blobs(base: &ProjectBaseRef) -> Result<usize, StreamError> {
+    let mut migrated = 0;
+    let mut failed = HashSet::new();
+
+    loop {
+        // claim the next stream by registering it as being written, which
+        // keeps writers out and garbage collection away from its scratch
+        // file. streams not yet referenced by media may still be written:
+        let skip = failed.clone();
+        let next = base.with_database({
+            let base = base.clone();
+            move |conn| -> Result<Option<StreamId>, rusqlite::Error> {
+                let candidates = conn.prepare(r"
+                        SELECT id FROM streams
+                        WHERE storage = 'blobs' AND id IN (SELECT stream_id FROM media)
+                        ORDER BY id
+                    ")?
+                    .query_map(rusqlite::NO_PARAMS, |row| Ok(StreamId(row.get(0)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let mut writing = base.writing.lock().expect("lock writing streams");
+
+                Ok(candidates.into_iter()
+                    .filter(|stream_id| !skip.contains(stream_id))
+                    .find(|stream_id| writing.insert(*stream_id)))
+            }
+        }).await?;
+
+        let stream_id = match next {
+            Some(stream_id) => stream_id,
+            None => break,
+        };
+
+        let result = migrate_stream(base, stream_id).await;
+
+        base.with_database({
+            let base = base.clone();
+            move |_| base.writing.lock().expect("lock writing streams").remove(&stream_id)
+        }).await;
+
+        match result {
+            Ok(()) => migrated += 1,
+            Err(e) => {
+                eprintln!("stream: could not move {:?} into object store: {:?}", stream_id, e);
+                failed.insert(stream_id);
+            }
+        }
+    }
+
+    if migrated > 0 {
+        base.with_database(|conn| conn.execute_batch("VACUUM")).await?;
+    }
+
+    Ok(migrated)
+}
+
+async fn migrate_stream(base: &ProjectBaseRef, stream_id: StreamId) -> Result<(), StreamError> {
+    let stream = match ReadStream::open(base.clone(), stream_id).await? {
+        Some(stream) => stream,
+        None => return Ok(()),
+    };
+
+    let hash = task::spawn_blocking({
+        let base = base.clone();
+        move || {
+            let result = copy_to_scratch(&base, stream, stream_id);
+
+            if result.is_err() {
+                let _ = base.objects.remove_scratch(stream_id);
+            }
+
+            result
+        }
+    }).await.expect("join blocking task")?;
+
+    base.with_database({
+        let base = base.clone();
+        move |conn| -> Result<(), StreamError> {
+            let txn = conn.transaction()?;
+
+            let updated = txn.execute(r"
+                    UPDATE streams SET storage = 'file', hash = ?
+                    WHERE id = ? AND storage = 'blobs'
+                ", params![hash, stream_id.0])?;
+
+            if updated == 0 {
+                // deleted while we were copying it
+                base.objects.remove_scratch(stream_id)?;
+                return Ok(());
+            }
+
+            txn.execute("DELETE FROM blobs WHERE stream_id = ?", params![stream_id.0])?;
+            txn.commit()?;
+
+            // as in seal, readers fall back to the scratch file if this fails:
+            base.objects.commit_scratch(stream_id, &hash)?;
+            Ok(())
+        }
+    }).await
+}
+
+fn copy_to_scratch(base: &ProjectBase, mut stream: ReadStream, stream_id: StreamId) -> Result<String, StreamError> {
+    let mut file = base.objects.open_scratch(stream_id, 0)?;
+    let mut hasher = Sha256::new();
+    let mut buff = vec![0u8; STREAM_BLOB_SIZE];
+
+    loop {
+        let len = IoReader::read(&mut stream, &mut buff)?;
+
+        if len == 0 {
+            break;
+        }
+
+        hasher.update(&buff[..len]);
+        file.write_all(&buff[..len])?;
+    }
+
+    file.sync_data()?;
+
+    Ok(object::hex(&hasher.finalize()))
+}
+
+/// Streams being written are not yet referenced by anything, but are safe
+/// from garbage collection until the WriteStream is dropped. Streams dropped
+/// without being marked to keep are deleted.
 pub struct WriteStream {
     base: ProjectBaseRef,
     id: StreamId,
+    storage: Storage,
+    // scratch file for streams stored in files, opened on first flush:
+    file: Option<File>,
     offset: i64,
     buff: Vec<u8>,
+    keep: bool,
 }
 
 impl WriteStream {
-    pub async fn write(&mut self, mut bytes: &[u8]) -> Result<(), rusqlite::Error> {
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+
+    /// Number of bytes written to the stream so far, including bytes not yet
+    /// flushed
+    pub fn offset(&self) -> u64 {
+        self.offset as u64 + self.buff.len() as u64
+    }
+
+    pub async fn write(&mut self, mut bytes: &[u8]) -> Result<(), StreamError> {
         while !bytes.is_empty() {
             let take = cmp::min(bytes.len(), STREAM_BLOB_SIZE - self.buff.len());
 
@@ -54,27 +453,67 @@ impl WriteStream {
         Ok(())
     }
 
-    pub async fn finalize(mut self) -> Result<StreamId, rusqlite::Error> {
+    /// Flushes all written bytes, after which the stream can be read
+    pub async fn finalize(&mut self) -> Result<StreamId, StreamError> {
         self.flush().await?;
+
+        if let Some(file) = self.file.take() {
+            task::spawn_blocking(move || file.sync_data()).await
+                .expect("join blocking task")?;
+        }
+
         Ok(self.id)
     }
 
-    async fn flush(&mut self) -> Result<(), rusqlite::Error> {
+    /// Keeps the stream when dropped. Callers should record a reference to
+    /// the stream first, or it will be garbage collected
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+
+    async fn flush(&mut self) -> Result<(), StreamError> {
         if self.buff.len() > 0 {
             let id = self.id;
             let offset = self.offset;
             let buff_len = i64::try_from(self.buff.len()).expect("buff.len as i64");
             let buff = mem::take(&mut self.buff);
 
-            self.base.with_database(move |conn| -> Result<(), rusqlite::Error> {
-                conn.execute(r"INSERT INTO blobs (stream_id, offset, data) VALUES (?, ?, ?)",
-                    params![id.0, offset, &buff])?;
+            match self.storage {
+                Storage::Blobs => {
+                    self.base.with_database(move |conn| -> Result<(), rusqlite::Error> {
+                        conn.execute(r"INSERT INTO blobs (stream_id, offset, data) VALUES (?, ?, ?)",
+                            params![id.0, offset, &buff])?;
 
-                conn.execute(r"UPDATE streams SET size = ? WHERE id = ?",
-                    params![offset + buff_len, id.0])?;
+                        conn.execute(r"UPDATE streams SET size = ? WHERE id = ?",
+                            params![offset + buff_len, id.0])?;
 
-                Ok(())
-            }).await?;
+                        Ok(())
+                    }).await?;
+                }
+                Storage::File => {
+                    let base = self.base.clone();
+                    let file = self.file.take();
+
+                    // bytes past the size recorded in the database are not
+                    // committed, and are truncated when the file is reopened:
+                    let file = task::spawn_blocking(move || -> Result<File, io::Error> {
+                        let mut file = match file {
+                            Some(file) => file,
+                            None => base.objects.open_scratch(id, offset as u64)?,
+                        };
+
+                        file.write_all(&buff)?;
+                        Ok(file)
+                    }).await.expect("join blocking task")?;
+
+                    self.file = Some(file);
+
+                    self.base.with_database(move |conn| {
+                        conn.execute(r"UPDATE streams SET size = ? WHERE id = ?",
+                            params![offset + buff_len, id.0])
+                    }).await?;
+                }
+            }
 
             self.offset += buff_len;
         }
@@ -83,38 +522,104 @@ impl WriteStream {
     }
 }
 
+impl Drop for WriteStream {
+    fn drop(&mut self) {
+        let base = self.base.clone();
+        let id = self.id;
+        let keep = self.keep;
+
+        tokio::spawn(async move {
+            let result = base.with_database({
+                let base = base.clone();
+                move |conn| -> Result<(), StreamError> {
+                    // unregister even if deleting fails, leaving the stream
+                    // for garbage collection:
+                    base.writing.lock().expect("lock writing streams").remove(&id);
+
+                    if !keep {
+                        let txn = conn.transaction()?;
+                        delete_blocking(&txn, id)?;
+                        txn.commit()?;
+
+                        base.objects.remove_scratch(id)?;
+                    }
+
+                    Ok(())
+                }
+            }).await;
+
+            if let Err(e) = result {
+                eprintln!("stream: could not clean up {:?}: {:?}", id, e);
+            }
+        });
+    }
+}
+
 #[derive(Debug)]
 pub struct ReadStream {
     base: ProjectBaseRef,
     stream_id: StreamId,
     offset: i64,
     size: i64,
+    source: Source,
+}
+
+#[derive(Debug)]
+enum Source {
+    Blobs,
+    File {
+        hash: Option<String>,
+        // opened on first read. reads are buffered because not all readers
+        // read as much at once as ffmpeg does, eg. downloads:
+        file: Option<BufReader<File>>,
+        // offset the file is at, seeking would discard the buffer even when
+        // reading on from where the last read left off:
+        position: u64,
+    },
 }
 
 impl ReadStream {
     pub async fn open(base: ProjectBaseRef, stream_id: StreamId) -> Result<Option<Self>, rusqlite::Error> {
         Ok(base.with_database(move |conn| {
             conn.query_row(
-                r"SELECT size FROM streams WHERE rowid = ?",
+                r"SELECT size, storage, hash FROM streams WHERE rowid = ?",
                 &[stream_id.0],
-                |row| row.get(0)
+                |row| Ok((row.get::<_, i64>(0)?, source(row.get(1)?, row.get(2)?)))
             ).optional()
-        }).await?.map(|size| {
+        }).await?.map(|(size, source)| {
             ReadStream {
                 base,
                 stream_id,
                 offset: 0,
                 size: size,
+                source,
             }
         }))
     }
-}
 
-impl ffmpeg::IoReader for ReadStream {
-    type Error = rusqlite::Error;
-    const BUFFER_SIZE: usize = STREAM_BLOB_SIZE;
+    /// Looks up where the stream is stored again, returning true if it has
+    /// moved into the object store since we last looked
+    fn reopen(&mut self) -> Result<bool, rusqlite::Error> {
+        let stream_id = self.stream_id;
 
-    fn read(&mut self, out: &mut [u8]) -> Result<usize, Self::Error> {
+        let source = self.base.with_database_in_blocking_context(|conn| {
+            conn.query_row(
+                r"SELECT storage, hash FROM streams WHERE rowid = ?",
+                &[stream_id.0],
+                |row| Ok(source(row.get(0)?, row.get(1)?))
+            ).optional()
+        })?;
+
+        match source {
+            Some(source @ Source::File { .. }) => {
+                self.source = source;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn read_blobs(&mut self, out: &mut [u8]) -> Result<usize, rusqlite::Error> {
         let mut read_bytes = 0;
 
         self.base.with_database_in_blocking_context(|conn| -> Result<(), rusqlite::Error> {
@@ -168,6 +673,98 @@ impl ffmpeg::IoReader for ReadStream {
             Ok(())
         })?;
 
+        Ok(read_bytes)
+    }
+
+    fn read_file(&mut self, out: &mut [u8]) -> Result<usize, io::Error> {
+        let base = &self.base;
+        let stream_id = self.stream_id;
+        let offset = self.offset as u64;
+
+        let (file, position) = match &mut self.source {
+            Source::File { hash, file, position } => {
+                if file.is_none() {
+                    *file = Some(BufReader::new(open_file(base, stream_id, hash.as_deref())?));
+                    *position = 0;
+                }
+
+                (file.as_mut().expect("file is open"), position)
+            }
+            Source::Blobs => unreachable!("read_file on stream stored as blobs"),
+        };
+
+        if *position != offset {
+            *position = file.seek(SeekFrom::Start(offset))?;
+        }
+
+        let mut read_bytes = 0;
+
+        while out.len() > read_bytes {
+            let len = file.read(&mut out[read_bytes..])?;
+
+            if len == 0 {
+                break;
+            }
+
+            read_bytes += len;
+            *position += len as u64;
+        }
+
+        Ok(read_bytes)
+    }
+}
+
+fn source(storage: String, hash: Option<String>) -> Source {
+    match Storage::from_column(&storage) {
+        Storage::Blobs => Source::Blobs,
+        Storage::File => Source::File { hash, file: None, position: 0 },
+    }
+}
+
+fn open_file(base: &ProjectBase, stream_id: StreamId, hash: Option<&str>) -> Result<File, io::Error> {
+    if let Some(hash) = hash {
+        match File::open(base.objects.object_path(hash)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // sealed but not yet moved out of scratch, see seal
+            }
+            result => return result,
+        }
+    }
+
+    File::open(base.objects.scratch_path(stream_id))
+}
+
+impl ffmpeg::IoReader for ReadStream {
+    type Error = StreamError;
+    const BUFFER_SIZE: usize = STREAM_BLOB_SIZE;
+
+    fn read(&mut self, out: &mut [u8]) -> Result<usize, Self::Error> {
+        // scratch files may hold bytes past the committed size:
+        let remaining = usize::try_from(cmp::max(self.size - self.offset, 0))
+            .unwrap_or(usize::MAX);
+
+        let out_len = cmp::min(out.len(), remaining);
+        let out = &mut out[..out_len];
+
+        if out.is_empty() {
+            return Ok(0);
+        }
+
+        let read_bytes = match self.source {
+            Source::Blobs => {
+                let read_bytes = self.read_blobs(out)?;
+
+                // the stream may have been migrated into the object store
+                // since it was opened, taking its blobs with it:
+                if read_bytes == 0 && self.reopen()? {
+                    self.read_file(out)?
+                } else {
+                    read_bytes
+                }
+            }
+            Source::File { .. } => self.read_file(out)?,
+        };
+
         self.offset += read_bytes as i64;
         Ok(read_bytes)
     }
@@ -188,3 +785,12 @@ impl ffmpeg::IoReader for ReadStream {
         Ok(self.size as u64)
     }
 }
+
+impl Read for ReadStream {
+    fn read(&mut self, out: &mut [u8]) -> Result<usize, io::Error> {
+        IoReader::read(self, out).map_err(|e| match e {
+            StreamError::Io(e) => e,
+            StreamError::Database(e) => io::Error::new(io::ErrorKind::Other, e),
+        })
+    }
+}
@@ -0,0 +1,130 @@
+// Content-addressed store for stream content, in a directory alongside the
+// project database. Streams are written to a scratch file named for the
+// stream, and once complete are moved to a file named for the SHA-256 of its
+// content. Streams with identical content share one object.
+//
+//   <project>.mixlab-objects/scratch/<stream id>
+//   <project>.mixlab-objects/objects/<first two hex digits>/<sha256>
+//
+// Nothing here touches the database. Callers must hold the database lock
+// while moving files between scratch and objects, and while sweeping, so that
+// the files on disk always agree with the streams table.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::StreamId;
+
+#[derive(Debug)]
+pub struct ObjectStore {
+    dir: PathBuf,
+}
+
+impl ObjectStore {
+    pub fn new(dir: PathBuf) -> Self {
+        ObjectStore { dir }
+    }
+
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("objects").join(&hash[..2]).join(hash)
+    }
+
+    pub fn scratch_path(&self, stream_id: StreamId) -> PathBuf {
+        self.dir.join("scratch").join(stream_id.0.to_string())
+    }
+
+    /// Opens a stream's scratch file for writing at `offset`, discarding
+    /// anything after it which was written but never committed
+    pub fn open_scratch(&self, stream_id: StreamId, offset: u64) -> Result<File, io::Error> {
+        let path = self.scratch_path(stream_id);
+        create_parent(&path)?;
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&path)?;
+
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(file)
+    }
+
+    /// Moves a complete scratch file into the object store under its hash.
+    /// If the object already exists the scratch file is simply removed
+    pub fn commit_scratch(&self, stream_id: StreamId, hash: &str) -> Result<(), io::Error> {
+        let scratch = self.scratch_path(stream_id);
+        let object = self.object_path(hash);
+
+        if object.exists() {
+            fs::remove_file(&scratch)
+        } else {
+            create_parent(&object)?;
+            fs::rename(&scratch, &object)
+        }
+    }
+
+    pub fn remove_scratch(&self, stream_id: StreamId) -> Result<(), io::Error> {
+        match fs::remove_file(self.scratch_path(stream_id)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Removes objects not in `objects` and scratch files for which `keep`
+    /// returns false, returning the number of files removed
+    pub fn sweep(&self, objects: &HashSet<String>, mut keep_scratch: impl FnMut(StreamId) -> bool) -> Result<usize, io::Error> {
+        let mut removed = 0;
+
+        for prefix in read_dir(&self.dir.join("objects"))? {
+            for path in read_dir(&prefix)? {
+                let live = path.file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| objects.contains(name))
+                    .unwrap_or(false);
+
+                if !live {
+                    fs::remove_file(&path)?;
+                    removed += 1;
+                }
+            }
+        }
+
+        for path in read_dir(&self.dir.join("scratch"))? {
+            let stream_id = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse().ok())
+                .map(StreamId);
+
+            if !stream_id.map(&mut keep_scratch).unwrap_or(false) {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn create_parent(path: &Path) -> Result<(), io::Error> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+// entries of a directory which may not exist yet:
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.map(|entry| entry.map(|entry| entry.path())).collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,203 @@
+// Copying modules from another project into the open one, for reusing chains
+// built in one show in others. The other project is opened read only next to
+// the open one. Modules selected from it are inserted under new ids, with the
+// connections between them kept, and media they play is copied over unless
+// the open project already has the same content.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use derive_more::From;
+use mixlab_codec::ffmpeg::IoReader;
+use mixlab_protocol::{MediaId, ModuleId, ModuleParams};
+use rusqlite::{params, OptionalExtension};
+use tokio::task;
+
+use crate::engine::EngineError;
+use crate::persist;
+use crate::project::{OpenError, ProjectBase, ProjectBaseRef};
+use crate::project::media::{self, preview, UploadError, UploadInfo};
+use crate::project::stream::{self, ReadStream, StreamError, StreamId};
+
+// bytes copied between projects at a time
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(From, Debug)]
+pub enum SubgraphError {
+    Database(rusqlite::Error),
+    Open(OpenError),
+    Stream(StreamError),
+    Media(UploadError),
+    Engine(EngineError),
+    // the other project was last opened by an older version:
+    OutOfDate,
+}
+
+pub struct SourceProject {
+    base: ProjectBaseRef,
+    workspace: persist::Workspace,
+}
+
+/// A module in another project, as listed for choosing what to import
+pub struct SourceModule {
+    pub id: ModuleId,
+    pub params: ModuleParams,
+    // modules whose outputs feed this one's inputs:
+    pub sources: Vec<ModuleId>,
+}
+
+impl SourceProject {
+    pub async fn open(path: PathBuf) -> Result<Self, SubgraphError> {
+        let base = ProjectBase::attach_read_only(path).await?
+            .ok_or(SubgraphError::OutOfDate)?;
+
+        let workspace = base.read_workspace().await?;
+
+        Ok(SourceProject {
+            base: Arc::new(base),
+            workspace,
+        })
+    }
+
+    pub fn modules(&self) -> Vec<SourceModule> {
+        let mut modules = self.workspace.modules.iter()
+            .map(|(id, module)| {
+                let mut sources = module.inputs.iter()
+                    .filter_map(|output| output.map(|output| output.module_id()))
+                    .collect::<Vec<_>>();
+
+                sources.sort();
+                sources.dedup();
+
+                SourceModule {
+                    id: *id,
+                    params: module.params.clone(),
+                    sources,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        modules.sort_by_key(|module| module.id);
+        modules
+    }
+}
+
+/// Prepares the given modules of another project for inserting into this
+/// one, copying any media they play. Modules which do not exist are skipped
+pub async fn extract(base: &ProjectBaseRef, source: &SourceProject, module_ids: &[ModuleId]) -> Result<Vec<(ModuleId, persist::Module)>, SubgraphError> {
+    let mut modules = module_ids.iter()
+        .filter_map(|id| source.workspace.modules.get(id).map(|module| (*id, module.clone())))
+        .collect::<Vec<_>>();
+
+    let mut media_ids = HashMap::new();
+
+    for (_, module) in &mut modules {
+        if let ModuleParams::MediaSource(params) = &mut module.params {
+            if let Some(media_id) = params.media_id {
+                let copied = match media_ids.get(&media_id) {
+                    Some(copied) => *copied,
+                    None => {
+                        let copied = copy_media(base, &source.base, media_id).await?;
+                        media_ids.insert(media_id, copied);
+                        copied
+                    }
+                };
+
+                params.media_id = copied;
+            }
+        }
+    }
+
+    Ok(modules)
+}
+
+/// Returns the id of the copy, which is existing media if this project has
+/// the same content already, or None if the media no longer exists
+async fn copy_media(base: &ProjectBaseRef, source: &ProjectBaseRef, media_id: MediaId) -> Result<Option<MediaId>, SubgraphError> {
+    let item = source.with_database(move |conn| {
+        conn.query_row(r"
+                SELECT media.name, media.kind, media.stream_id, streams.hash
+                FROM media
+                INNER JOIN streams ON streams.id = media.stream_id
+                WHERE media.id = ?
+            ",
+            params![media_id.0],
+            |row| Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                StreamId(row.get(2)?),
+                row.get::<_, Option<String>>(3)?,
+            ))
+        ).optional()
+    }).await?;
+
+    let (name, kind, source_stream_id, hash) = match item {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+
+    if let Some(hash) = hash {
+        let existing = base.with_database(move |conn| {
+            conn.query_row(r"
+                    SELECT media.id FROM media
+                    INNER JOIN streams ON streams.id = media.stream_id
+                    WHERE streams.hash = ?
+                    ORDER BY media.id
+                    LIMIT 1
+                ",
+                params![hash],
+                |row| Ok(MediaId(row.get(0)?))
+            ).optional()
+        }).await?;
+
+        if let Some(existing) = existing {
+            return Ok(Some(existing));
+        }
+    }
+
+    let mut reader = match ReadStream::open(source.clone(), source_stream_id).await? {
+        Some(reader) => reader,
+        None => return Ok(None),
+    };
+
+    let mut writer = stream::create(base.clone()).await?;
+
+    loop {
+        // ReadStream reads synchronously:
+        let (returned, chunk) = task::spawn_blocking(move || {
+            let mut chunk = vec![0u8; COPY_CHUNK_SIZE];
+            let result = IoReader::read(&mut reader, &mut chunk).map(|len| {
+                chunk.truncate(len);
+                chunk
+            });
+            (reader, result)
+        }).await.expect("join blocking task");
+
+        reader = returned;
+        let chunk = chunk?;
+
+        if chunk.is_empty() {
+            break;
+        }
+
+        writer.write(&chunk).await?;
+    }
+
+    let stream_id = writer.finalize().await?;
+    let created = media::insert_media(base, UploadInfo { name, kind }, stream_id).await?;
+
+    // the copy is deleted on drop if it duplicated an existing stream:
+    if created.stream_id == stream_id {
+        writer.keep();
+    }
+
+    if !created.have_previews {
+        match preview::load(source, media_id).await? {
+            Some(previews) => preview::store(base, created.media_id, previews).await?,
+            None => { tokio::spawn(preview::generate(base.clone(), created.media_id, created.stream_id)); }
+        }
+    }
+
+    Ok(Some(created.media_id))
+}
@@ -39,6 +39,10 @@ pub fn listen(mountpoint: &str) -> Result<SourceRecv, ListenError> {
     MOUNTPOINTS.listen(mountpoint)
 }
 
+pub fn mountpoints() -> Vec<(String, bool)> {
+    MOUNTPOINTS.mountpoints()
+}
+
 pub const TIME_BASE: i32 = 1000;
 
 #[derive(From, Debug)]
@@ -6,49 +6,84 @@ use std::sync::Arc;
 use bytes::Buf;
 use derive_more::From;
 use futures::sink::{Sink, SinkExt};
-use futures::stream::{self, Stream, StreamExt};
+use futures::stream::{self, Stream, StreamExt, SplitSink, SplitStream};
 use percent_encoding::percent_decode;
 use structopt::StructOpt;
 use tokio::sync::broadcast;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use uuid::Uuid;
 use warp::Filter;
+use warp::http::StatusCode;
 use warp::reply::{self, Reply};
 use warp::ws::{self, Ws, WebSocket};
 
-use mixlab_protocol::{ClientMessage, ServerMessage};
+use mixlab_protocol::{ClientMessage, ServerMessage, MediaOp, ModuleId, SceneOp, PresetOp, ProjectOp, Role, PresenceOp, PresenceUpdate, ClientSequence};
+use mixlab_protocol::{ProtocolError, PROTOCOL_VERSION};
 
 use crate::engine::EngineEvent;
 use crate::listen::{self, Disambiguation};
+use crate::listen::tls::{self, TlsOpts};
 use crate::project::{self, ProjectHandle, Notification};
-use crate::{icecast, module, rtmp};
+use crate::project::manager::{ProjectError, ProjectManager, ProjectManagerRef};
+use crate::project::stream::StorageBackend;
+use crate::{icecast, module, osc, rtmp};
+
+mod api;
+mod auth;
+mod bundle;
+mod coalesce;
+mod media;
+mod subgraph;
+
+use auth::{Auth, AuthOpts};
+use coalesce::Coalesce;
 
 #[derive(StructOpt)]
 pub struct RunOpts {
     #[structopt(short, long, default_value = "127.0.0.1:8000")]
     listen: SocketAddr,
     workspace_path: PathBuf,
+    /// Address to receive OSC messages on, eg. 0.0.0.0:9000. OSC is not
+    /// authenticated, anyone who can reach this address has full control
+    #[structopt(long)]
+    osc_listen: Option<SocketAddr>,
+    /// Where to keep uploaded media: "database" inside the project itself, or
+    /// "files" in a directory alongside each project. Passing "files" also
+    /// moves media already kept in the database out into the directory
+    #[structopt(long, default_value = "database")]
+    media_storage: StorageBackend,
+    #[structopt(flatten)]
+    auth: AuthOpts,
+    #[structopt(flatten)]
+    tls: TlsOpts,
 }
 
 struct Server {
-    project: ProjectHandle,
+    projects: ProjectManagerRef,
 }
 
 type ServerRef = Arc<Server>;
 
 impl Server {
-    pub fn new(project: ProjectHandle) -> Self {
+    pub fn new(projects: ProjectManagerRef) -> Self {
         Server {
-            project,
+            projects,
         }
     }
+
+    /// The active project, requests which are not tied to a session go to
+    /// whichever project is active at the time
+    pub fn project(&self) -> ProjectHandle {
+        self.projects.active()
+    }
 }
 
 pub async fn run(opts: RunOpts) {
-    let project = project::open_or_create(opts.workspace_path).await
-        .expect("create_or_open_project");
+    let projects = ProjectManager::start(opts.workspace_path, opts.media_storage).await
+        .expect("ProjectManager::start");
 
-    let server = Arc::new(Server::new(project));
+    let server = Arc::new(Server::new(projects));
+    let auth = Arc::new(Auth::new(opts.auth));
 
     let index = warp::path::end()
         .map(index);
@@ -63,6 +98,7 @@ pub async fn run(opts: RunOpts) {
         .map(wasm);
 
     let static_content = warp::get()
+        .and(auth::any_role(auth.clone()))
         .and(index
             .or(style)
             .or(js)
@@ -70,19 +106,21 @@ pub async fn run(opts: RunOpts) {
 
     let websocket = warp::get()
         .and(warp::path("session"))
+        .and(auth::authenticate(auth.clone()))
         .and(warp::ws())
         .map({
             let server = server.clone();
-            move |ws: Ws| {
+            move |role: Role, ws: Ws| {
                 let server = server.clone();
                 ws.on_upgrade(move |websocket| {
-                    session(websocket, server.clone())
+                    session(websocket, server.clone(), role)
                 })
             }
         });
 
     let monitor_socket = warp::get()
         .and(warp::path!("_monitor" / Uuid))
+        .and(auth::any_role(auth.clone()))
         .and(warp::ws())
         .map(move |socket_id: Uuid, ws: Ws| {
             ws.on_upgrade(move |websocket| async move {
@@ -93,6 +131,7 @@ pub async fn run(opts: RunOpts) {
     let media_upload = warp::post()
         .and(warp::path!("_upload" / String)
             .map(|filename: String| percent_decode(filename.as_bytes()).decode_utf8_lossy().into_owned()))
+        .and(auth::operator(auth.clone()))
         .and(warp::header::<String>("content-type"))
         .and(warp::filters::body::stream())
         .and_then({
@@ -105,13 +144,21 @@ pub async fn run(opts: RunOpts) {
                         kind,
                     };
 
-                    handle_upload(params, stream, server).await
-                        .map(|()| warp::reply::reply())
-                        .map_err(|e| {
+                    match handle_upload(params, stream, server).await {
+                        Ok(()) => Ok(warp::reply::reply().into_response()),
+                        Err(UploadError::Upload(project::media::UploadError::Unreadable(e))) => {
+                            eprintln!("upload rejected, could not read media: {:?}", e);
+                            Ok(reply::with_status(reply::reply(), StatusCode::UNSUPPORTED_MEDIA_TYPE).into_response())
+                        }
+                        Err(UploadError::Upload(project::media::UploadError::InvalidKind)) => {
+                            Ok(reply::with_status(reply::reply(), StatusCode::BAD_REQUEST).into_response())
+                        }
+                        Err(e) => {
                             eprintln!("upload failed: {:?}", e);
                             // TODO - internal server error?
-                            warp::reject::not_found()
-                        })
+                            Err(warp::reject::not_found())
+                        }
+                    }
                 }
             }
         });
@@ -120,14 +167,31 @@ pub async fn run(opts: RunOpts) {
         .or(websocket)
         .or(monitor_socket)
         .or(media_upload)
+        .or(media::routes(server.clone(), auth.clone()))
+        .or(bundle::routes(server.clone(), auth.clone()))
+        .or(subgraph::routes(server.clone(), auth.clone()))
+        .or(api::routes(server.clone(), auth.clone()))
+        .recover(auth::recover)
         .with(warp::log("mixlab-http"));
 
     let warp = warp::serve(routes);
 
-    let mut listener = listen::start(opts.listen).await
+    let tls = tls::acceptor(opts.tls)
+        .expect("tls::acceptor");
+
+    let scheme = if tls.is_some() { "https" } else { "http" };
+
+    let mut listener = listen::start(opts.listen, tls).await
         .expect("listen::start");
 
-    println!("Mixlab is now running at http://{}", listener.local_addr);
+    println!("Mixlab is now running at {}://{}", scheme, listener.local_addr);
+
+    if let Some(osc_listen) = opts.osc_listen {
+        let osc_addr = osc::start(osc_listen, server.projects.clone()).await
+            .expect("osc::start");
+
+        println!("Listening for OSC on udp://{}", osc_addr);
+    }
 
     let (mut incoming_tx, incoming_rx) = mpsc::channel::<Result<_, warp::Error>>(1);
 
@@ -194,121 +258,452 @@ fn wasm() -> impl Reply {
     content("application/wasm", app_wasm)
 }
 
-async fn session(websocket: WebSocket, server: ServerRef) {
-    let (tx, rx) = websocket.split();
+async fn session(websocket: WebSocket, server: ServerRef, role: Role) {
+    let (tx, mut rx) = websocket.split();
     let mut tx = ClientTx(tx);
 
-    let notifications = server.project.notifications();
+    if !handshake(&mut tx, &mut rx).await {
+        return;
+    }
 
-    let (state, engine_ops, engine) = server.project.connect_engine().await
+    tx.send(ServerMessage::Role(role))
+        .await
+        .expect("tx.send Role");
+
+    let mut projects = server.projects.watch();
+
+    let mut project = match projects.recv().await {
+        Some(project) => project,
+        None => return,
+    };
+
+    let mut session = SessionState {
+        role,
+        last_sequence: None,
+        name: None,
+        switched: false,
+    };
+
+    // the session follows the active project, moving over each time it changes:
+    loop {
+        project = match project_session(&server, &project, &mut session, &mut tx, &mut rx, &mut projects).await {
+            Some(next) => next,
+            None => return,
+        };
+
+        session.switched = true;
+    }
+}
+
+// state of a session which carries over from one project to the next
+struct SessionState {
+    role: Role,
+    // last workspace update forwarded to the engine from this session, so
+    // that a resync can tell the client which of its updates are reflected:
+    last_sequence: Option<ClientSequence>,
+    // display name the client chose, presence is per project:
+    name: Option<String>,
+    switched: bool,
+}
+
+type SessionTx = ClientTx<SplitSink<WebSocket, ws::Message>>;
+type SessionRx = SplitStream<WebSocket>;
+
+// runs the session against a single project, returning the next project
+// when the active one changes, or None if the client disconnected
+async fn project_session(
+    server: &ServerRef,
+    project: &ProjectHandle,
+    session: &mut SessionState,
+    tx: &mut SessionTx,
+    rx: &mut SessionRx,
+    projects: &mut watch::Receiver<ProjectHandle>,
+) -> Option<ProjectHandle> {
+    let role = session.role;
+
+    let notifications = project.notifications();
+
+    let (peer, presence) = project.join_presence(role);
+
+    if let Some(name) = &session.name {
+        peer.update(PresenceOp::SetName(name.clone()));
+    }
+
+    let (state, engine_ops, engine) = project.connect_engine().await
         .expect("connect engine");
 
-    let library = server.project.fetch_media_library().await
+    let library = project.fetch_media_library().await
         .expect("fetch_media_library");
 
-    tx.send(ServerMessage::WorkspaceState(state))
+    let scenes = project.fetch_scene_library().await
+        .expect("fetch_scene_library");
+
+    let presets = project.fetch_preset_library().await
+        .expect("fetch_preset_library");
+
+    let projects_library = server.projects.library().await
+        .expect("fetch project library");
+
+    // updates sent to the previous project's engine will never be synced,
+    // so the client must be told which of its updates are reflected:
+    let state_msg = if session.switched {
+        ServerMessage::Resync(state, session.last_sequence)
+    } else {
+        ServerMessage::WorkspaceState(state)
+    };
+
+    tx.send(state_msg)
         .await
-        .expect("tx.send WorkspaceState");
+        .ok()?;
 
     tx.send(ServerMessage::MediaLibrary(library))
         .await
-        .expect("tx.send MediaLibrary");
+        .ok()?;
+
+    tx.send(ServerMessage::SceneLibrary(scenes))
+        .await
+        .ok()?;
+
+    tx.send(ServerMessage::PresetLibrary(presets))
+        .await
+        .ok()?;
+
+    tx.send(ServerMessage::ProjectLibrary(projects_library))
+        .await
+        .ok()?;
+
+    tx.send(ServerMessage::Presence(project.presence_snapshot(peer.id())))
+        .await
+        .ok()?;
 
     enum Event {
         ClientMessage(Result<ws::Message, warp::Error>),
         Engine(Result<EngineEvent, broadcast::RecvError>),
         Notification(Notification),
+        Presence(Result<PresenceUpdate, broadcast::RecvError>),
+        Project(ProjectHandle),
     }
 
-    let mut events = stream::select(
-        rx.map(Event::ClientMessage),
-        stream::select(
-            engine_ops.map(Event::Engine),
-            notifications.map(Event::Notification)));
-
-    while let Some(event) = events.next().await {
-        match event {
-            Event::ClientMessage(Err(e)) => {
-                println!("error reading from client: {:?}", e);
-                return;
-            }
-            Event::ClientMessage(Ok(msg)) => {
-                if !msg.is_binary() {
-                    continue;
+    let mut engine_ops = Coalesce::new(engine_ops);
+    let mut notifications = Box::pin(notifications);
+    let mut presence = presence;
+
+    loop {
+        let mut events = stream::select(
+            rx.by_ref().map(Event::ClientMessage),
+            stream::select(
+                engine_ops.by_ref().map(Event::Engine),
+                stream::select(
+                    notifications.as_mut().map(Event::Notification),
+                    stream::select(
+                        presence.by_ref().map(Event::Presence),
+                        projects.by_ref().map(Event::Project)))));
+
+        // runs until this session lags behind the engine:
+        loop {
+            let event = match events.next().await {
+                Some(event) => event,
+                None => return None,
+            };
+
+            match event {
+                Event::ClientMessage(Err(e)) => {
+                    println!("error reading from client: {:?}", e);
+                    return None;
                 }
+                Event::ClientMessage(Ok(msg)) => {
+                    if !msg.is_binary() {
+                        continue;
+                    }
 
-                let msg = bincode::deserialize::<ClientMessage>(msg.as_bytes())
-                    .expect("bincode::deserialize");
+                    let msg = match bincode::deserialize::<ClientMessage>(msg.as_bytes()) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            println!("invalid message from client: {:?}", e);
 
-                match msg {
-                    ClientMessage::Workspace(msg) => {
-                        if let Err(e) = engine.update(msg) {
-                            println!("Engine update failed: {:?}", e);
+                            match tx.send(ServerMessage::Error(ProtocolError::InvalidMessage)).await {
+                                Ok(()) => continue,
+                                Err(_) => return None,
+                            }
+                        }
+                    };
+
+                    match msg {
+                        ClientMessage::Hello(_) => {
+                            // already handshaken, ignore
+                        }
+                        ClientMessage::Presence(op) => {
+                            if let PresenceOp::SetName(name) = &op {
+                                session.name = Some(name.clone());
+                            }
+
+                            // presence is not part of the workspace, so viewers
+                            // may show where they are too
+                            peer.update(op);
+                        }
+                        _ if role == Role::Viewer => {
+                            println!("rejecting message from viewer: {:?}", msg);
+                        }
+                        ClientMessage::Workspace(msg) => {
+                            // updates refused by a busy engine are never
+                            // applied, count them anyway so that the client
+                            // does not wait on them forever after a resync:
+                            session.last_sequence = Some(msg.sequence);
+
+                            if let Err(e) = engine.update(msg) {
+                                println!("Engine update failed: {:?}", e);
+                            }
+                        }
+                        ClientMessage::Scene(op) => {
+                            tokio::spawn(scene_op(project.clone(), op));
+                        }
+                        ClientMessage::Preset(op) => {
+                            tokio::spawn(preset_op(project.clone(), op));
+                        }
+                        ClientMessage::Project(op) => {
+                            tokio::spawn(project_op(server.clone(), op));
+                        }
+                        ClientMessage::Media(op) => {
+                            tokio::spawn(media_op(project.clone(), op));
                         }
                     }
                 }
-            }
-            Event::Engine(Err(broadcast::RecvError::Lagged(skipped))) => {
-                println!("disconnecting client: lagged {} messages behind", skipped);
-                return;
-            }
-            Event::Engine(Err(broadcast::RecvError::Closed)) => {
-                // TODO we should tell the user that the engine has stopped
-                unimplemented!()
-            }
-            Event::Engine(Ok(event)) => {
-                // sequence is only applicable if it belongs to this session:
-                let msg = match event {
-                    EngineEvent::ServerUpdate(update) => Some(ServerMessage::Update(update)),
-                    EngineEvent::Sync(clock) => {
-                        if clock.0 == engine.session_id() {
-                            Some(ServerMessage::Sync(clock.1))
-                        } else {
-                            None
+                Event::Engine(Err(broadcast::RecvError::Lagged(skipped))) => {
+                    println!("resyncing client: lagged {} messages behind", skipped);
+                    break;
+                }
+                Event::Engine(Err(broadcast::RecvError::Closed)) => {
+                    // TODO we should tell the user that the engine has stopped
+                    unimplemented!()
+                }
+                Event::Engine(Ok(event)) => {
+                    // sequence is only applicable if it belongs to this session:
+                    let msg = match event {
+                        EngineEvent::ServerUpdate(update) => Some(ServerMessage::Update(update)),
+                        EngineEvent::Sync(clock) => {
+                            if clock.0 == engine.session_id() {
+                                Some(ServerMessage::Sync(clock.1))
+                            } else {
+                                None
+                            }
+                        }
+                    };
+
+                    if let Some(msg) = msg {
+                        match tx.send(msg).await {
+                            Ok(()) => {}
+                            Err(_) => {
+                                // client disconnected
+                                return None;
+                            }
                         }
                     }
-                };
+                }
+                Event::Presence(update) => {
+                    let update = match update {
+                        Ok(update) => update,
+                        Err(broadcast::RecvError::Lagged(_)) => {
+                            // presence is not important enough to disconnect a
+                            // client over, just catch them up
+                            project.presence_snapshot(peer.id())
+                        }
+                        Err(broadcast::RecvError::Closed) => {
+                            continue;
+                        }
+                    };
 
-                if let Some(msg) = msg {
-                    match tx.send(msg).await {
+                    match tx.send(ServerMessage::Presence(update)).await {
                         Ok(()) => {}
                         Err(_) => {
                             // client disconnected
-                            return;
+                            return None;
                         }
                     }
                 }
-            }
-            Event::Notification(notif) => {
-                let msg = match &notif {
-                    Notification::PerformanceInfo(perf_info) => {
-                        Some(ServerMessage::Performance(Cow::Borrowed(perf_info)))
-                    }
-                    Notification::MediaLibrary => {
-                        match server.project.fetch_media_library().await {
-                            Ok(library) => Some(ServerMessage::MediaLibrary(library)),
-                            Err(e) => {
-                                eprintln!("failed to query media library: {:?}", e);
-                                None
+                Event::Project(next) => {
+                    return Some(next);
+                }
+                Event::Notification(notif) => {
+                    let msg = match &notif {
+                        Notification::PerformanceInfo(perf_info) => {
+                            Some(ServerMessage::Performance(Cow::Borrowed(perf_info)))
+                        }
+                        Notification::MediaLibrary => {
+                            match project.fetch_media_library().await {
+                                Ok(library) => Some(ServerMessage::MediaLibrary(library)),
+                                Err(e) => {
+                                    eprintln!("failed to query media library: {:?}", e);
+                                    None
+                                }
                             }
                         }
-                    }
-                };
+                        Notification::SceneLibrary => {
+                            match project.fetch_scene_library().await {
+                                Ok(scenes) => Some(ServerMessage::SceneLibrary(scenes)),
+                                Err(e) => {
+                                    eprintln!("failed to query scene library: {:?}", e);
+                                    None
+                                }
+                            }
+                        }
+                        Notification::PresetLibrary => {
+                            match project.fetch_preset_library().await {
+                                Ok(presets) => Some(ServerMessage::PresetLibrary(presets)),
+                                Err(e) => {
+                                    eprintln!("failed to query preset library: {:?}", e);
+                                    None
+                                }
+                            }
+                        }
+                    };
 
-                if let Some(msg) = msg {
-                    match tx.send(msg).await {
-                        Ok(()) => {}
-                        Err(_) => {
-                            // client disconnected
-                            return;
+                    if let Some(msg) = msg {
+                        match tx.send(msg).await {
+                            Ok(()) => {}
+                            Err(_) => {
+                                // client disconnected
+                                return None;
+                            }
                         }
                     }
                 }
             }
         }
+
+        let (state, ops) = match engine.resync().await {
+            Ok(resync) => resync,
+            Err(e) => {
+                println!("disconnecting client: could not resync: {:?}", e);
+                return None;
+            }
+        };
+
+        engine_ops = Coalesce::new(ops);
+
+        match tx.send(ServerMessage::Resync(state, session.last_sequence)).await {
+            Ok(()) => {}
+            Err(_) => {
+                // client disconnected
+                return None;
+            }
+        }
+    }
+}
+
+// Exchanges protocol versions with the client, returning true if the session
+// may proceed. The client must send its hello before anything else.
+async fn handshake<S, R>(tx: &mut ClientTx<S>, rx: &mut R) -> bool
+    where S: Sink<ws::Message, Error = warp::Error> + Unpin,
+          R: Stream<Item = Result<ws::Message, warp::Error>> + Unpin,
+{
+    if tx.send(ServerMessage::Hello(PROTOCOL_VERSION)).await.is_err() {
+        return false;
+    }
+
+    let msg = loop {
+        match rx.next().await {
+            Some(Ok(msg)) if msg.is_binary() => break msg,
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => {
+                println!("error reading from client: {:?}", e);
+                return false;
+            }
+            None => return false,
+        }
+    };
+
+    match bincode::deserialize::<ClientMessage>(msg.as_bytes()) {
+        Ok(ClientMessage::Hello(version)) if version == PROTOCOL_VERSION => true,
+        Ok(ClientMessage::Hello(version)) => {
+            println!("rejecting client with protocol version {:?}, expected {:?}",
+                version, PROTOCOL_VERSION);
+            let _ = tx.send(ServerMessage::Error(ProtocolError::VersionMismatch)).await;
+            false
+        }
+        _ => {
+            // anything other than a hello means a client that predates or
+            // does not match this protocol version:
+            println!("rejecting client: expected protocol hello");
+            let _ = tx.send(ServerMessage::Error(ProtocolError::VersionMismatch)).await;
+            false
+        }
     }
 }
 
+async fn scene_op(project: ProjectHandle, op: SceneOp) {
+    let result = match op {
+        SceneOp::Save { name, modules } => {
+            project.save_scene(name, modules).await.map(|_| ())
+        }
+        SceneOp::Delete(scene_id) => {
+            project.delete_scene(scene_id).await
+        }
+        SceneOp::Recall(scene_id, recall) => {
+            project.recall_scene(scene_id, recall).await
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("scene operation failed: {:?}", e);
+    }
+}
+
+async fn preset_op(project: ProjectHandle, op: PresetOp) {
+    let result = match op {
+        PresetOp::Save { module, name } => {
+            project.save_preset(module, name).await.map(|_| ())
+        }
+        PresetOp::Delete(preset_id) => {
+            project.delete_preset(preset_id).await
+        }
+        PresetOp::Apply(module_id, preset) => {
+            project.apply_preset(module_id, preset).await
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("preset operation failed: {:?}", e);
+    }
+}
+
+async fn media_op(project: ProjectHandle, op: MediaOp) {
+    let result = match op {
+        MediaOp::Rename(media_id, name) => {
+            project.rename_media(media_id, name).await
+        }
+        MediaOp::Delete(media_id) => {
+            project.delete_media(media_id).await
+        }
+        MediaOp::CollectGarbage => {
+            project.collect_garbage().await
+                .map(|streams| println!("garbage collection deleted {} streams", streams))
+                .map_err(project::media::MediaError::Database)
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("media operation failed: {:?}", e);
+    }
+}
+
+async fn project_op(server: ServerRef, op: ProjectOp) {
+    let result = match op {
+        ProjectOp::Open(name) => server.projects.open(name).await,
+        ProjectOp::Create(name) => server.projects.create(name).await,
+        ProjectOp::ImportModules(name, modules) => import_modules(&server, &name, modules).await,
+    };
+
+    if let Err(e) = result {
+        eprintln!("project operation failed: {:?}", e);
+    }
+}
+
+async fn import_modules(server: &ServerRef, name: &str, modules: Vec<ModuleId>) -> Result<(), ProjectError> {
+    let source = server.projects.open_source(name).await?;
+    server.project().import_modules(&source, modules).await?;
+    Ok(())
+}
+
 #[derive(From, Debug)]
 enum UploadError {
     Warp(warp::Error),
@@ -327,7 +722,7 @@ async fn handle_upload(
 ) -> Result<(), UploadError> {
     futures::pin_mut!(stream);
 
-    let mut upload = server.project.begin_media_upload(project::media::UploadInfo {
+    let mut upload = server.project().begin_media_upload(project::media::UploadInfo {
         name: params.filename,
         kind: params.kind,
     }).await?;
@@ -0,0 +1,408 @@
+// JSON REST API for scripting the workspace from outside the browser. Every
+// change is made as a WorkspaceOp through its own EngineSession, so connected
+// UIs see API changes exactly as they would see another user's.
+
+use std::future::Future;
+use std::mem;
+use std::num::NonZeroUsize;
+
+use derive_more::From;
+use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+use warp::http::StatusCode;
+use warp::reply::{self, Reply};
+use warp::{Filter, Rejection};
+
+use mixlab_protocol::{ModuleId, ModuleParams, InputId, OutputId, Indication, Terminal, WindowGeometry, WorkspaceState, WorkspaceOp, WorkspaceMessage, ServerUpdate, ClientSequence, Role};
+
+use crate::engine::{EngineError, EngineEvent};
+use crate::util::Sequence;
+
+use super::ServerRef;
+use super::auth::{self, AuthRef};
+
+mod events;
+mod status;
+
+#[derive(From, Debug)]
+enum ApiError {
+    Engine(EngineError),
+    NoSuchModule,
+    ParamsMismatch,
+    ConnectionRejected,
+    Lagged,
+    InvalidFilter,
+}
+
+impl Reply for ApiError {
+    fn into_response(self) -> reply::Response {
+        let (status, message) = match self {
+            ApiError::Engine(EngineError::Busy) => (StatusCode::SERVICE_UNAVAILABLE, "engine busy"),
+            ApiError::Engine(EngineError::Stopped) => (StatusCode::INTERNAL_SERVER_ERROR, "engine stopped"),
+            ApiError::NoSuchModule => (StatusCode::NOT_FOUND, "no such module"),
+            ApiError::ParamsMismatch => (StatusCode::BAD_REQUEST, "params do not match module type"),
+            ApiError::ConnectionRejected => (StatusCode::BAD_REQUEST, "connection rejected"),
+            ApiError::Lagged => (StatusCode::SERVICE_UNAVAILABLE, "lagged behind engine"),
+            ApiError::InvalidFilter => (StatusCode::BAD_REQUEST, "invalid event filter"),
+        };
+
+        let body = reply::json(&ErrorBody { error: message });
+        reply::with_status(body, status).into_response()
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+}
+
+#[derive(Serialize)]
+struct Module {
+    id: ModuleId,
+    params: ModuleParams,
+    geometry: Option<WindowGeometry>,
+    bypass: bool,
+    indication: Option<Indication>,
+    inputs: Vec<Terminal>,
+    outputs: Vec<Terminal>,
+}
+
+#[derive(Serialize)]
+struct Connection {
+    input: InputId,
+    output: OutputId,
+}
+
+#[derive(Deserialize)]
+struct CreateModule {
+    params: ModuleParams,
+    #[serde(default)]
+    geometry: WindowGeometry,
+}
+
+#[derive(Deserialize)]
+struct CreateConnection {
+    input: InputId,
+    output: OutputId,
+}
+
+pub fn routes(server: ServerRef, auth: AuthRef) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let server = warp::any().map(move || server.clone());
+
+    let list_modules = warp::get()
+        .and(warp::path!("api" / "modules"))
+        .and(auth::any_role(auth.clone()))
+        .and(server.clone())
+        .and_then(|server| respond(list_modules(server)));
+
+    let create_module = warp::post()
+        .and(warp::path!("api" / "modules"))
+        .and(auth::operator(auth.clone()))
+        .and(server.clone())
+        .and(warp::body::json())
+        .and_then(|server, body| respond(create_module(server, body)));
+
+    let get_module = warp::get()
+        .and(warp::path!("api" / "modules" / usize))
+        .and(auth::any_role(auth.clone()))
+        .and(server.clone())
+        .and_then(|id, server| respond(get_module(server, id)));
+
+    let delete_module = warp::delete()
+        .and(warp::path!("api" / "modules" / usize))
+        .and(auth::operator(auth.clone()))
+        .and(server.clone())
+        .and_then(|id, server| respond(delete_module(server, id)));
+
+    let get_params = warp::get()
+        .and(warp::path!("api" / "modules" / usize / "params"))
+        .and(auth::any_role(auth.clone()))
+        .and(server.clone())
+        .and_then(|id, server| respond(get_params(server, id)));
+
+    let set_params = warp::put()
+        .and(warp::path!("api" / "modules" / usize / "params"))
+        .and(auth::operator(auth.clone()))
+        .and(server.clone())
+        .and(warp::body::json())
+        .and_then(|id, server, params| respond(set_params(server, id, params)));
+
+    let get_indication = warp::get()
+        .and(warp::path!("api" / "modules" / usize / "indication"))
+        .and(auth::any_role(auth.clone()))
+        .and(server.clone())
+        .and_then(|id, server| respond(get_indication(server, id)));
+
+    let list_connections = warp::get()
+        .and(warp::path!("api" / "connections"))
+        .and(auth::any_role(auth.clone()))
+        .and(server.clone())
+        .and_then(|server| respond(list_connections(server)));
+
+    let create_connection = warp::post()
+        .and(warp::path!("api" / "connections"))
+        .and(auth::operator(auth.clone()))
+        .and(server.clone())
+        .and(warp::body::json())
+        .and_then(|server, body| respond(create_connection(server, body)));
+
+    let delete_connection = warp::delete()
+        .and(warp::path!("api" / "connections" / usize / usize))
+        .and(auth::operator(auth.clone()))
+        .and(server.clone())
+        .and_then(|id, input, server| respond(delete_connection(server, id, input)));
+
+    let events = warp::get()
+        .and(warp::path!("api" / "events"))
+        .and(auth::any_role(auth.clone()))
+        .and(server.clone())
+        .and(warp::query())
+        .and_then(|server, query| respond(events::feed(server, query)));
+
+    // probes need no credentials, only the details do, see status.rs:
+    let status = warp::get()
+        .and(warp::path!("status"))
+        .and(auth::optional_role(auth.clone()))
+        .and(server.clone())
+        .and(warp::query())
+        .and_then(|role: Option<Role>, server, query| respond(status::status(server, role.is_some(), query)));
+
+    list_modules
+        .or(create_module)
+        .or(get_module)
+        .or(delete_module)
+        .or(get_params)
+        .or(set_params)
+        .or(get_indication)
+        .or(list_connections)
+        .or(create_connection)
+        .or(delete_connection)
+        .or(events)
+        .or(status)
+}
+
+async fn respond<T: Reply>(f: impl Future<Output = Result<T, ApiError>>) -> Result<reply::Response, Rejection> {
+    Ok(match f.await {
+        Ok(reply) => reply.into_response(),
+        Err(e) => e.into_response(),
+    })
+}
+
+async fn list_modules(server: ServerRef) -> Result<impl Reply, ApiError> {
+    let state = workspace_state(&server).await?;
+
+    let modules = state.modules.iter()
+        .filter_map(|(id, _)| module_from_state(&state, *id))
+        .collect::<Vec<_>>();
+
+    Ok(reply::json(&modules))
+}
+
+async fn create_module(server: ServerRef, body: CreateModule) -> Result<impl Reply, ApiError> {
+    let updates = perform(&server, WorkspaceOp::CreateModule(body.params, body.geometry)).await?;
+
+    let module = updates.into_iter()
+        .filter_map(|update| match update {
+            ServerUpdate::CreateModule { id, params, geometry, indication, inputs, outputs } => {
+                Some(Module {
+                    id,
+                    params,
+                    geometry: Some(geometry),
+                    bypass: false,
+                    indication: Some(indication),
+                    inputs,
+                    outputs,
+                })
+            }
+            _ => None,
+        })
+        .next()
+        .ok_or(EngineError::Stopped)?;
+
+    Ok(reply::with_status(reply::json(&module), StatusCode::CREATED))
+}
+
+async fn get_module(server: ServerRef, id: usize) -> Result<impl Reply, ApiError> {
+    let module_id = module_id(id)?;
+    let state = workspace_state(&server).await?;
+
+    let module = module_from_state(&state, module_id)
+        .ok_or(ApiError::NoSuchModule)?;
+
+    Ok(reply::json(&module))
+}
+
+async fn delete_module(server: ServerRef, id: usize) -> Result<impl Reply, ApiError> {
+    let module_id = module_id(id)?;
+    let updates = perform(&server, WorkspaceOp::DeleteModule(module_id)).await?;
+
+    let deleted = updates.iter().any(|update| match update {
+        ServerUpdate::DeleteModule(id) => *id == module_id,
+        _ => false,
+    });
+
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NoSuchModule)
+    }
+}
+
+async fn get_params(server: ServerRef, id: usize) -> Result<impl Reply, ApiError> {
+    let module_id = module_id(id)?;
+    let state = workspace_state(&server).await?;
+
+    let params = module_params(&state, module_id)
+        .ok_or(ApiError::NoSuchModule)?;
+
+    Ok(reply::json(params))
+}
+
+async fn set_params(server: ServerRef, id: usize, params: ModuleParams) -> Result<impl Reply, ApiError> {
+    let module_id = module_id(id)?;
+
+    {
+        // the engine requires params to be of the same type as the module
+        // they are applied to, so we must check that before sending them:
+        let state = workspace_state(&server).await?;
+
+        let current = module_params(&state, module_id)
+            .ok_or(ApiError::NoSuchModule)?;
+
+        if mem::discriminant(current) != mem::discriminant(&params) {
+            return Err(ApiError::ParamsMismatch);
+        }
+    }
+
+    let updates = perform(&server, WorkspaceOp::UpdateModuleParams(module_id, params)).await?;
+
+    let params = updates.into_iter()
+        .filter_map(|update| match update {
+            ServerUpdate::UpdateModuleParams(id, params) if id == module_id => Some(params),
+            _ => None,
+        })
+        .last()
+        .ok_or(ApiError::NoSuchModule)?;
+
+    Ok(reply::json(&params))
+}
+
+async fn get_indication(server: ServerRef, id: usize) -> Result<impl Reply, ApiError> {
+    let module_id = module_id(id)?;
+    let state = workspace_state(&server).await?;
+
+    let indication = state.indications.iter()
+        .find(|(id, _)| *id == module_id)
+        .map(|(_, indication)| indication)
+        .ok_or(ApiError::NoSuchModule)?;
+
+    Ok(reply::json(indication))
+}
+
+async fn list_connections(server: ServerRef) -> Result<impl Reply, ApiError> {
+    let state = workspace_state(&server).await?;
+
+    let connections = state.connections.into_iter()
+        .map(|(input, output)| Connection { input, output })
+        .collect::<Vec<_>>();
+
+    Ok(reply::json(&connections))
+}
+
+async fn create_connection(server: ServerRef, body: CreateConnection) -> Result<impl Reply, ApiError> {
+    let updates = perform(&server, WorkspaceOp::CreateConnection(body.input, body.output)).await?;
+
+    let connected = updates.iter().any(|update| match update {
+        ServerUpdate::CreateConnection(input, output) => *input == body.input && *output == body.output,
+        _ => false,
+    });
+
+    if connected {
+        let connection = Connection { input: body.input, output: body.output };
+        Ok(reply::with_status(reply::json(&connection), StatusCode::CREATED))
+    } else {
+        Err(ApiError::ConnectionRejected)
+    }
+}
+
+async fn delete_connection(server: ServerRef, id: usize, input: usize) -> Result<impl Reply, ApiError> {
+    let input_id = InputId(module_id(id)?, input);
+
+    // deleting a connection that does not exist is not an error:
+    perform(&server, WorkspaceOp::DeleteConnection(input_id)).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn module_id(id: usize) -> Result<ModuleId, ApiError> {
+    NonZeroUsize::new(id)
+        .map(ModuleId)
+        .ok_or(ApiError::NoSuchModule)
+}
+
+fn module_params(state: &WorkspaceState, module_id: ModuleId) -> Option<&ModuleParams> {
+    state.modules.iter()
+        .find(|(id, _)| *id == module_id)
+        .map(|(_, params)| params)
+}
+
+fn module_from_state(state: &WorkspaceState, module_id: ModuleId) -> Option<Module> {
+    fn find<T: Clone>(items: &[(ModuleId, T)], module_id: ModuleId) -> Option<T> {
+        items.iter()
+            .find(|(id, _)| *id == module_id)
+            .map(|(_, item)| item.clone())
+    }
+
+    Some(Module {
+        id: module_id,
+        params: find(&state.modules, module_id)?,
+        geometry: find(&state.geometry, module_id),
+        bypass: state.bypass.contains(&module_id),
+        indication: find(&state.indications, module_id),
+        inputs: find(&state.inputs, module_id).unwrap_or_default(),
+        outputs: find(&state.outputs, module_id).unwrap_or_default(),
+    })
+}
+
+async fn workspace_state(server: &ServerRef) -> Result<WorkspaceState, ApiError> {
+    let (state, _, _) = server.project().connect_engine().await?;
+    Ok(state)
+}
+
+// performs a single op in its own engine session and returns the updates the
+// engine made in response to it
+async fn perform(server: &ServerRef, op: WorkspaceOp) -> Result<Vec<ServerUpdate>, ApiError> {
+    let (_, mut events, engine) = server.project().connect_engine().await?;
+
+    engine.update(WorkspaceMessage {
+        sequence: ClientSequence(Sequence::new().next()),
+        op,
+    })?;
+
+    // the engine logs all updates for an op followed by a sync for that op,
+    // so updates since the most recent sync belong to the op being synced.
+    // indications from ticks may be interleaved, but these are not relevant
+    // to any of the ops we perform
+    let mut updates = Vec::new();
+
+    loop {
+        match events.recv().await {
+            Ok(EngineEvent::ServerUpdate(update)) => {
+                updates.push(update);
+            }
+            Ok(EngineEvent::Sync(clock)) => {
+                if clock.0 == engine.session_id() {
+                    return Ok(updates);
+                }
+
+                updates.clear();
+            }
+            Err(broadcast::RecvError::Lagged(_)) => {
+                return Err(ApiError::Lagged);
+            }
+            Err(broadcast::RecvError::Closed) => {
+                return Err(EngineError::Stopped.into());
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+// Server-sent event feed of engine activity as JSON, for dashboards and
+// alerting which want to watch the workspace without a browser session. The
+// feed ends when the active project changes, clients should reconnect to
+// follow the new project.
+
+use std::collections::HashSet;
+use std::convert::Infallible;
+
+use futures::future;
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use warp::reply::Reply;
+use warp::sse;
+
+use mixlab_protocol::{MidiTarget, ModuleId, ServerUpdate};
+
+use crate::engine::{EngineError, EngineEvent};
+use crate::project::Notification;
+use crate::server::coalesce::Coalesce;
+
+use super::{module_id, ApiError, ServerRef};
+
+/// Comma separated lists filtering the feed. Types are any of update,
+/// indication and performance, and default to all of them. If modules are
+/// given, only updates and indications relating to those modules are sent.
+#[derive(Deserialize)]
+pub(super) struct FeedQuery {
+    types: Option<String>,
+    modules: Option<String>,
+}
+
+#[derive(Clone)]
+struct FeedFilter {
+    updates: bool,
+    indications: bool,
+    performance: bool,
+    modules: Option<HashSet<ModuleId>>,
+}
+
+enum FeedEvent {
+    Engine(Result<EngineEvent, broadcast::RecvError>),
+    Notification(Notification),
+    ProjectChanged,
+}
+
+pub(super) async fn feed(server: ServerRef, query: FeedQuery) -> Result<impl Reply, ApiError> {
+    let filter = FeedFilter::parse(query)?;
+
+    let mut projects = server.projects.watch();
+    let project = projects.recv().await.ok_or(EngineError::Stopped)?;
+
+    let (_, events, _) = project.connect_engine().await?;
+
+    // indications are coalesced for feeds which fall behind, like they are
+    // for browser sessions:
+    let events = Coalesce::new(events).map(FeedEvent::Engine);
+    let notifications = project.notifications().map(FeedEvent::Notification);
+    let project_changed = projects.map(|_| FeedEvent::ProjectChanged);
+
+    let feed = stream::select(events, stream::select(notifications, project_changed))
+        .take_while(|event| future::ready(match event {
+            FeedEvent::ProjectChanged => false,
+            _ => true,
+        }))
+        .filter_map(move |event| future::ready(filter.event(event)))
+        .map(|(name, data)| Ok::<_, Infallible>((sse::event(name), sse::json(data))));
+
+    Ok(sse::reply(sse::keep_alive().stream(feed)))
+}
+
+impl FeedFilter {
+    fn parse(query: FeedQuery) -> Result<Self, ApiError> {
+        let mut filter = FeedFilter {
+            updates: true,
+            indications: true,
+            performance: true,
+            modules: None,
+        };
+
+        if let Some(types) = query.types {
+            filter.updates = false;
+            filter.indications = false;
+            filter.performance = false;
+
+            for ty in types.split(',') {
+                match ty.trim() {
+                    "update" => { filter.updates = true; }
+                    "indication" => { filter.indications = true; }
+                    "performance" => { filter.performance = true; }
+                    _ => { return Err(ApiError::InvalidFilter); }
+                }
+            }
+        }
+
+        if let Some(modules) = query.modules {
+            let modules = modules.split(',')
+                .map(|id| id.trim().parse().map_err(|_| ApiError::InvalidFilter).and_then(module_id))
+                .collect::<Result<_, _>>()?;
+
+            filter.modules = Some(modules);
+        }
+
+        Ok(filter)
+    }
+
+    fn event(&self, event: FeedEvent) -> Option<(&'static str, Value)> {
+        match event {
+            FeedEvent::Engine(Ok(EngineEvent::ServerUpdate(ServerUpdate::UpdateModuleIndication(id, indication)))) => {
+                if self.indications && self.includes(&[id]) {
+                    Some(("indication", json!({ "module": id, "indication": indication })))
+                } else {
+                    None
+                }
+            }
+            FeedEvent::Engine(Ok(EngineEvent::ServerUpdate(update))) => {
+                if self.updates && self.includes(&update_modules(&update)) {
+                    Some(("update", json!(update)))
+                } else {
+                    None
+                }
+            }
+            FeedEvent::Engine(Ok(EngineEvent::Sync(_))) => None,
+            FeedEvent::Engine(Err(broadcast::RecvError::Lagged(skipped))) => {
+                // consumers can refetch state from the rest of the API:
+                Some(("lagged", json!({ "skipped": skipped })))
+            }
+            FeedEvent::Engine(Err(broadcast::RecvError::Closed)) => None,
+            FeedEvent::Notification(Notification::PerformanceInfo(info)) => {
+                if self.performance {
+                    Some(("performance", json!(*info)))
+                } else {
+                    None
+                }
+            }
+            FeedEvent::Notification(_) => None,
+            FeedEvent::ProjectChanged => None,
+        }
+    }
+
+    fn includes(&self, modules: &[ModuleId]) -> bool {
+        match &self.modules {
+            Some(filter) => modules.iter().any(|id| filter.contains(id)),
+            None => true,
+        }
+    }
+}
+
+fn update_modules(update: &ServerUpdate) -> Vec<ModuleId> {
+    match update {
+        ServerUpdate::CreateModule { id, .. } |
+        ServerUpdate::UpdateModuleParams(id, _) |
+        ServerUpdate::UpdateWindowGeometry(id, _) |
+        ServerUpdate::UpdateModuleIndication(id, _) |
+        ServerUpdate::SetBypass(id, _) |
+        ServerUpdate::DeleteModule(id) => vec![*id],
+        ServerUpdate::CreateConnection(input, output) => vec![input.0, output.0],
+        ServerUpdate::DeleteConnection(input) => vec![input.0],
+        ServerUpdate::UpdateMacro(..) |
+        ServerUpdate::DeleteMacro(..) => vec![],
+        ServerUpdate::SetMidiMapping(MidiTarget::ModuleParam(id, _), _) => vec![*id],
+        ServerUpdate::SetMidiMapping(..) => vec![],
+    }
+}
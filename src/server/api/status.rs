@@ -0,0 +1,136 @@
+// Health check for process supervisors and monitoring on headless installs.
+// Responds 200 when the engine thread and database are both answering, and
+// 503 otherwise. Ingest and output state is reported but does not affect
+// health, a workspace with nothing streaming is still a working one. Engine
+// lag only does when asked for with ?realtime=true, since a single slow tick
+// is not worth restarting for.
+//
+// Anyone may ask whether the server is healthy, so probes need no
+// credentials. The rest reports on the workspace, and is only included for
+// authenticated users.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time;
+use warp::http::StatusCode;
+use warp::reply::{self, Reply};
+
+use mixlab_protocol::{Indication, ModuleParams, TemporalWarningStatus, WorkspaceState};
+
+use crate::{icecast, rtmp};
+
+use super::{ApiError, ServerRef};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+pub(super) struct StatusQuery {
+    // whether the engine must also be keeping up with real time to count as
+    // healthy:
+    #[serde(default)]
+    realtime: bool,
+}
+
+pub(super) async fn status(server: ServerRef, authenticated: bool, query: StatusQuery) -> Result<impl Reply, ApiError> {
+    let project = server.project();
+
+    let engine_alive = match time::timeout(CHECK_TIMEOUT, project.ping_engine()).await {
+        Ok(Ok(())) => true,
+        Ok(Err(_)) | Err(_) => false,
+    };
+
+    let database_ok = match time::timeout(CHECK_TIMEOUT, project.check_database()).await {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            eprintln!("status: database check failed: {:?}", e);
+            false
+        }
+        Err(_) => false,
+    };
+
+    let perf = project.latest_performance_info();
+
+    // lag stays active for a moment after a tick runs over, see
+    // util::temporal_warning:
+    let keeping_up = perf.as_ref().map_or(false, |perf| {
+        perf.realtime && perf.lag != Some(TemporalWarningStatus::Active)
+    });
+
+    let healthy = engine_alive && database_ok && (keeping_up || !query.realtime);
+
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    if !authenticated {
+        let body = json!({ "healthy": healthy });
+        return Ok(reply::with_status(reply::json(&body), status));
+    }
+
+    let stream_outputs = if engine_alive {
+        match project.connect_engine().await {
+            Ok((state, _, _)) => stream_outputs(&state),
+            Err(_) => Vec::new(),
+        }
+    } else {
+        Vec::new()
+    };
+
+    let body = json!({
+        "healthy": healthy,
+        "project": project.name(),
+        "engine": {
+            "alive": engine_alive,
+            "realtime": perf.as_ref().map(|perf| perf.realtime),
+            "lag": perf.as_ref().and_then(|perf| perf.lag),
+        },
+        "database": {
+            "ok": database_ok,
+        },
+        "ingest": {
+            "icecast": mountpoints(icecast::mountpoints()),
+            "rtmp": mountpoints(rtmp::mountpoints()),
+        },
+        "stream_outputs": stream_outputs,
+    });
+
+    Ok(reply::with_status(reply::json(&body), status))
+}
+
+fn mountpoints(mountpoints: Vec<(String, bool)>) -> Vec<Value> {
+    mountpoints.into_iter()
+        .map(|(mountpoint, connected)| json!({
+            "mountpoint": mountpoint,
+            "connected": connected,
+        }))
+        .collect()
+}
+
+// stream output params carry the stream key, so only report the module id
+// and its live status
+fn stream_outputs(state: &WorkspaceState) -> Vec<Value> {
+    state.modules.iter()
+        .filter(|(_, params)| match params {
+            ModuleParams::StreamOutput(_) => true,
+            _ => false,
+        })
+        .map(|(id, _)| {
+            let indication = state.indications.iter()
+                .find(|(indication_id, _)| indication_id == id)
+                .and_then(|(_, indication)| match indication {
+                    Indication::StreamOutput(indication) => Some(indication),
+                    _ => None,
+                });
+
+            json!({
+                "module": id,
+                "live": indication.map(|indication| indication.live),
+                "error": indication.map(|indication| indication.error).unwrap_or(false),
+            })
+        })
+        .collect()
+}
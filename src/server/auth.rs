@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use structopt::StructOpt;
+use warp::http::StatusCode;
+use warp::reject::{self, Reject};
+use warp::reply::{self, Reply};
+use warp::{Filter, Rejection};
+
+use mixlab_protocol::Role;
+
+#[derive(StructOpt)]
+pub struct AuthOpts {
+    /// Password or token granting full control. If not set, authentication
+    /// is disabled and everyone is an operator
+    #[structopt(long)]
+    operator_password: Option<String>,
+    /// Password or token granting read-only access
+    #[structopt(long)]
+    viewer_password: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Auth {
+    operator: Option<String>,
+    viewer: Option<String>,
+}
+
+pub type AuthRef = Arc<Auth>;
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+#[derive(Debug)]
+struct Forbidden;
+
+impl Reject for Forbidden {}
+
+impl Auth {
+    pub fn new(opts: AuthOpts) -> Self {
+        if opts.operator_password.is_none() && opts.viewer_password.is_some() {
+            eprintln!("WARNING: --viewer-password has no effect without --operator-password");
+        }
+
+        Auth {
+            operator: opts.operator_password,
+            viewer: opts.viewer_password,
+        }
+    }
+
+    fn role(&self, password: Option<&str>) -> Option<Role> {
+        let operator = match &self.operator {
+            Some(operator) => operator,
+            // authentication disabled:
+            None => return Some(Role::Operator),
+        };
+
+        let password = password?;
+
+        if constant_time_eq(password.as_bytes(), operator.as_bytes()) {
+            return Some(Role::Operator);
+        }
+
+        match &self.viewer {
+            Some(viewer) if constant_time_eq(password.as_bytes(), viewer.as_bytes()) => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+}
+
+/// Extracts the role of the requesting user, rejecting the request if they
+/// are not authenticated. Credentials are accepted as either a bearer token
+/// for scripts, or basic auth so browsers can prompt for a password. The
+/// username in basic auth is ignored.
+pub fn authenticate(auth: AuthRef) -> impl Filter<Extract = (Role,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let role = auth.role(header.as_ref().and_then(|header| password(header)).as_deref());
+
+            async move {
+                role.ok_or_else(|| reject::custom(Unauthorized))
+            }
+        })
+}
+
+/// Extracts the role of the requesting user, or None if they are not
+/// authenticated, for routes which serve anyone but tell users more
+pub fn optional_role(auth: AuthRef) -> impl Filter<Extract = (Option<Role>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .map(move |header: Option<String>| {
+            auth.role(header.as_ref().and_then(|header| password(header)).as_deref())
+        })
+}
+
+/// Rejects the request unless the requesting user is authenticated, in any role
+pub fn any_role(auth: AuthRef) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    authenticate(auth)
+        .map(|_: Role| ())
+        .untuple_one()
+}
+
+/// Rejects the request unless the requesting user is an operator
+pub fn operator(auth: AuthRef) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    authenticate(auth)
+        .and_then(|role| async move {
+            match role {
+                Role::Operator => Ok(()),
+                Role::Viewer => Err(reject::custom(Forbidden)),
+            }
+        })
+        .untuple_one()
+}
+
+pub async fn recover(err: Rejection) -> Result<impl Reply, Rejection> {
+    if err.find::<Unauthorized>().is_some() {
+        let reply = reply::with_status("authentication required", StatusCode::UNAUTHORIZED);
+        return Ok(reply::with_header(reply, "www-authenticate", "Basic realm=\"mixlab\"").into_response());
+    }
+
+    if err.find::<Forbidden>().is_some() {
+        return Ok(reply::with_status("operator role required", StatusCode::FORBIDDEN).into_response());
+    }
+
+    Err(err)
+}
+
+fn password(header: &str) -> Option<String> {
+    let mut parts = header.splitn(2, ' ');
+    let scheme = parts.next()?;
+    let credentials = parts.next()?.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Some(credentials.to_owned())
+    } else if scheme.eq_ignore_ascii_case("basic") {
+        let decoded = base64::decode(credentials).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let mut parts = decoded.splitn(2, ':');
+        let _username = parts.next()?;
+        parts.next().map(str::to_owned)
+    } else {
+        None
+    }
+}
+
+// avoids leaking how much of a password matched through response timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
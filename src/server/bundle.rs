@@ -0,0 +1,182 @@
+// Project bundles over HTTP, see project/bundle.rs:
+//
+//   GET  /_projects/export[?all_media=true]   -> tar of the active project
+//   POST /_projects/import?name=N             tar body, becomes the active project
+//
+// Both stream, bundles are never held in memory or spooled to disk whole.
+
+use std::cmp;
+use std::io::{self, BufWriter, Read, Write};
+
+use bytes::{Buf, Bytes};
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::{SinkExt, Stream, StreamExt};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use warp::http::{self, StatusCode};
+use warp::reply::{self, Reply, Response};
+use warp::{Filter, Rejection};
+
+use crate::project::bundle::BundleError;
+use crate::project::manager::ProjectError;
+
+use super::ServerRef;
+use super::auth::{self, AuthRef};
+
+// bytes written to the response per chunk of an export
+const EXPORT_CHUNK_SIZE: usize = 256 * 1024;
+
+pub fn routes(server: ServerRef, auth: AuthRef) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let server = warp::any().map(move || server.clone());
+
+    let export = warp::get()
+        .and(warp::path!("_projects" / "export"))
+        .and(auth::operator(auth.clone()))
+        .and(warp::query::<ExportParams>())
+        .and(server.clone())
+        .and_then(|params, server| export(server, params));
+
+    let import = warp::post()
+        .and(warp::path!("_projects" / "import"))
+        .and(auth::operator(auth.clone()))
+        .and(warp::query::<ImportParams>())
+        .and(warp::filters::body::stream())
+        .and(server.clone())
+        .and_then(|params, body, server| import(server, params, body));
+
+    export.or(import)
+}
+
+#[derive(Deserialize)]
+struct ExportParams {
+    #[serde(default)]
+    all_media: bool,
+}
+
+#[derive(Deserialize)]
+struct ImportParams {
+    name: String,
+}
+
+async fn export(server: ServerRef, params: ExportParams) -> Result<Response, Rejection> {
+    let project = server.project();
+    let filename = format!("{}.mixlab.tar", project.name());
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn({
+        let mut error_tx = tx.clone();
+        let out = BufWriter::with_capacity(EXPORT_CHUNK_SIZE, ChannelWriter(tx));
+
+        async move {
+            if let Err(e) = project.export_bundle(params.all_media, out).await {
+                eprintln!("could not export {:?}: {:?}", project.name(), e);
+
+                // fail the response rather than end it, so the client does
+                // not mistake a truncated bundle for a complete one:
+                let error = io::Error::new(io::ErrorKind::Other, "export failed");
+                let _ = error_tx.send(Err(error)).await;
+            }
+        }
+    });
+
+    let disposition = format!("attachment; filename*=UTF-8''{}",
+        utf8_percent_encode(&filename, NON_ALPHANUMERIC));
+
+    Ok(http::Response::builder()
+        .header("content-type", "application/x-tar")
+        .header("content-disposition", disposition)
+        .body(hyper::Body::wrap_stream(rx))
+        .expect("build response"))
+}
+
+async fn import(server: ServerRef, params: ImportParams, body: impl Stream<Item = Result<impl Buf, warp::Error>>) -> Result<Response, Rejection> {
+    let (mut tx, rx) = mpsc::channel(4);
+
+    let receive = async move {
+        futures::pin_mut!(body);
+
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk
+                .map(|mut chunk| chunk.to_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()));
+
+            // the importer stops reading if the bundle is no good:
+            if tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    let bundle = ChannelReader { rx, chunk: Bytes::new() };
+    let (_, result) = futures::join!(receive, server.projects.import(params.name, bundle));
+
+    let status = match result {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(ProjectError::InvalidName) => StatusCode::BAD_REQUEST,
+        Err(ProjectError::AlreadyExists) => StatusCode::CONFLICT,
+        Err(ProjectError::Bundle(e)) if bad_bundle(&e) => {
+            eprintln!("import rejected, bad bundle: {:?}", e);
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        Err(e) => {
+            eprintln!("import failed: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+
+    Ok(reply::with_status(reply::reply(), status).into_response())
+}
+
+fn bad_bundle(e: &BundleError) -> bool {
+    match e {
+        BundleError::Io(_) |
+        BundleError::Json(_) |
+        BundleError::Media(_) |
+        BundleError::NoManifest |
+        BundleError::NoWorkspace |
+        BundleError::UnsupportedVersion(_) |
+        BundleError::MissingMedia(_) => true,
+        _ => false,
+    }
+}
+
+// export writes synchronously from a blocking task, this hands what it writes
+// to the response body:
+struct ChannelWriter(mpsc::Sender<Result<Bytes, io::Error>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        block_on(self.0.send(Ok(Bytes::copy_from_slice(buf))))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// and import reads synchronously from a blocking task, this feeds it the
+// request body:
+struct ChannelReader {
+    rx: mpsc::Receiver<Result<Bytes, io::Error>>,
+    chunk: Bytes,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match block_on(self.rx.next()) {
+                Some(chunk) => self.chunk = chunk?,
+                None => return Ok(0),
+            }
+        }
+
+        let len = cmp::min(out.len(), self.chunk.len());
+        out[..len].copy_from_slice(&self.chunk[..len]);
+        self.chunk.advance(len);
+        Ok(len)
+    }
+}
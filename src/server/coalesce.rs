@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::stream::Stream;
+use tokio::sync::broadcast::RecvError;
+
+use mixlab_protocol::ServerUpdate;
+
+use crate::engine::{EngineEvent, EngineEvents};
+
+// Modules may send indications as often as every tick, which is more than a
+// slow client can keep up with. Coalesce buffers whatever engine events have
+// queued up while the session was busy sending, dropping any indication that
+// is superseded by a newer one for the same module. Fast clients never build
+// up a backlog and so see every indication, slow ones see the latest.
+pub struct Coalesce {
+    events: EngineEvents,
+    buffer: VecDeque<EngineEvent>,
+    closed: bool,
+}
+
+// a session with this many events buffered is falling behind even with
+// indications coalesced, so is treated as having lagged:
+const MAX_BUFFERED: usize = 64;
+
+impl Coalesce {
+    pub fn new(events: EngineEvents) -> Self {
+        Coalesce {
+            events,
+            buffer: VecDeque::new(),
+            closed: false,
+        }
+    }
+
+    fn push(&mut self, event: EngineEvent) {
+        if let EngineEvent::ServerUpdate(ServerUpdate::UpdateModuleIndication(module_id, _)) = &event {
+            let module_id = *module_id;
+
+            self.buffer.retain(|buffered| match buffered {
+                EngineEvent::ServerUpdate(ServerUpdate::UpdateModuleIndication(id, _)) => *id != module_id,
+                _ => true,
+            });
+        }
+
+        self.buffer.push_back(event);
+    }
+}
+
+impl Stream for Coalesce {
+    type Item = Result<EngineEvent, RecvError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        while !this.closed {
+            match Pin::new(&mut this.events).poll_next(cx) {
+                Poll::Ready(Some(Ok(event))) => {
+                    this.push(event);
+
+                    if this.buffer.len() > MAX_BUFFERED {
+                        let skipped = this.buffer.len() as u64;
+                        this.buffer.clear();
+                        return Poll::Ready(Some(Err(RecvError::Lagged(skipped))));
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    // anything buffered is stale now, the session resyncs
+                    // after lagging:
+                    this.buffer.clear();
+                    return Poll::Ready(Some(Err(e)));
+                }
+                Poll::Ready(None) => {
+                    this.closed = true;
+                }
+                Poll::Pending => {
+                    break;
+                }
+            }
+        }
+
+        match this.buffer.pop_front() {
+            Some(event) => Poll::Ready(Some(Ok(event))),
+            None if this.closed => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,456 @@
+// Media library items and their previews, loaded by the browser over plain
+// HTTP rather than the session websocket, and the resumable upload protocol:
+//
+//   POST   /_uploads                  {name, kind} -> {id, offset}
+//   GET    /_uploads/<id>             -> {id, offset}
+//   PUT    /_uploads/<id>?offset=N    chunk body -> {offset}
+//   POST   /_uploads/<id>/finalize    -> {media_id}
+//   DELETE /_uploads/<id>
+//
+// A chunk not starting at the committed offset is refused with 409 and the
+// committed offset, so the client can resume from there.
+
+use std::cmp;
+use std::io::{self, SeekFrom};
+
+use bytes::{Buf, Bytes};
+use futures::{Stream, StreamExt};
+use mixlab_codec::ffmpeg::IoReader;
+use mixlab_protocol::MediaId;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::task;
+use uuid::Uuid;
+use warp::http::{self, HeaderValue, StatusCode};
+use warp::reply::{self, Reply, Response};
+use warp::{Filter, Rejection};
+
+use crate::project::media::{self, preview, UploadError, UploadInfo};
+use crate::project::media::upload::UploadId;
+use crate::project::stream::ReadStream;
+
+// bytes read from the database per chunk of a download response
+const DOWNLOAD_CHUNK_SIZE: u64 = 256 * 1024;
+
+use super::ServerRef;
+use super::auth::{self, AuthRef};
+
+pub fn routes(server: ServerRef, auth: AuthRef) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let server = warp::any().map(move || server.clone());
+
+    let download = warp::get()
+        .and(warp::path!("_media" / i64))
+        .and(auth::any_role(auth.clone()))
+        .and(warp::header::optional::<String>("range"))
+        .and(server.clone())
+        .and_then(|media_id, range: Option<String>, server| download(server, MediaId(media_id), range));
+
+    let thumbnail = warp::get()
+        .and(warp::path!("_media" / i64 / "thumbnail"))
+        .and(auth::any_role(auth.clone()))
+        .and(server.clone())
+        .and_then(|media_id, server| thumbnail(server, MediaId(media_id)));
+
+    let waveform = warp::get()
+        .and(warp::path!("_media" / i64 / "waveform"))
+        .and(auth::any_role(auth.clone()))
+        .and(server.clone())
+        .and_then(|media_id, server| waveform(server, MediaId(media_id)));
+
+    let waveform_svg = warp::get()
+        .and(warp::path!("_media" / i64 / "waveform.svg"))
+        .and(auth::any_role(auth.clone()))
+        .and(server.clone())
+        .and_then(|media_id, server| waveform_svg(server, MediaId(media_id)));
+
+    let create_upload = warp::post()
+        .and(warp::path!("_uploads"))
+        .and(auth::operator(auth.clone()))
+        .and(warp::body::json())
+        .and(server.clone())
+        .and_then(|params: CreateUploadParams, server| create_upload(server, params));
+
+    let upload_offset = warp::get()
+        .and(warp::path!("_uploads" / Uuid))
+        .and(auth::operator(auth.clone()))
+        .and(server.clone())
+        .and_then(|upload_id, server| upload_offset(server, UploadId(upload_id)));
+
+    let upload_chunk = warp::put()
+        .and(warp::path!("_uploads" / Uuid))
+        .and(auth::operator(auth.clone()))
+        .and(warp::query::<ChunkParams>())
+        .and(warp::filters::body::stream())
+        .and(server.clone())
+        .and_then(|upload_id, params: ChunkParams, body, server| {
+            upload_chunk(server, UploadId(upload_id), params.offset, body)
+        });
+
+    let finalize_upload = warp::post()
+        .and(warp::path!("_uploads" / Uuid / "finalize"))
+        .and(auth::operator(auth.clone()))
+        .and(server.clone())
+        .and_then(|upload_id, server| finalize_upload(server, UploadId(upload_id)));
+
+    let abort_upload = warp::delete()
+        .and(warp::path!("_uploads" / Uuid))
+        .and(auth::operator(auth.clone()))
+        .and(server.clone())
+        .and_then(|upload_id, server| abort_upload(server, UploadId(upload_id)));
+
+    download
+        .or(thumbnail)
+        .or(waveform)
+        .or(waveform_svg)
+        .or(create_upload)
+        .or(upload_offset)
+        .or(upload_chunk)
+        .or(finalize_upload)
+        .or(abort_upload)
+}
+
+async fn download(server: ServerRef, media_id: MediaId, range: Option<String>) -> Result<Response, Rejection> {
+    let media = match server.project().open_media_download(media_id).await {
+        Ok(Some(media)) => media,
+        Ok(None) => return Err(warp::reject::not_found()),
+        Err(e) => {
+            eprintln!("could not open {:?} for download: {:?}", media_id, e);
+            return Ok(reply::with_status(reply::reply(), StatusCode::INTERNAL_SERVER_ERROR).into_response());
+        }
+    };
+
+    let mut stream = media.stream;
+
+    let size = match IoReader::size(&mut stream) {
+        Ok(size) => size,
+        Err(e) => {
+            eprintln!("could not read size of {:?}: {:?}", media_id, e);
+            return Ok(reply::with_status(reply::reply(), StatusCode::INTERNAL_SERVER_ERROR).into_response());
+        }
+    };
+
+    let disposition = format!("inline; filename*=UTF-8''{}",
+        utf8_percent_encode(&media.name, NON_ALPHANUMERIC));
+
+    // media stored before kinds were checked may not have a valid one:
+    let content_type = HeaderValue::from_str(&media.kind)
+        .unwrap_or_else(|_| HeaderValue::from_static(media::DEFAULT_KIND));
+
+    let response = http::Response::builder()
+        .header("accept-ranges", "bytes")
+        .header("content-type", content_type)
+        .header("content-disposition", disposition);
+
+    let (response, start, len) = match parse_range(range.as_deref(), size) {
+        ByteRange::Full => {
+            (response.status(StatusCode::OK), 0, size)
+        }
+        ByteRange::Partial(start, end) => {
+            let response = response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header("content-range", format!("bytes {}-{}/{}", start, end, size));
+
+            (response, start, end - start + 1)
+        }
+        ByteRange::Unsatisfiable => {
+            let response = http::Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("content-range", format!("bytes */{}", size))
+                .body(hyper::Body::empty())
+                .expect("build response");
+
+            return Ok(response);
+        }
+    };
+
+    if let Err(e) = IoReader::seek(&mut stream, SeekFrom::Start(start)) {
+        eprintln!("could not seek {:?}: {:?}", media_id, e);
+        return Ok(reply::with_status(reply::reply(), StatusCode::INTERNAL_SERVER_ERROR).into_response());
+    }
+
+    let body = hyper::Body::wrap_stream(read_chunks(stream, len));
+
+    Ok(response
+        .header("content-length", len)
+        .body(body)
+        .expect("build response"))
+}
+
+/// Reads `len` bytes from the stream's current position. ReadStream reads
+/// synchronously, so each chunk is read on a blocking task
+fn read_chunks(stream: ReadStream, len: u64) -> impl Stream<Item = Result<Bytes, io::Error>> {
+    futures::stream::try_unfold((stream, len), |(mut stream, remaining)| async move {
+        if remaining == 0 {
+            return Ok(None);
+        }
+
+        let (stream, result) = task::spawn_blocking(move || {
+            let mut buff = vec![0u8; cmp::min(remaining, DOWNLOAD_CHUNK_SIZE) as usize];
+
+            let result = IoReader::read(&mut stream, &mut buff).map(|len| {
+                buff.truncate(len);
+                buff
+            });
+
+            (stream, result)
+        }).await.expect("join blocking task");
+
+        let chunk = match result {
+            Ok(chunk) => chunk,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::Other, format!("{:?}", e))),
+        };
+
+        if chunk.is_empty() {
+            // stream is shorter than its recorded size. nothing more we can
+            // do but end the response early
+            eprintln!("stream ended {} bytes early during download", remaining);
+            return Ok(None);
+        }
+
+        let remaining = remaining - chunk.len() as u64;
+        Ok(Some((Bytes::from(chunk), (stream, remaining))))
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    // inclusive of both ends, as in the range header:
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parses a range header of a single byte range. Anything we don't
+/// understand, including multiple ranges, is ignored and the full content
+/// served, which the spec permits
+fn parse_range(header: Option<&str>, size: u64) -> ByteRange {
+    const PREFIX: &str = "bytes=";
+
+    let header = match header {
+        Some(header) if header.starts_with(PREFIX) => header[PREFIX.len()..].trim(),
+        _ => return ByteRange::Full,
+    };
+
+    if header.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let mut parts = header.splitn(2, '-');
+    let first = parts.next().unwrap_or("").trim();
+    let last = match parts.next() {
+        Some(last) => last.trim(),
+        None => return ByteRange::Full,
+    };
+
+    if first.is_empty() {
+        // suffix range, the final N bytes:
+        return match last.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if size == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(size.saturating_sub(suffix), size - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let start = match first.parse::<u64>() {
+        Ok(start) => start,
+        Err(_) => return ByteRange::Full,
+    };
+
+    let end = if last.is_empty() {
+        None
+    } else {
+        match last.parse::<u64>() {
+            Ok(end) if end >= start => Some(end),
+            _ => return ByteRange::Full,
+        }
+    };
+
+    if start >= size {
+        return ByteRange::Unsatisfiable;
+    }
+
+    let end = end.map(|end| cmp::min(end, size - 1)).unwrap_or(size - 1);
+    ByteRange::Partial(start, end)
+}
+
+async fn thumbnail(server: ServerRef, media_id: MediaId) -> Result<impl Reply, Rejection> {
+    match server.project().media_thumbnail(media_id).await {
+        Ok(Some(png)) => Ok(reply::with_header(png, "content-type", "image/png")),
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(e) => {
+            eprintln!("could not load thumbnail for {:?}: {:?}", media_id, e);
+            Err(warp::reject::not_found())
+        }
+    }
+}
+
+async fn load_waveform(server: &ServerRef, media_id: MediaId) -> Result<Vec<preview::Peak>, Rejection> {
+    match server.project().media_waveform(media_id).await {
+        Ok(Some(peaks)) => Ok(peaks),
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(e) => {
+            eprintln!("could not load waveform for {:?}: {:?}", media_id, e);
+            Err(warp::reject::not_found())
+        }
+    }
+}
+
+/// Peaks as an array of [min, max] pairs, each within -1.0 to 1.0
+async fn waveform(server: ServerRef, media_id: MediaId) -> Result<impl Reply, Rejection> {
+    let peaks = load_waveform(&server, media_id).await?;
+
+    let pairs = peaks.iter()
+        .map(|peak| (peak.min, peak.max))
+        .collect::<Vec<_>>();
+
+    Ok(reply::json(&pairs))
+}
+
+async fn waveform_svg(server: ServerRef, media_id: MediaId) -> Result<impl Reply, Rejection> {
+    let peaks = load_waveform(&server, media_id).await?;
+    let svg = preview::waveform_svg(&peaks);
+    Ok(reply::with_header(svg, "content-type", "image/svg+xml"))
+}
+
+#[derive(Deserialize)]
+struct CreateUploadParams {
+    name: String,
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct ChunkParams {
+    offset: u64,
+}
+
+fn upload_error(upload_id: UploadId, e: UploadError) -> Response {
+    match e {
+        UploadError::NoSuchUpload => {
+            reply::with_status(reply::reply(), StatusCode::NOT_FOUND).into_response()
+        }
+        UploadError::InvalidKind => {
+            reply::with_status(reply::reply(), StatusCode::BAD_REQUEST).into_response()
+        }
+        UploadError::OffsetMismatch(offset) => {
+            let body = reply::json(&json!({ "id": upload_id.0.to_string(), "offset": offset }));
+            reply::with_status(body, StatusCode::CONFLICT).into_response()
+        }
+        UploadError::Busy => {
+            reply::with_status(reply::reply(), StatusCode::CONFLICT).into_response()
+        }
+        UploadError::Unreadable(e) => {
+            eprintln!("upload rejected, could not read media: {:?}", e);
+            reply::with_status(reply::reply(), StatusCode::UNSUPPORTED_MEDIA_TYPE).into_response()
+        }
+        UploadError::Database(e) => {
+            eprintln!("upload {:?} failed: {:?}", upload_id, e);
+            reply::with_status(reply::reply(), StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+        UploadError::Stream(e) => {
+            eprintln!("upload {:?} failed: {:?}", upload_id, e);
+            reply::with_status(reply::reply(), StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    }
+}
+
+async fn create_upload(server: ServerRef, params: CreateUploadParams) -> Result<Response, Rejection> {
+    let info = UploadInfo {
+        name: params.name,
+        kind: params.kind,
+    };
+
+    match server.project().create_upload(info).await {
+        Ok(upload_id) => Ok(reply::json(&json!({ "id": upload_id.0.to_string(), "offset": 0 })).into_response()),
+        Err(UploadError::InvalidKind) => {
+            Ok(reply::with_status(reply::reply(), StatusCode::BAD_REQUEST).into_response())
+        }
+        Err(e) => {
+            eprintln!("could not create upload: {:?}", e);
+            Ok(reply::with_status(reply::reply(), StatusCode::INTERNAL_SERVER_ERROR).into_response())
+        }
+    }
+}
+
+async fn upload_offset(server: ServerRef, upload_id: UploadId) -> Result<Response, Rejection> {
+    match server.project().upload_offset(upload_id).await {
+        Ok(Some(offset)) => Ok(reply::json(&json!({ "id": upload_id.0.to_string(), "offset": offset })).into_response()),
+        Ok(None) => Ok(upload_error(upload_id, UploadError::NoSuchUpload)),
+        Err(e) => Ok(upload_error(upload_id, e)),
+    }
+}
+
+async fn upload_chunk(
+    server: ServerRef,
+    upload_id: UploadId,
+    offset: u64,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>>,
+) -> Result<Response, Rejection> {
+    futures::pin_mut!(body);
+
+    let mut chunk = match server.project().resume_upload(upload_id, offset).await {
+        Ok(chunk) => chunk,
+        Err(e) => return Ok(upload_error(upload_id, e)),
+    };
+
+    while let Some(buf) = body.next().await {
+        let buf = match buf {
+            Ok(buf) => buf,
+            Err(e) => {
+                // commit what we have, the client will ask for the offset
+                // when it reconnects:
+                eprintln!("upload {:?} interrupted: {:?}", upload_id, e);
+                break;
+            }
+        };
+
+        if let Err(e) = chunk.receive_bytes(buf.bytes()).await {
+            return Ok(upload_error(upload_id, e));
+        }
+    }
+
+    match chunk.commit().await {
+        Ok(offset) => Ok(reply::json(&json!({ "id": upload_id.0.to_string(), "offset": offset })).into_response()),
+        Err(e) => Ok(upload_error(upload_id, e)),
+    }
+}
+
+async fn finalize_upload(server: ServerRef, upload_id: UploadId) -> Result<Response, Rejection> {
+    match server.project().finalize_upload(upload_id).await {
+        Ok(media_id) => Ok(reply::json(&json!({ "media_id": media_id.0 })).into_response()),
+        Err(e) => Ok(upload_error(upload_id, e)),
+    }
+}
+
+async fn abort_upload(server: ServerRef, upload_id: UploadId) -> Result<Response, Rejection> {
+    match server.project().abort_upload(upload_id).await {
+        Ok(()) => Ok(reply::with_status(reply::reply(), StatusCode::NO_CONTENT).into_response()),
+        Err(e) => Ok(upload_error(upload_id, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_byte_ranges() {
+        assert_eq!(parse_range(None, 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-"), 100), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range(Some("bytes=10-19"), 100), ByteRange::Partial(10, 19));
+        assert_eq!(parse_range(Some("bytes=90-200"), 100), ByteRange::Partial(90, 99));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), ByteRange::Partial(90, 99));
+        assert_eq!(parse_range(Some("bytes=-200"), 100), ByteRange::Partial(0, 99));
+    }
+
+    #[test]
+    fn parse_unusual_byte_ranges() {
+        assert_eq!(parse_range(Some("bytes=100-"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=20-10"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-1"), 100), ByteRange::Full);
+    }
+}
@@ -0,0 +1,66 @@
+// Lists the modules of another project, for choosing which to import into the
+// open one with ProjectOp::ImportModules:
+//
+//   GET /_projects/modules?project=N   -> [{id, kind, sources}]
+
+use serde::{Deserialize, Serialize};
+use warp::http::StatusCode;
+use warp::reply::{self, Reply, Response};
+use warp::{Filter, Rejection};
+
+use mixlab_protocol::ModuleId;
+
+use crate::project::manager::ProjectError;
+
+use super::ServerRef;
+use super::auth::{self, AuthRef};
+
+pub fn routes(server: ServerRef, auth: AuthRef) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let server = warp::any().map(move || server.clone());
+
+    warp::get()
+        .and(warp::path!("_projects" / "modules"))
+        .and(auth::operator(auth.clone()))
+        .and(warp::query::<ModulesParams>())
+        .and(server.clone())
+        .and_then(|params: ModulesParams, server| modules(server, params.project))
+}
+
+#[derive(Deserialize)]
+struct ModulesParams {
+    project: String,
+}
+
+#[derive(Serialize)]
+struct Module {
+    id: ModuleId,
+    // name of the module type, eg. "Mixer":
+    kind: String,
+    sources: Vec<ModuleId>,
+}
+
+async fn modules(server: ServerRef, project: String) -> Result<Response, Rejection> {
+    let source = match server.projects.open_source(&project).await {
+        Ok(source) => source,
+        Err(ProjectError::InvalidName) | Err(ProjectError::NoSuchProject) => {
+            return Err(warp::reject::not_found());
+        }
+        Err(ProjectError::Active) => {
+            return Ok(reply::with_status(reply::reply(), StatusCode::CONFLICT).into_response());
+        }
+        Err(e) => {
+            eprintln!("could not open {:?} to list modules: {:?}", project, e);
+            return Ok(reply::with_status(reply::reply(), StatusCode::INTERNAL_SERVER_ERROR).into_response());
+        }
+    };
+
+    let modules = source.modules().into_iter()
+        .map(|module| Module {
+            id: module.id,
+            kind: module.params.kind().to_owned(),
+            sources: module.sources,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(reply::json(&modules).into_response())
+}
@@ -123,6 +123,20 @@ impl Registry {
         Ok(recv)
     }
 
+    /// Names of all mountpoints listening for a source, along with whether a
+    /// source is currently connected to each
+    pub fn mountpoints(&self) -> Vec<(String, bool)> {
+        let registry = self.inner.lock()
+            .expect("registry lock");
+
+        let mut mountpoints = registry.channels.iter()
+            .map(|(name, source)| (name.clone(), source.tx.is_none()))
+            .collect::<Vec<_>>();
+
+        mountpoints.sort();
+        mountpoints
+    }
+
     pub fn connect(&self, channel_name: &str) -> Result<SourceSend, ConnectError> {
         let mut registry = self.inner.lock()
             .expect("registry lock");